@@ -0,0 +1,137 @@
+use std::env;
+use std::fs;
+use std::path::Path;
+use sgx_types::*;
+use sgx_ucrypto::*;
+use pct_core::constant::{
+    AUTHORITY_ID, CHUNK_KEY_SIZE, CHUNK_AAD, CHUNK_KEY_MESSAGE_U8, CHUNK_KEY_REPLACE,
+    DIRECTION_CHUNK_KEY, NONCE_SIZE_U8, TAG_SIZE_U8, SEALED_OVERHEAD_U8
+};
+use ecalls::provision_chunk_key;
+use secure_channel::{ QuoteVerifier, establish_secure_channel, seal };
+use util::fill_random;
+
+/*
+    Health authority side of the central data.
+    It seals every chunk with its own chunk key (bin/app seal-chunks), so the
+    service operator only stores and forwards ciphertext, and releases that key
+    to the enclave over its own secure channel after verifying the enclave report.
+    The enclave only finishes that channel if the authority signs the handshake with
+    the signing key whose public half the enclave pins when it is built.
+    In this prototype the host plays the authority as well, reading the keys from
+    the files named by PCT_AUTHORITY_KEY and PCT_AUTHORITY_SIGNING_KEY.
+*/
+pub type ChunkKey = [u8; CHUNK_KEY_SIZE];
+// ECDSA P-256, little endian like the rest of the sgx crypto
+pub type SigningKey = sgx_ec256_private_t;
+
+// the existing key, or a fresh random one written to key_filename
+pub fn read_or_create_chunk_key(key_filename: &str) -> ChunkKey {
+    let mut key: ChunkKey = [0; CHUNK_KEY_SIZE];
+    if Path::new(key_filename).exists() {
+        let bytes = fs::read(key_filename).unwrap();
+        assert_eq!(bytes.len(), CHUNK_KEY_SIZE, "{} is not a chunk key!", key_filename);
+        key.copy_from_slice(&bytes);
+    } else {
+        fill_random(&mut key);
+        fs::write(key_filename, &key[..]).unwrap();
+    }
+    key
+}
+
+pub fn chunk_key_from_env() -> Option<ChunkKey> {
+    let key_filename = env::var("PCT_AUTHORITY_KEY").ok()?;
+    let bytes = fs::read(&key_filename).ok()?;
+    if bytes.len() != CHUNK_KEY_SIZE {
+        return None;
+    }
+    let mut key: ChunkKey = [0; CHUNK_KEY_SIZE];
+    key.copy_from_slice(&bytes);
+    Some(key)
+}
+
+pub fn signing_key_from_env() -> Option<SigningKey> {
+    let key_filename = env::var("PCT_AUTHORITY_SIGNING_KEY").ok()?;
+    let bytes = fs::read(&key_filename).ok()?;
+    if bytes.len() != SGX_ECP256_KEY_SIZE {
+        return None;
+    }
+    let mut signing_key = SigningKey::default();
+    signing_key.r.copy_from_slice(&bytes);
+    Some(signing_key)
+}
+
+/*
+    a fresh signing key written to key_filename, and its public half (gx || gy) written to
+    public_key_filename, which the enclave pins when it is built (enclave/src/authority_key.rs)
+*/
+pub fn create_signing_key(key_filename: &str, public_key_filename: &str) -> Result<(), String> {
+    if Path::new(key_filename).exists() {
+        return Err(format!("{} already exists", key_filename));
+    }
+    let ecc_handle = SgxEccHandle::new();
+    ecc_handle.open().map_err(|e| e.as_str().to_string())?;
+    let key_pair = ecc_handle.create_key_pair();
+    ecc_handle.close().map_err(|e| e.as_str().to_string())?;
+    let (mut signing_key, public_key) = key_pair.map_err(|e| e.as_str().to_string())?;
+    let written = fs::write(key_filename, &signing_key.r[..]);
+    signing_key.r = [0; SGX_ECP256_KEY_SIZE];
+    written.map_err(|e| format!("{}: {}", key_filename, e))?;
+    fs::write(public_key_filename, [&public_key.gx[..], &public_key.gy[..]].concat())
+        .map_err(|e| format!("{}: {}", public_key_filename, e))
+}
+
+// nonce || AES-GCM(backend id || codec || dictionary) || tag, opened by enclave/src/chunk_key.rs
+pub fn seal_chunk(chunk_key: &ChunkKey, chunk: &[u8]) -> SgxResult<Vec<u8>> {
+    let mut nonce = [0_u8; NONCE_SIZE_U8];
+    fill_random(&mut nonce);
+    let mut sealed: Vec<u8> = vec![0; SEALED_OVERHEAD_U8 + chunk.len()];
+    let mut tag: sgx_aes_gcm_128bit_tag_t = [0; TAG_SIZE_U8];
+    rsgx_rijndael128GCM_encrypt(
+        chunk_key,
+        chunk,
+        &nonce,
+        CHUNK_AAD,
+        &mut sealed[NONCE_SIZE_U8..NONCE_SIZE_U8 + chunk.len()],
+        &mut tag
+    )?;
+    sealed[..NONCE_SIZE_U8].copy_from_slice(&nonce);
+    sealed[NONCE_SIZE_U8 + chunk.len()..].copy_from_slice(&tag);
+    Ok(sealed)
+}
+
+/*
+    attest the enclave as AUTHORITY_ID with the signed handshake, then hand over the
+    chunk key sealed under that session. The enclave refuses to replace a key it
+    already has unless replace is set
+*/
+pub fn provision_chunk_key_to_enclave(
+    eid: sgx_enclave_id_t,
+    chunk_key: &ChunkKey,
+    signing_key: &SigningKey,
+    replace: bool,
+    verifier: &dyn QuoteVerifier,
+) -> SgxResult<()> {
+    let session_key = establish_secure_channel(eid, AUTHORITY_ID, &verifier.target_info(), verifier, Some(signing_key))?;
+    // flags || key, see pct_core/src/constant.rs
+    let mut message: Vec<u8> = Vec::with_capacity(CHUNK_KEY_MESSAGE_U8);
+    message.push(if replace { CHUNK_KEY_REPLACE } else { 0 });
+    message.extend_from_slice(chunk_key);
+    let sealed_key = seal(&session_key, AUTHORITY_ID, DIRECTION_CHUNK_KEY, &message);
+    for byte in message.iter_mut() {
+        *byte = 0;
+    }
+    let sealed_key = sealed_key?;
+    let mut retval = sgx_status_t::SGX_SUCCESS;
+    let result = unsafe {
+        provision_chunk_key(
+            eid,
+            &mut retval,
+            sealed_key.as_ptr() as * const u8,
+            sealed_key.len()
+        )
+    };
+    if result != sgx_status_t::SGX_SUCCESS { return Err(result); }
+    if retval != sgx_status_t::SGX_SUCCESS { return Err(retval); }
+    Ok(())
+}
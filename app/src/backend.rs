@@ -0,0 +1,495 @@
+use std::env;
+use std::cmp;
+use std::thread;
+use std::sync::{ Arc, mpsc };
+use std::sync::atomic::{ AtomicBool, Ordering };
+use sgx_types::*;
+use sgx_urts::SgxEnclave;
+use pct_core::contact_tracer::ContactTracer;
+use pct_core::encoded_dictionary_buffer::LookupMode;
+use pct_core::constant::{ QUERY_ID_SIZE_U8, RESPONSE_DATA_SIZE_U8, RESPONSE_FORMAT_VERSION, DIRECTION_RESPONSE };
+use pct_core::primitive::QueryId;
+use pct_core::query_result::{ matched_points_u8, plain_response_size };
+use pct_core::wire::{ Capabilities, CAPABILITIES_U8, WIRE_VERSION, FRAME_HEADER_U8, FRAME_RESPONSE, read_frame };
+use ecalls::{
+    init_enclave, get_capabilities, open_session, close_session, set_contact_rules, set_lookup_mode,
+    upload_encoded_query_data, private_encode_contact_trace, get_encoded_result
+};
+use query_data::{ EncodedQueryData, EncodedQueryDataDetail };
+use chunk_pipeline::{ ChunkPipeline, ChunkFrame };
+use query_result::RiskScore;
+use contact_rules::ContactRules;
+use encoding::Encoding;
+use secure_channel::{ QuoteVerifier, SessionKeys, establish_secure_channels, quote_verifier_from_env, open };
+use authority::{ ChunkKey, SigningKey, provision_chunk_key_to_enclave };
+use util::query_id_from_u8;
+
+// sealed response of a client with query_size points
+fn response_data_size(query_size: usize) -> usize {
+    RESPONSE_DATA_SIZE_U8 + matched_points_u8(query_size)
+}
+
+/*
+Trait TracingBackend
+    Where the tracing logic (pct_core) runs. SgxBackend goes through the ECALLs
+    and the secure channel of every client, NativeBackend calls pct_core in process
+    without enclave or attestation and is meant for development and CI only.
+*/
+pub trait TracingBackend {
+    // what the tracing logic reads, see check_capabilities
+    fn capabilities(&mut self) -> SgxResult<Capabilities>;
+
+    fn set_contact_rules(&mut self, rules: &ContactRules) -> SgxResult<()>;
+
+    // see lookup_mode_from_env, SGX_ERROR_FEATURE_NOT_SUPPORTED for a mode the backend does not offer
+    fn set_lookup_mode(&mut self, mode: LookupMode) -> SgxResult<()>;
+
+    // chunk key of the health authority, for chunks sealed by bin/app seal-chunks,
+    // released over the handshake signed with the authority's signing_key
+    fn provision_chunk_key(&mut self, chunk_key: &ChunkKey, signing_key: &SigningKey) -> SgxResult<()>;
+
+    // whether chunks the health authority has not sealed can be traced at all
+    fn takes_plaintext_chunks(&self) -> bool;
+
+    // secure channel of every client, nothing to do without an enclave
+    fn establish_channels(&mut self, query_id_list: &Vec<QueryId>) -> SgxResult<()>;
+
+    fn upload_queries(&mut self, query_data: &EncodedQueryData, encoding: Encoding) -> SgxResult<()>;
+
+    // every chunk of the pipeline, on up to threads threads at once (see threads_from_env);
+    // the index of the first failing chunk seen and its status otherwise
+    fn trace_chunks(&mut self, chunks: Arc<ChunkPipeline>, threads: usize) -> Result<(), (usize, sgx_status_t)>;
+
+    // result of every client in upload order, matched points mapped back to its values,
+    // which are decoded as encoding, the one the queries were uploaded with
+    fn get_result(&mut self, query_data: &EncodedQueryData, encoding: Encoding) -> SgxResult<Vec<RiskScore>>;
+
+    // releases the batch, the enclave zeroizes its decrypted queries and results
+    fn destroy(self: Box<Self>);
+}
+
+/*
+    PCT_THREADS threads trace chunks at once, 1 by default. The enclave takes at
+    most its TCSNum (Capabilities::max_threads) of them, and every chunk in flight
+    takes its own share of the chunk budget, see ChunkBudget::shared_by
+*/
+pub fn threads_from_env() -> Option<usize> {
+    match env::var("PCT_THREADS") {
+        Ok(threads) => match threads.parse::<usize>() {
+            Ok(threads) if threads > 0 => Some(threads),
+            _ => None,
+        },
+        Err(_) => Some(1),
+    }
+}
+
+// threads the enclave can run at once, requested is cut down to its TCSNum
+pub fn usable_threads(capabilities: &Capabilities, requested: usize) -> usize {
+    if capabilities.max_threads == 0 {
+        requested
+    } else {
+        cmp::min(requested, capabilities.max_threads as usize)
+    }
+}
+
+/*
+    PCT_BACKEND selects the backend, "sgx" (default) or "native"
+*/
+pub fn backend_from_env() -> Result<Box<dyn TracingBackend>, String> {
+    let name = env::var("PCT_BACKEND").unwrap_or_else(|_| "sgx".to_string());
+    match name.as_ref() {
+        "sgx" => {
+            let verifier = match quote_verifier_from_env() {
+                Some(verifier) => verifier,
+                None => { return Err("Unknown quote verifier, check PCT_QUOTE_VERIFIER".to_string()); }
+            };
+            let enclave = match init_enclave() {
+                Ok(enclave) => enclave,
+                Err(x) => { return Err(format!("Init Enclave Failed {}!", x.as_str())); }
+            };
+            match SgxBackend::open(enclave, verifier) {
+                Ok(backend) => Ok(Box::new(backend)),
+                Err(x) => Err(format!("Open Session Failed {}!", x.as_str())),
+            }
+        },
+        "native" => Ok(Box::new(NativeBackend::new())),
+        _ => Err("Unknown backend, check PCT_BACKEND".to_string()),
+    }
+}
+
+pub const INDEXED_IS_NATIVE_ONLY: &'static str =
+    "PCT_LOOKUP=indexed reveals which points matched and only runs on the native backend (PCT_BACKEND=native)";
+
+// the enclave has to speak the wire format and response format of the app and read
+// the encoding, lookup mode, dictionary backends and chunk codec of this run
+pub fn check_capabilities(
+    capabilities: &Capabilities,
+    encoding: Encoding,
+    lookup_mode: LookupMode,
+    dictionaries: &[u8],
+    codec: u8,
+) -> Result<(), String> {
+    if capabilities.wire_version != WIRE_VERSION {
+        return Err(format!("wire format version {} of the enclave, the app speaks {}", capabilities.wire_version, WIRE_VERSION));
+    }
+    if capabilities.response_format_version != RESPONSE_FORMAT_VERSION {
+        return Err(format!("response format version {} of the enclave, the app reads {}", capabilities.response_format_version, RESPONSE_FORMAT_VERSION));
+    }
+    if !Capabilities::supports(capabilities.encodings, encoding.id()) {
+        return Err(format!("the enclave does not read {}", encoding.name()));
+    }
+    if lookup_mode == LookupMode::Indexed && !Capabilities::supports(capabilities.lookup_modes, lookup_mode.id()) {
+        return Err(INDEXED_IS_NATIVE_ONLY.to_string());
+    }
+    if !Capabilities::supports(capabilities.lookup_modes, lookup_mode.id()) {
+        return Err(format!("the enclave does not support lookup mode {:?}", lookup_mode));
+    }
+    for &id in dictionaries.iter() {
+        if !Capabilities::supports(capabilities.dictionaries, id) {
+            return Err(format!("the enclave does not read dictionary backend {}", id));
+        }
+    }
+    if !Capabilities::supports(capabilities.codecs, codec) {
+        return Err(format!("the enclave does not decode chunk codec {}", codec));
+    }
+    Ok(())
+}
+
+/*
+    runs trace on every chunk of the pipeline, each of threads workers taking the next
+    chunk it hands out, and hands what it returns to merge on the calling thread as it
+    arrives, so merge needs no lock. After a failure the workers stop taking chunks.
+*/
+fn trace_on_threads<T, F, M>(chunks: Arc<ChunkPipeline>, threads: usize, trace: F, mut merge: M) -> Result<(), (usize, sgx_status_t)>
+    where T: Send + 'static,
+          F: Fn(ChunkFrame) -> SgxResult<T> + Send + Sync + 'static,
+          M: FnMut(T) -> SgxResult<()>
+{
+    let trace = Arc::new(trace);
+    let failed = Arc::new(AtomicBool::new(false));
+    let (sender, receiver) = mpsc::sync_channel::<(usize, SgxResult<T>)>(threads);
+    let workers: Vec<thread::JoinHandle<()>> = (0..threads).map(|_| {
+        let (chunks, trace, failed, sender) = (chunks.clone(), trace.clone(), failed.clone(), sender.clone());
+        thread::spawn(move || {
+            while !failed.load(Ordering::SeqCst) {
+                let (index, chunk) = match chunks.next() {
+                    Some(next) => next,
+                    None => break,
+                };
+                let result = trace(chunk);
+                if result.is_err() {
+                    failed.store(true, Ordering::SeqCst);
+                }
+                if sender.send((index, result)).is_err() {
+                    break;
+                }
+            }
+        })
+    }).collect();
+    drop(sender);
+
+    let mut ret = Ok(());
+    let mut traced = 0;
+    for (index, result) in receiver.iter() {
+        traced += 1;
+        if ret.is_err() {
+            continue;
+        }
+        if let Err(e) = result.and_then(|hits| merge(hits)) {
+            failed.store(true, Ordering::SeqCst);
+            ret = Err((index, e));
+        }
+    }
+    for worker in workers {
+        if worker.join().is_err() {
+            return Err((traced, sgx_status_t::SGX_ERROR_UNEXPECTED));
+        }
+    }
+    ret
+}
+
+fn trace_chunk_ecall(eid: sgx_enclave_id_t, session_id: u64, chunk: &[u8]) -> SgxResult<()> {
+    let mut retval = sgx_status_t::SGX_SUCCESS;
+    let result = unsafe {
+        private_encode_contact_trace(
+            eid,
+            &mut retval,
+            session_id,
+            chunk.as_ptr() as * const u8,
+            chunk.len()
+        )
+    };
+    ecall_result(result, retval)
+}
+
+fn ecall_result(result: sgx_status_t, retval: sgx_status_t) -> SgxResult<()> {
+    if result != sgx_status_t::SGX_SUCCESS {
+        return Err(result);
+    }
+    if retval != sgx_status_t::SGX_SUCCESS {
+        return Err(retval);
+    }
+    Ok(())
+}
+
+// result of the client of detail, whose response must come in upload order
+fn risk_score(detail: &EncodedQueryDataDetail, query_id: QueryId, result: &[u8], encoding: Encoding) -> SgxResult<RiskScore> {
+    if query_id != detail.query_id {
+        println!("[UNTRUSTED] Response of query_id {} where {} was expected", query_id, detail.query_id);
+        return Err(sgx_status_t::SGX_ERROR_UNEXPECTED);
+    }
+    match RiskScore::from_be_bytes(query_id, result, detail.query_size) {
+        Ok(score) => Ok(score.with_values(&detail.values, encoding)),
+        Err(x) => {
+            println!("[UNTRUSTED] Invalid response for query_id {}: {}", query_id, x);
+            Err(sgx_status_t::SGX_ERROR_UNEXPECTED)
+        }
+    }
+}
+
+pub struct SgxBackend {
+    enclave: SgxEnclave,
+    verifier: Box<dyn QuoteVerifier>,
+    session_keys: SessionKeys,
+    // handle of the batch of this backend inside the enclave
+    session_id: u64,
+}
+
+impl SgxBackend {
+    // opens a session for the batch, the enclave is destroyed if that fails
+    pub fn open(enclave: SgxEnclave, verifier: Box<dyn QuoteVerifier>) -> SgxResult<Self> {
+        let mut session_id: u64 = 0;
+        let mut retval = sgx_status_t::SGX_SUCCESS;
+        let result = unsafe {
+            open_session(enclave.geteid(), &mut retval, &mut session_id)
+        };
+        if let Err(x) = ecall_result(result, retval) {
+            enclave.destroy();
+            return Err(x);
+        }
+        Ok(SgxBackend {
+            enclave: enclave,
+            verifier: verifier,
+            session_keys: SessionKeys::new(),
+            session_id: session_id,
+        })
+    }
+}
+
+impl TracingBackend for SgxBackend {
+    fn capabilities(&mut self) -> SgxResult<Capabilities> {
+        let mut bytes = [0_u8; CAPABILITIES_U8];
+        let mut retval = sgx_status_t::SGX_SUCCESS;
+        let result = unsafe {
+            get_capabilities(self.enclave.geteid(), &mut retval, bytes.as_mut_ptr(), CAPABILITIES_U8)
+        };
+        ecall_result(result, retval)?;
+        Capabilities::from_be_bytes(&bytes)
+    }
+
+    fn set_contact_rules(&mut self, rules: &ContactRules) -> SgxResult<()> {
+        let rules_vec = rules.to_be_bytes();
+        let mut retval = sgx_status_t::SGX_SUCCESS;
+        let result = unsafe {
+            set_contact_rules(
+                self.enclave.geteid(),
+                &mut retval,
+                self.session_id,
+                rules_vec.as_ptr() as * const u8,
+                rules_vec.len()
+            )
+        };
+        ecall_result(result, retval)
+    }
+
+    // indexed responses give the hits away to the host, the enclave never builds them
+    fn set_lookup_mode(&mut self, mode: LookupMode) -> SgxResult<()> {
+        if mode == LookupMode::Indexed {
+            return Err(sgx_status_t::SGX_ERROR_FEATURE_NOT_SUPPORTED);
+        }
+        let mut retval = sgx_status_t::SGX_SUCCESS;
+        let result = unsafe {
+            set_lookup_mode(self.enclave.geteid(), &mut retval, self.session_id, mode.id())
+        };
+        ecall_result(result, retval)
+    }
+
+    // a fresh enclave per run, there is no key to replace
+    fn provision_chunk_key(&mut self, chunk_key: &ChunkKey, signing_key: &SigningKey) -> SgxResult<()> {
+        provision_chunk_key_to_enclave(self.enclave.geteid(), chunk_key, signing_key, false, &*self.verifier)
+    }
+
+    // only an enclave built with FEATURE=unsealed_chunks, see enclave/Cargo.toml
+    fn takes_plaintext_chunks(&self) -> bool {
+        cfg!(feature = "unsealed_chunks")
+    }
+
+    fn establish_channels(&mut self, query_id_list: &Vec<QueryId>) -> SgxResult<()> {
+        self.session_keys = establish_secure_channels(self.enclave.geteid(), query_id_list, &*self.verifier)?;
+        Ok(())
+    }
+
+    fn upload_queries(&mut self, query_data: &EncodedQueryData, encoding: Encoding) -> SgxResult<()> {
+        let total_data_vec = query_data.total_data_to_u8(encoding, &self.session_keys);
+        let mut retval = sgx_status_t::SGX_SUCCESS;
+        let result = unsafe {
+            upload_encoded_query_data(
+                self.enclave.geteid(),
+                &mut retval,
+                self.session_id,
+                total_data_vec.as_ptr() as * const u8,
+                total_data_vec.len(),
+                query_data.client_size,
+                query_data.query_id_list().as_ptr() as * const u64
+            )
+        };
+        ecall_result(result, retval)
+    }
+
+    // one ECALL per chunk, concurrent ones run on their own TCS and merge in the enclave
+    fn trace_chunks(&mut self, chunks: Arc<ChunkPipeline>, threads: usize) -> Result<(), (usize, sgx_status_t)> {
+        let eid = self.enclave.geteid();
+        let session_id = self.session_id;
+        trace_on_threads(chunks, threads, move |chunk| trace_chunk_ecall(eid, session_id, &chunk), |()| Ok(()))
+    }
+
+    fn get_result(&mut self, query_data: &EncodedQueryData, encoding: Encoding) -> SgxResult<Vec<RiskScore>> {
+        let client_size = query_data.client_size;
+        let response_size = FRAME_HEADER_U8 + query_data.data.iter()
+            .map(|detail| response_data_size(detail.query_size))
+            .sum::<usize>();
+        let mut response: Vec<u8> = vec![0; response_size];
+        let mut retval = sgx_status_t::SGX_SUCCESS;
+        let result = unsafe {
+            get_encoded_result(
+                self.enclave.geteid(),
+                &mut retval,
+                self.session_id,
+                response.as_mut_ptr(),
+                response_size
+            )
+        };
+        ecall_result(result, retval)?;
+
+        // response frame, see pct_core/src/wire.rs
+        let (header, records) = read_frame(&response, FRAME_RESPONSE)?;
+        if header.count as usize != client_size {
+            return Err(sgx_status_t::SGX_ERROR_INVALID_METADATA);
+        }
+        let mut scores: Vec<RiskScore> = Vec::with_capacity(client_size);
+        let mut cursor = 0;
+        for detail in query_data.data.iter() {
+            let record_size = response_data_size(detail.query_size);
+            if records.len() - cursor < record_size {
+                return Err(sgx_status_t::SGX_ERROR_INVALID_METADATA);
+            }
+            let record = &records[cursor..cursor + record_size];
+            cursor += record_size;
+            /* decryption for each clients using their keys */
+            let query_id: QueryId = query_id_from_u8(&record[..QUERY_ID_SIZE_U8]);
+            let shared_key = match self.session_keys.get(&query_id) {
+                Some(key) => *key,
+                None => {
+                    println!("[UNTRUSTED] Unknown query_id {} in response", query_id);
+                    return Err(sgx_status_t::SGX_ERROR_UNEXPECTED);
+                }
+            };
+            // SGX_ERROR_MAC_MISMATCH if the response has been tampered with
+            let result = open(&shared_key, query_id, DIRECTION_RESPONSE, &record[QUERY_ID_SIZE_U8..])?;
+            scores.push(risk_score(detail, query_id, &result, encoding)?);
+        }
+        Ok(scores)
+    }
+
+    fn destroy(self: Box<Self>) {
+        let mut retval = sgx_status_t::SGX_SUCCESS;
+        let result = unsafe {
+            close_session(self.enclave.geteid(), &mut retval, self.session_id)
+        };
+        if let Err(x) = ecall_result(result, retval) {
+            println!("[UNTRUSTED] Closing session {} failed {}", self.session_id, x.as_str());
+        }
+        self.enclave.destroy();
+    }
+}
+
+#[derive(Default)]
+pub struct NativeBackend {
+    tracer: ContactTracer,
+}
+
+impl NativeBackend {
+    pub fn new() -> Self {
+        NativeBackend::default()
+    }
+}
+
+impl TracingBackend for NativeBackend {
+    // the host sees its own memory anyway, so the indexed responses are offered here only
+    fn capabilities(&mut self) -> SgxResult<Capabilities> {
+        let mut capabilities = Capabilities::current();
+        capabilities.lookup_modes |= 1 << LookupMode::Indexed.id();
+        Ok(capabilities)
+    }
+
+    fn set_contact_rules(&mut self, rules: &ContactRules) -> SgxResult<()> {
+        self.tracer.set_contact_rules(&rules.to_be_bytes())
+    }
+
+    fn set_lookup_mode(&mut self, mode: LookupMode) -> SgxResult<()> {
+        self.tracer.set_lookup_mode(mode);
+        Ok(())
+    }
+
+    // there is no enclave to release the key to, sealed chunks need the sgx backend
+    fn provision_chunk_key(&mut self, _chunk_key: &ChunkKey, _signing_key: &SigningKey) -> SgxResult<()> {
+        Err(sgx_status_t::SGX_ERROR_FEATURE_NOT_SUPPORTED)
+    }
+
+    fn takes_plaintext_chunks(&self) -> bool {
+        true
+    }
+
+    fn establish_channels(&mut self, _query_id_list: &Vec<QueryId>) -> SgxResult<()> {
+        Ok(())
+    }
+
+    fn upload_queries(&mut self, query_data: &EncodedQueryData, encoding: Encoding) -> SgxResult<()> {
+        let queries: Vec<Vec<u8>> = query_data.data.iter()
+            .map(|detail| detail.to_u8(encoding))
+            .collect();
+        self.tracer.upload_queries(encoding, queries, query_data.query_id_list())
+    }
+
+    fn trace_chunks(&mut self, chunks: Arc<ChunkPipeline>, threads: usize) -> Result<(), (usize, sgx_status_t)> {
+        let job = self.tracer.chunk_job().map_err(|e| (0, e))?;
+        let merge_job = job.clone();
+        let tracer = &mut self.tracer;
+        trace_on_threads(
+            chunks, threads,
+            move |chunk| job.trace(chunk.into_vec(), None),
+            |hits| tracer.merge(&merge_job, hits)
+        )
+    }
+
+    fn get_result(&mut self, query_data: &EncodedQueryData, encoding: Encoding) -> SgxResult<Vec<RiskScore>> {
+        let response = self.tracer.build_response()?;
+        let response_size: usize = query_data.data.iter()
+            .map(|detail| plain_response_size(detail.query_size))
+            .sum();
+        if response.len() != response_size {
+            return Err(sgx_status_t::SGX_ERROR_UNEXPECTED);
+        }
+        let mut scores: Vec<RiskScore> = Vec::with_capacity(query_data.client_size);
+        let mut cursor = 0;
+        for detail in query_data.data.iter() {
+            let record = &response[cursor..cursor + plain_response_size(detail.query_size)];
+            cursor += record.len();
+            let query_id: QueryId = query_id_from_u8(&record[..QUERY_ID_SIZE_U8]);
+            scores.push(risk_score(detail, query_id, &record[QUERY_ID_SIZE_U8..], encoding)?);
+        }
+        Ok(scores)
+    }
+
+    fn destroy(self: Box<Self>) {}
+}
@@ -5,9 +5,16 @@ use std::collections::HashMap;
 use std::vec::Vec;
 use std::collections::HashSet;
 use fst::{Set};
-use bincode;
-use util::*;
+use encoding::{ Encoding, EncodedValue, DatasetEncoding };
+use dictionary::ChunkBuilder;
+use chunk_budget::ChunkBudget;
+use record_file::{ RecordReader, RecordWriter, RECORDS_CENTRAL };
+use authority::{ ChunkKey, seal_chunk };
+use sgx_types::SgxResult;
+use pct_core::wire::{ write_frame, read_frame, FRAME_CHUNK };
 use std::mem;
+use std::cmp;
+use std::fs;
 
 
 /* Type Period */
@@ -18,20 +25,33 @@ pub const TIME_INTERVAL: u64 = 600;
 /* TrajectoryTrie
     チャンク化しない
 */
-pub type EncodedValue = [u8; ENCODEDVALUE_SIZE];
-
 #[derive(Clone, Default, Debug)]
 pub struct EncodedData {
+    // None for an empty dataset
+    encoding: Option<Encoding>,
     structure: Vec<EncodedValue>
 }
 
 impl EncodedData {
     pub fn new() -> Self {
         EncodedData {
+            encoding: None,
             structure: Vec::with_capacity(1000000)
         }
     }
 
+    pub fn from_values(encoding: Option<Encoding>, values: Vec<EncodedValue>) -> Self {
+        EncodedData { encoding: encoding, structure: values }
+    }
+
+    pub fn encoding(&self) -> Option<Encoding> {
+        self.encoding
+    }
+
+    pub fn values(&self) -> &[EncodedValue] {
+        &self.structure
+    }
+
     pub fn size(&self) -> usize {
         self.structure.len()
     }
@@ -41,32 +61,40 @@ impl EncodedData {
         let reader = BufReader::new(file);
         let data: ExternalEncodedDataJson = serde_json::from_reader(reader).unwrap();
         
+        let encoding = Encoding::detect_all(data.data.iter(), filename);
         let mut set: HashSet<EncodedValue> = HashSet::with_capacity(100000);
-        if cfg!(any(feature = "th72", feature = "th48", feature = "th54", feature = "th60")) {
+        if let Some(encoding) = encoding {
             for v in data.data.iter() {
-                let mut encoded_value_u8: EncodedValue = [0_u8; ENCODEDVALUE_SIZE];
-                encoded_value_u8.copy_from_slice(base8decode(v.to_string()).as_slice());
-                set.insert(encoded_value_u8);
+                set.insert(encoding.decode(v));
             }
-        } else if cfg!(feature = "gp10") {
-            for v in data.data.iter() {
-                let mut encoded_value_u8: EncodedValue = [0_u8; ENCODEDVALUE_SIZE];
-                // ascii-code
-                encoded_value_u8.copy_from_slice(v.as_bytes());
-                set.insert(encoded_value_u8);
-            }    
-        } else {
-            panic!("Error attribute 'encode' is wrong.")
         }
 
         let vec: Vec<EncodedValue> = set.into_iter().collect();
-        EncodedData { structure: vec }
+        EncodedData { encoding: encoding, structure: vec }
     }
     
+    // central record file, see record_file.rs; duplicates are dropped like in read_raw_from_file
+    pub fn read_records(filename: &str) -> Result<Self, String> {
+        let mut reader = RecordReader::open(filename, RECORDS_CENTRAL)?;
+        let mut set: HashSet<EncodedValue> = HashSet::with_capacity(reader.count().min(1 << 24) as usize);
+        while let Some(value) = reader.next_value() {
+            set.insert(value?);
+        }
+        Ok(EncodedData { encoding: reader.encoding(), structure: set.into_iter().collect() })
+    }
+
+    pub fn write_records(&self, filename: &str) -> Result<u64, String> {
+        let mut writer = RecordWriter::create(filename, RECORDS_CENTRAL, self.encoding)?;
+        for value in self.structure.iter() {
+            writer.push_value(value)?;
+        }
+        writer.finish()
+    }
+
     pub fn prepare_sgx_data(&self, encoded_value_u8: &mut Vec<u8>) -> usize {
         let mut i = 0;
         for value in self.structure.iter() {
-            encoded_value_u8.extend_from_slice(value);
+            encoded_value_u8.extend_from_slice(value.as_ref());
             i += 1;
         }
         i
@@ -75,11 +103,13 @@ impl EncodedData {
     pub fn disribute(&self, buf: &mut Vec<Self>, threashould: usize) {
         let mut val_num = 0;
         let mut data = Self::new();
+        data.encoding = self.encoding;
         for (i, value) in self.structure.iter().enumerate() {
             data.structure.push(*value);
             if (i+1) % threashould == 0 {
                 buf.push(data);
                 data = Self::new();
+                data.encoding = self.encoding;
             }
         }
         if data.structure.len() > 0 {
@@ -88,95 +118,114 @@ impl EncodedData {
     }
 }
 
-#[derive(Clone, Default, Debug)]
-pub struct CentralFST {
-    data: Vec<Vec<u8>>,
+/*
+Type ChunkProducer
+    central data split into chunks of at most threashould values and at most
+    ChunkBudget::max_chunk_bytes, each serialized by one of the builders (round
+    robin), compressed by codec, prefixed with its backend id and codec and sent
+    as one chunk frame (pct_core/src/wire.rs) naming its encoding and number of keys.
+    Chunks are built one by one as the iterator is advanced, see ChunkPipeline
+*/
+pub struct ChunkProducer {
+    encoding: Encoding,
+    values: Vec<EncodedValue>,
+    threashould: usize,
+    budget: ChunkBudget,
+    builders: Vec<Box<dyn ChunkBuilder>>,
+    codec: u8,
+    value_size: usize,
+    // first value of the next chunk
+    start: usize,
+    built: usize,
 }
 
-impl CentralFST {
-    pub fn new() -> Self {
-        CentralFST {
-            data: Vec::with_capacity(100),
+impl ChunkProducer {
+    pub fn new(
+        encoded_data: EncodedData,
+        threashould: usize,
+        budget: ChunkBudget,
+        builders: Vec<Box<dyn ChunkBuilder>>,
+        codec: u8,
+    ) -> Self {
+        let mut values = encoded_data.structure;
+        values.sort();
+        let value_size = values.first().map_or(1, |value| cmp::max(1, value.as_ref().len()));
+        ChunkProducer {
+            encoding: encoded_data.encoding.unwrap_or_default(),
+            values: values,
+            threashould: threashould,
+            budget: budget,
+            builders: builders,
+            codec: codec,
+            value_size: value_size,
+            start: 0,
+            built: 0,
         }
     }
 
-    pub fn len(&self) -> usize {
-        self.data.len()
-    }
-
-    pub fn prepare_sgx_data(&self, index: usize) -> &Vec<u8> {
-        &self.data[index]
-    }
-
-    pub fn from_EncodedData(encoded_data: EncodedData, threashould: usize) -> Self {
-        let mut encoded_value_vec = encoded_data.structure;
-        encoded_value_vec.sort();
-
-        let mut ordered_vec: Vec<EncodedValue> = vec![];
-        let mut this = CentralFST::new();
-
-        for (i, value) in encoded_value_vec.iter().enumerate() {
-            ordered_vec.push(*value);
-            if (i+1) % threashould == 0 {
-                let bytes: Vec<u8> = Set::from_iter(ordered_vec)
-                    .unwrap().as_ref().as_bytes().to_vec();
-                println!(" r_i (server side chunk data) size = {} bytes", bytes.len());
-                this.data.push(bytes);
-                ordered_vec = vec![];
+    /*
+        the number of values per chunk adapts to the budget: it starts from the
+        raw size of a value, and a chunk over the budget is rebuilt with fewer values,
+        scaled by how far it overshot. threashould = usize::MAX leaves it to the budget
+    */
+    fn build_next(&mut self) -> Result<Vec<u8>, String> {
+        let builder = &self.builders[self.built % self.builders.len()];
+        let max_chunk_bytes = self.budget.max_chunk_bytes(&**builder);
+        let remaining = self.values.len() - self.start;
+        let mut count = cmp::max(1, cmp::min(cmp::min(self.threashould, remaining), max_chunk_bytes / self.value_size));
+        loop {
+            let ordered_vec = &self.values[self.start..self.start+count];
+            let dictionary = builder.serialize(ordered_vec);
+            if dictionary.len() <= max_chunk_bytes {
+                let bytes = builder.build_chunk(&dictionary, self.codec);
+                println!("[{}] r_i (server side chunk data) size = {} bytes ({} bytes uncompressed)", builder.name(), bytes.len(), dictionary.len());
+                self.start += count;
+                self.built += 1;
+                return Ok(write_frame(FRAME_CHUNK, self.encoding, count as u32, &bytes));
             }
+            if count == 1 {
+                return Err(format!("a {} chunk of one value needs {} bytes, the budget is {} bytes",
+                    builder.name(), dictionary.len(), max_chunk_bytes));
+            }
+            count = cmp::max(1, cmp::min(count - 1, count * max_chunk_bytes / dictionary.len() * 9 / 10));
         }
-        if ordered_vec.len() > 0 {
-            let bytes = Set::from_iter(ordered_vec)
-                .unwrap().as_ref().as_bytes().to_vec();
-            println!(" r_i (server side chunk data) size = {} bytes", bytes.len());
-            this.data.push(bytes);
-        }
-        this
     }
 }
 
-#[derive(Clone, Default, Debug)]
-pub struct CentralHashSet {
-    data: Vec<Vec<u8>>,
-}
+impl Iterator for ChunkProducer {
+    type Item = Result<Vec<u8>, String>;
 
-impl CentralHashSet {
-    pub fn new() -> Self {
-        CentralHashSet {
-            data: Vec::with_capacity(100),
+    // the chunk frame of the next values, nothing more after an error
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.start >= self.values.len() {
+            return None;
         }
+        let chunk = self.build_next();
+        if chunk.is_err() {
+            self.start = self.values.len();
+        }
+        Some(chunk)
     }
+}
 
-    pub fn len(&self) -> usize {
-        self.data.len()
-    }
+// the body of a chunk frame sealed with the chunk key of the health authority, the header stays in the clear
+pub fn seal_chunk_frame(chunk_key: &ChunkKey, frame: &[u8]) -> SgxResult<Vec<u8>> {
+    let (header, chunk) = read_frame(frame, FRAME_CHUNK)?;
+    Ok(write_frame(FRAME_CHUNK, header.encoding, header.count, &seal_chunk(chunk_key, chunk)?))
+}
 
-    pub fn prepare_sgx_data(&self, index: usize) -> &Vec<u8> {
-        &self.data[index]
-    }
+// the whole central data in one dictionary, for the non private baseline
+pub trait NonPrivateDictionary {
+    fn contains(&self, encoded_value: &EncodedValue) -> bool;
 
-    pub fn from_EncodedData(encoded_data: EncodedData, threashould: usize) -> Self {
-        let mut encoded_value_vec = encoded_data.structure;
-        encoded_value_vec.sort();
+    fn calc_memory(&self);
+}
 
-        let mut hashset: HashSet<EncodedValue> = HashSet::with_capacity(threashould);
-        
-        let mut this = CentralHashSet::new();
-        for (i, value) in encoded_value_vec.iter().enumerate() {
-            hashset.insert(*value);
-            if (i+1) % threashould == 0 {
-                let bytes: Vec<u8> = bincode::serialize(&hashset).unwrap();
-                println!("[HashSet] r_i size = {} bytes", bytes.len());
-                this.data.push(bytes);
-                hashset = HashSet::with_capacity(threashould);
-            }
-        }
-        if hashset.len() > 0 {
-            let bytes: Vec<u8> = bincode::serialize(&hashset).unwrap();
-            println!("[HashSet] r_i size = {} bytes", bytes.len());
-            this.data.push(bytes);
-        }
-        this
+pub fn non_private_dictionary_from_name(name: &str, encoded_data: EncodedData) -> Option<Box<dyn NonPrivateDictionary>> {
+    match name {
+        "fsa" => Some(Box::new(NonPrivateFSA::from_EncodedData(encoded_data))),
+        "hashtable" => Some(Box::new(NonPrivateHashSet::from_EncodedData(encoded_data))),
+        _ => None,
     }
 }
 
@@ -201,8 +250,14 @@ impl NonPrivateHashSet {
         }
         this
     }
+}
+
+impl NonPrivateDictionary for NonPrivateHashSet {
+    fn contains(&self, encoded_value: &EncodedValue) -> bool {
+        self.set.contains(encoded_value)
+    }
 
-    pub fn calc_memory(&self) {
+    fn calc_memory(&self) {
         println!("HashTable size = {} bytes", (self.set.capacity() * 11 / 10) * (mem::size_of::<EncodedValue>() + mem::size_of::<()>() + mem::size_of::<u64>()));
     }
 }
@@ -219,7 +274,7 @@ impl FstValue {
 impl AsRef<[u8]> for FstValue {
     #[inline]
     fn as_ref(&self) -> &[u8] {
-        &self.value
+        self.value.as_ref()
     }
 }
 
@@ -243,8 +298,14 @@ impl NonPrivateFSA {
         this.set = Set::from_iter(encoded_value_vec).unwrap();
         this
     }
+}
+
+impl NonPrivateDictionary for NonPrivateFSA {
+    fn contains(&self, encoded_value: &EncodedValue) -> bool {
+        self.set.contains(encoded_value)
+    }
 
-    pub fn calc_memory(&self) {
+    fn calc_memory(&self) {
         println!("FSA size = {} bytes", self.set.as_ref().size());
     }
 }
@@ -0,0 +1,587 @@
+use serde::*;
+use std::fs;
+use std::cmp;
+use std::collections::BTreeMap;
+use std::path::{ Path, PathBuf };
+use fst::{ Set, SetBuilder, Streamer };
+use fst::set::{ OpBuilder, Difference };
+use encoding::{ Encoding, EncodedValue, DatasetEncoding };
+use central_data::EncodedData;
+
+pub const STORE_MANIFEST: &'static str = "MANIFEST";
+// infectious window, older records are dropped by expire
+pub const RETENTION_SECS: u64 = 14 * 24 * 60 * 60;
+const DAY_SECS: u64 = 24 * 60 * 60;
+
+/*
+Type CentralStore
+    Persistent central data of the health authority, one directory of
+        MANIFEST                 current snapshot, replaced atomically (write + rename)
+        <id>.fst                 immutable FST set of one appended batch or compaction
+        <batch id>.batch.fst     keys of an appended batch (provenance), kept until it expires
+        <id>.tombstone.fst       keys of a revoked batch that no other live batch has
+    append adds a batch of new positive cases as a new segment, expire drops the
+    records older than RETENTION_SECS, compact merges segments with OpBuilder::union.
+    revoke withdraws a batch (a retracted positive test) at once: its tombstone is
+    subtracted from the segments with OpBuilder::difference whenever the snapshot
+    is read, until compact drops the tombstoned keys from the segments.
+    Every operation writes the next snapshot and only then removes the files
+    it no longer references, so a reader sees either snapshot in full.
+    Data read from the store outside of it (prebuilt or sealed chunks) names the
+    StoreSnapshot it was read from and is refused once a later revoke or expire
+    has withdrawn records, see check_snapshot.
+
+    Expiry reads the time slot of the gp10 keys (slot_origin + slot * slot_seconds).
+    TrajectoryHash interleaves time with space, so those segments expire as a whole
+    RETENTION_SECS after they were appended, and are only compacted with segments
+    appended on the same day.
+*/
+#[derive(Clone, Debug)]
+pub struct CentralStore {
+    dir: PathBuf,
+    manifest: StoreManifest,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct StoreManifest {
+    pub snapshot: u64,
+    // Encoding::id, None until the first append
+    pub encoding: Option<u8>,
+    // unix seconds of time slot 0 and the length of a slot (gp10)
+    pub slot_origin: u64,
+    pub slot_seconds: u64,
+    // last id of a segment, batch or tombstone file, ids are never reused
+    pub last_segment_id: u64,
+    pub segments: Vec<StoreSegment>,
+    #[serde(default)]
+    pub batches: Vec<StoreBatch>,
+    #[serde(default)]
+    pub tombstones: Vec<StoreTombstone>,
+    // last snapshot that withdrew records (revoke, expire), 0 if none has
+    #[serde(default)]
+    pub withdrawn: u64,
+}
+
+// the snapshot of a store some data has been read from
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct StoreSnapshot {
+    // canonical path of the store directory
+    pub dir: String,
+    pub snapshot: u64,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct StoreSegment {
+    pub id: u64,
+    // unix seconds of the append, the newest one for a compacted segment
+    pub added: u64,
+    pub count: usize,
+    // time slots of its keys, None without a separable time
+    pub min_slot: Option<u32>,
+    pub max_slot: Option<u32>,
+}
+
+// provenance of one append, named by the id of the segment it was written to
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct StoreBatch {
+    pub id: u64,
+    pub added: u64,
+    // central data file it was read from
+    pub source: String,
+    pub count: usize,
+    pub min_slot: Option<u32>,
+    pub max_slot: Option<u32>,
+    // unix seconds of the revocation
+    pub revoked: Option<u64>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct StoreTombstone {
+    pub id: u64,
+    // revoked batch
+    pub batch: u64,
+    pub count: usize,
+}
+
+const SEGMENT: &'static str = "";
+const BATCH: &'static str = ".batch";
+const TOMBSTONE: &'static str = ".tombstone";
+
+impl CentralStore {
+    pub fn exists(dirname: &str) -> bool {
+        Path::new(dirname).join(STORE_MANIFEST).is_file()
+    }
+
+    // an empty store, its encoding is taken from the first batch
+    pub fn create(dirname: &str, slot_origin: u64, slot_seconds: u64) -> Result<Self, String> {
+        if CentralStore::exists(dirname) {
+            return Err(format!("{} is already a central store", dirname));
+        }
+        if slot_seconds == 0 {
+            return Err("a time slot has to be at least one second".to_string());
+        }
+        fs::create_dir_all(dirname).map_err(|e| format!("{}: {}", dirname, e))?;
+        let mut this = CentralStore {
+            dir: PathBuf::from(dirname),
+            manifest: StoreManifest {
+                snapshot: 0,
+                encoding: None,
+                slot_origin: slot_origin,
+                slot_seconds: slot_seconds,
+                last_segment_id: 0,
+                segments: Vec::new(),
+                batches: Vec::new(),
+                tombstones: Vec::new(),
+                withdrawn: 0,
+            },
+        };
+        this.commit(Vec::new())?;
+        Ok(this)
+    }
+
+    pub fn open(dirname: &str) -> Result<Self, String> {
+        let filename = Path::new(dirname).join(STORE_MANIFEST);
+        let bytes = fs::read(&filename).map_err(|e| format!("{}: {}", filename.display(), e))?;
+        let manifest: StoreManifest = serde_json::from_slice(&bytes)
+            .map_err(|e| format!("{}: {}", filename.display(), e))?;
+        Ok(CentralStore { dir: PathBuf::from(dirname), manifest: manifest })
+    }
+
+    pub fn manifest(&self) -> &StoreManifest {
+        &self.manifest
+    }
+
+    // names the current snapshot, for data read with encoded_data
+    pub fn snapshot(&self) -> Result<StoreSnapshot, String> {
+        let dir = fs::canonicalize(&self.dir).map_err(|e| format!("{}: {}", self.dir.display(), e))?;
+        Ok(StoreSnapshot { dir: dir.to_string_lossy().into_owned(), snapshot: self.manifest.snapshot })
+    }
+
+    // fails if the store has withdrawn records since the data of snapshot has been read
+    pub fn check_snapshot(snapshot: &StoreSnapshot) -> Result<(), String> {
+        let store = CentralStore::open(&snapshot.dir)?;
+        if store.manifest.withdrawn > snapshot.snapshot {
+            return Err(format!("read from snapshot {} of {}, records have been revoked or expired in snapshot {} since",
+                snapshot.snapshot, snapshot.dir, store.manifest.withdrawn));
+        }
+        Ok(())
+    }
+
+    pub fn encoding(&self) -> Option<Encoding> {
+        self.manifest.encoding.and_then(Encoding::from_id)
+    }
+
+    // records of the segments of the current snapshot, tombstones not subtracted
+    pub fn size(&self) -> usize {
+        self.manifest.segments.iter().map(|segment| segment.count).sum()
+    }
+
+    /*
+        a batch of new positive cases read from source, added is the unix time of the append,
+        returns its batch id (None for an empty batch)
+    */
+    pub fn append(&mut self, batch: &EncodedData, source: &str, added: u64) -> Result<Option<u64>, String> {
+        let encoding = match batch.encoding() {
+            Some(encoding) => encoding,
+            None => { return Ok(None); }
+        };
+        match self.encoding() {
+            Some(current) if current != encoding => {
+                return Err(format!("the store is {} but the batch is {}", current.name(), encoding.name()));
+            },
+            _ => { self.manifest.encoding = Some(encoding.id()); }
+        }
+        let mut values: Vec<EncodedValue> = batch.values().to_vec();
+        values.sort();
+        values.dedup();
+        let set = Set::from_iter(values.iter()).map_err(|e| e.to_string())?;
+        let segment = self.write_segment(&set, added)?;
+        // the batch may bring back keys a revoked batch had
+        let mut removed: Vec<PathBuf> = Vec::new();
+        let mut tombstones: Vec<StoreTombstone> = Vec::with_capacity(self.manifest.tombstones.len());
+        for tombstone in self.manifest.tombstones.clone().into_iter() {
+            let tombstone_set = self.read_set(tombstone.id, TOMBSTONE)?;
+            let mut builder = SetBuilder::memory();
+            builder.extend_stream(tombstone_set.op().add(&set).difference()).map_err(|e| e.to_string())?;
+            let remaining = builder.into_set();
+            let id = self.write_set(&remaining, TOMBSTONE)?;
+            tombstones.push(StoreTombstone { id: id, batch: tombstone.batch, count: remaining.len() });
+            removed.push(self.set_path(tombstone.id, TOMBSTONE));
+        }
+        self.manifest.tombstones = tombstones;
+        fs::copy(self.set_path(segment.id, SEGMENT), self.set_path(segment.id, BATCH))
+            .map_err(|e| format!("{}: {}", self.set_path(segment.id, BATCH).display(), e))?;
+        self.manifest.batches.push(StoreBatch {
+            id: segment.id,
+            added: added,
+            source: source.to_string(),
+            count: segment.count,
+            min_slot: segment.min_slot,
+            max_slot: segment.max_slot,
+            revoked: None,
+        });
+        let id = segment.id;
+        self.manifest.segments.push(segment);
+        self.commit(removed)?;
+        Ok(Some(id))
+    }
+
+    /*
+        withdraws the records of a batch from now on, returns how many keys are
+        tombstoned; keys that another live batch has as well stay
+    */
+    pub fn revoke(&mut self, batch_id: u64, now: u64) -> Result<usize, String> {
+        let index = match self.manifest.batches.iter().position(|batch| batch.id == batch_id) {
+            Some(index) => index,
+            None => { return Err(format!("no batch {} in the store", batch_id)); }
+        };
+        if self.manifest.batches[index].revoked.is_some() {
+            return Err(format!("batch {} is already revoked", batch_id));
+        }
+        let revoked_set = self.read_set(batch_id, BATCH)?;
+        let mut live_sets: Vec<Set<Vec<u8>>> = Vec::new();
+        for batch in self.manifest.batches.iter() {
+            if batch.id != batch_id && batch.revoked.is_none() {
+                live_sets.push(self.read_set(batch.id, BATCH)?);
+            }
+        }
+        let mut op = OpBuilder::new().add(&revoked_set);
+        for live_set in live_sets.iter() {
+            op.push(live_set);
+        }
+        let mut builder = SetBuilder::memory();
+        builder.extend_stream(op.difference()).map_err(|e| e.to_string())?;
+        let tombstone_set = builder.into_set();
+        let count = tombstone_set.len();
+        let id = self.write_set(&tombstone_set, TOMBSTONE)?;
+        self.manifest.tombstones.push(StoreTombstone { id: id, batch: batch_id, count: count });
+        self.manifest.batches[index].revoked = Some(now);
+        self.manifest.withdrawn = self.manifest.snapshot + 1;
+        // later revocations only subtract the live batches
+        let removed = vec![self.set_path(batch_id, BATCH)];
+        self.commit(removed)?;
+        Ok(count)
+    }
+
+    /*
+        drops the records of the snapshot older than RETENTION_SECS before now,
+        returns how many have been dropped
+    */
+    pub fn expire(&mut self, now: u64) -> Result<usize, String> {
+        let cutoff = now.saturating_sub(RETENTION_SECS);
+        let encoding = self.encoding();
+        let cutoff_slot = self.slot_of(cutoff);
+        let mut kept: Vec<StoreSegment> = Vec::with_capacity(self.manifest.segments.len());
+        let mut removed: Vec<PathBuf> = Vec::new();
+        let mut dropped = 0;
+        for segment in self.manifest.segments.clone().into_iter() {
+            let expired = match (segment.min_slot, segment.max_slot) {
+                // every key of the segment is in the window
+                (Some(min_slot), _) if min_slot >= cutoff_slot => false,
+                // some keys are
+                (Some(_), Some(max_slot)) if max_slot >= cutoff_slot => {
+                    let set = self.read_set(segment.id, SEGMENT)?;
+                    let mut builder = SetBuilder::memory();
+                    let mut stream = set.stream();
+                    while let Some(key) = stream.next() {
+                        let in_window = encoding.and_then(|encoding| encoding.time_slot(key))
+                            .map_or(true, |slot| slot >= cutoff_slot);
+                        if in_window {
+                            builder.insert(key).map_err(|e| e.to_string())?;
+                        }
+                    }
+                    let filtered = builder.into_set();
+                    dropped += segment.count - filtered.len();
+                    kept.push(self.write_segment(&filtered, segment.added)?);
+                    removed.push(self.set_path(segment.id, SEGMENT));
+                    continue;
+                },
+                (Some(_), _) => true,
+                (None, _) => segment.added < cutoff,
+            };
+            if expired {
+                dropped += segment.count;
+                removed.push(self.set_path(segment.id, SEGMENT));
+            } else {
+                kept.push(segment);
+            }
+        }
+        self.manifest.segments = kept;
+        // with all its keys, the provenance of a batch expires
+        let mut batches: Vec<StoreBatch> = Vec::with_capacity(self.manifest.batches.len());
+        for batch in self.manifest.batches.clone().into_iter() {
+            let expired = match batch.max_slot {
+                Some(max_slot) => max_slot < cutoff_slot,
+                None => batch.added < cutoff,
+            };
+            if !expired {
+                batches.push(batch);
+            } else if batch.revoked.is_none() {
+                removed.push(self.set_path(batch.id, BATCH));
+            }
+        }
+        self.manifest.batches = batches;
+        if dropped > 0 {
+            self.manifest.withdrawn = self.manifest.snapshot + 1;
+        }
+        self.commit(removed)?;
+        Ok(dropped)
+    }
+
+    /*
+        merges the segments into one with OpBuilder::union, per append day
+        for encodings without a time slot, and drops the tombstoned keys from them,
+        returns the number of segments left
+    */
+    pub fn compact(&mut self) -> Result<usize, String> {
+        let separable = self.encoding() == Some(Encoding::Gp10);
+        let mut groups: BTreeMap<u64, Vec<StoreSegment>> = BTreeMap::new();
+        for segment in self.manifest.segments.iter() {
+            let day = if separable { 0 } else { segment.added / DAY_SECS };
+            groups.entry(day).or_insert_with(Vec::new).push(segment.clone());
+        }
+        let tombstone_sets = self.read_tombstones()?;
+        let mut kept: Vec<StoreSegment> = Vec::with_capacity(groups.len());
+        let mut removed: Vec<PathBuf> = Vec::new();
+        for (_, group) in groups.into_iter() {
+            if group.len() == 1 && tombstone_sets.is_empty() {
+                kept.extend(group);
+                continue;
+            }
+            let mut sets: Vec<Set<Vec<u8>>> = Vec::with_capacity(group.len());
+            for segment in group.iter() {
+                sets.push(self.read_set(segment.id, SEGMENT)?);
+            }
+            let mut builder = SetBuilder::memory();
+            builder.extend_stream(live_keys(&sets, &tombstone_sets)).map_err(|e| e.to_string())?;
+            let added = group.iter().map(|segment| segment.added).max().unwrap_or(0);
+            kept.push(self.write_segment(&builder.into_set(), added)?);
+            removed.extend(group.iter().map(|segment| self.set_path(segment.id, SEGMENT)));
+        }
+        kept.sort_by_key(|segment| segment.id);
+        self.manifest.segments = kept;
+        removed.extend(self.manifest.tombstones.iter().map(|tombstone| self.set_path(tombstone.id, TOMBSTONE)));
+        self.manifest.tombstones = Vec::new();
+        self.commit(removed)?;
+        Ok(self.manifest.segments.len())
+    }
+
+    // every live record of the snapshot, revoked ones subtracted
+    pub fn encoded_data(&self) -> Result<EncodedData, String> {
+        let mut sets: Vec<Set<Vec<u8>>> = Vec::with_capacity(self.manifest.segments.len());
+        for segment in self.manifest.segments.iter() {
+            sets.push(self.read_set(segment.id, SEGMENT)?);
+        }
+        let tombstone_sets = self.read_tombstones()?;
+        let mut values: Vec<EncodedValue> = Vec::with_capacity(self.size());
+        let mut keys = live_keys(&sets, &tombstone_sets);
+        while let Some(key) = keys.next() {
+            values.push(EncodedValue::from_slice(key).ok_or_else(|| format!("{}: a key longer than any encoding", self.dir.display()))?);
+        }
+        Ok(EncodedData::from_values(self.encoding(), values))
+    }
+
+    // time slot a unix time falls in, 0 before slot_origin
+    fn slot_of(&self, unixepoch: u64) -> u32 {
+        let slot = unixepoch.saturating_sub(self.manifest.slot_origin) / self.manifest.slot_seconds;
+        cmp::min(slot, u32::max_value() as u64) as u32
+    }
+
+    // kind is SEGMENT, BATCH or TOMBSTONE
+    fn set_path(&self, id: u64, kind: &str) -> PathBuf {
+        self.dir.join(format!("{:08}{}.fst", id, kind))
+    }
+
+    fn read_set(&self, id: u64, kind: &str) -> Result<Set<Vec<u8>>, String> {
+        let filename = self.set_path(id, kind);
+        let bytes = fs::read(&filename).map_err(|e| format!("{}: {}", filename.display(), e))?;
+        Set::new(bytes).map_err(|e| format!("{}: {}", filename.display(), e))
+    }
+
+    fn read_tombstones(&self) -> Result<Vec<Set<Vec<u8>>>, String> {
+        let mut sets: Vec<Set<Vec<u8>>> = Vec::with_capacity(self.manifest.tombstones.len());
+        for tombstone in self.manifest.tombstones.iter() {
+            sets.push(self.read_set(tombstone.id, TOMBSTONE)?);
+        }
+        Ok(sets)
+    }
+
+    // a new immutable file under the next id, referenced once the next snapshot is committed
+    fn write_set(&mut self, set: &Set<Vec<u8>>, kind: &str) -> Result<u64, String> {
+        self.manifest.last_segment_id += 1;
+        let id = self.manifest.last_segment_id;
+        let filename = self.set_path(id, kind);
+        fs::write(&filename, set.as_fst().as_bytes()).map_err(|e| format!("{}: {}", filename.display(), e))?;
+        Ok(id)
+    }
+
+    fn write_segment(&mut self, set: &Set<Vec<u8>>, added: u64) -> Result<StoreSegment, String> {
+        let encoding = self.encoding();
+        let mut min_slot: Option<u32> = None;
+        let mut max_slot: Option<u32> = None;
+        let mut stream = set.stream();
+        while let Some(key) = stream.next() {
+            if let Some(slot) = encoding.and_then(|encoding| encoding.time_slot(key)) {
+                min_slot = Some(min_slot.map_or(slot, |min| cmp::min(min, slot)));
+                max_slot = Some(max_slot.map_or(slot, |max| cmp::max(max, slot)));
+            }
+        }
+        let id = self.write_set(set, SEGMENT)?;
+        Ok(StoreSegment { id: id, added: added, count: set.len(), min_slot: min_slot, max_slot: max_slot })
+    }
+
+    // writes the next snapshot, then removes the files it replaced
+    fn commit(&mut self, removed: Vec<PathBuf>) -> Result<(), String> {
+        self.manifest.snapshot += 1;
+        let bytes = serde_json::to_vec_pretty(&self.manifest).map_err(|e| e.to_string())?;
+        let filename = self.dir.join(STORE_MANIFEST);
+        let tmp_filename = self.dir.join(format!("{}.tmp", STORE_MANIFEST));
+        fs::write(&tmp_filename, &bytes).map_err(|e| format!("{}: {}", tmp_filename.display(), e))?;
+        fs::rename(&tmp_filename, &filename).map_err(|e| format!("{}: {}", filename.display(), e))?;
+        for filename in removed.into_iter() {
+            // a leftover file is never read again, so a failure here is harmless
+            let _ = fs::remove_file(filename);
+        }
+        Ok(())
+    }
+}
+
+// union of the segments minus the union of the tombstones
+fn live_keys<'s>(sets: &'s [Set<Vec<u8>>], tombstone_sets: &'s [Set<Vec<u8>>]) -> Difference<'s> {
+    let mut union = OpBuilder::new();
+    for set in sets.iter() {
+        union.push(set);
+    }
+    let mut op = OpBuilder::new().add(union.union());
+    for tombstone_set in tombstone_sets.iter() {
+        op.push(tombstone_set);
+    }
+    op.difference()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use util::test_dir;
+
+    const SLOT_SECONDS: u64 = 600;
+    const SLOTS_PER_DAY: u32 = (DAY_SECS / SLOT_SECONDS) as u32;
+
+    fn gp10(cell: &str, slot: u32) -> EncodedValue {
+        Encoding::Gp10.decode(&format!("{}{:04}", cell, slot))
+    }
+
+    fn batch(values: &[EncodedValue]) -> EncodedData {
+        EncodedData::from_values(Some(Encoding::Gp10), values.to_vec())
+    }
+
+    fn live_values(store: &CentralStore) -> Vec<EncodedValue> {
+        store.encoded_data().unwrap().values().to_vec()
+    }
+
+    fn create(name: &str) -> CentralStore {
+        let dir = test_dir(name);
+        CentralStore::create(dir.to_str().unwrap(), 0, SLOT_SECONDS).unwrap()
+    }
+
+    #[test]
+    fn append_drops_duplicate_keys() {
+        let mut store = create("append");
+        let a = gp10("xn76urx6rb", 10);
+        let b = gp10("xn76urx6rc", 10);
+        store.append(&batch(&[b, a, b, a, a]), "first.json", 0).unwrap();
+        assert_eq!(store.size(), 2);
+        assert_eq!(live_values(&store), vec![a, b]);
+        // the same keys appended again are read once
+        store.append(&batch(&[a, b]), "second.json", 1).unwrap();
+        assert_eq!(live_values(&store), vec![a, b]);
+        assert_eq!(store.compact().unwrap(), 1);
+        assert_eq!(store.size(), 2);
+        assert_eq!(store.append(&EncodedData::from_values(None, Vec::new()), "empty.json", 2).unwrap(), None);
+        assert!(store.append(&EncodedData::from_values(Some(Encoding::Th48), vec![Encoding::Th48.decode("0123456701234567")]), "th48.json", 3).is_err());
+
+        // a reopened store reads the committed snapshot
+        let reopened = CentralStore::open(store.dir.to_str().unwrap()).unwrap();
+        assert_eq!(reopened.manifest().snapshot, store.manifest().snapshot);
+        assert_eq!(live_values(&reopened), vec![a, b]);
+    }
+
+    #[test]
+    fn expire_drops_the_records_older_than_14_days() {
+        let mut store = create("expire");
+        let now = 30 * DAY_SECS;
+        let today = (now / SLOT_SECONDS) as u32;
+        let old = gp10("xn76urx6rb", today - 15 * SLOTS_PER_DAY);
+        let edge = gp10("xn76urx6rb", today - 14 * SLOTS_PER_DAY);
+        let fresh = gp10("xn76urx6rc", today - SLOTS_PER_DAY);
+        let old_batch = store.append(&batch(&[old]), "old.json", 0).unwrap().unwrap();
+        store.append(&batch(&[old, edge, fresh]), "mixed.json", 0).unwrap();
+        assert_eq!(store.expire(now).unwrap(), 2);
+        assert_eq!(live_values(&store), vec![edge, fresh]);
+        // with all its keys, the batch has expired
+        assert!(store.manifest().batches.iter().all(|batch| batch.id != old_batch));
+        assert_eq!(store.expire(now).unwrap(), 0);
+        assert_eq!(store.expire(now + DAY_SECS).unwrap(), 1);
+        assert_eq!(live_values(&store), vec![fresh]);
+    }
+
+    #[test]
+    fn trajectory_hash_segments_expire_as_a_whole() {
+        let mut store = create("expire-th48");
+        let value = Encoding::Th48.decode("0123456701234567");
+        store.append(&EncodedData::from_values(Some(Encoding::Th48), vec![value]), "th48.json", DAY_SECS).unwrap();
+        assert_eq!(store.expire(DAY_SECS + RETENTION_SECS).unwrap(), 0);
+        assert_eq!(store.expire(DAY_SECS + RETENTION_SECS + 1).unwrap(), 1);
+        assert_eq!(store.size(), 0);
+    }
+
+    #[test]
+    fn revoked_keys_stay_out_after_compact() {
+        let mut store = create("revoke");
+        let only_revoked = gp10("xn76urx6rb", 10);
+        let shared = gp10("xn76urx6rc", 10);
+        let live = gp10("xn76urx6rd", 10);
+        let revoked = store.append(&batch(&[only_revoked, shared]), "revoked.json", 0).unwrap().unwrap();
+        store.append(&batch(&[shared, live]), "live.json", 0).unwrap();
+        // the key another live batch has as well stays
+        assert_eq!(store.revoke(revoked, 1).unwrap(), 1);
+        assert!(store.revoke(revoked, 1).is_err());
+        assert!(store.revoke(revoked + 100, 1).is_err());
+        assert_eq!(live_values(&store), vec![shared, live]);
+        let tombstones: Vec<PathBuf> = store.manifest().tombstones.iter()
+            .map(|tombstone| store.set_path(tombstone.id, TOMBSTONE))
+            .collect();
+        assert_eq!(tombstones.len(), 1);
+
+        assert_eq!(store.compact().unwrap(), 1);
+        assert!(store.manifest().tombstones.is_empty());
+        assert!(tombstones.iter().all(|tombstone| !tombstone.exists()));
+        assert_eq!(store.size(), 2);
+        assert_eq!(live_values(&store), vec![shared, live]);
+        // appended again, the key is live again
+        store.append(&batch(&[only_revoked]), "again.json", 2).unwrap();
+        assert_eq!(live_values(&store), vec![only_revoked, shared, live]);
+    }
+
+    #[test]
+    fn snapshots_are_stale_after_a_revoke_or_expire() {
+        let mut store = create("snapshot");
+        let old = gp10("xn76urx6rb", 0);
+        let batch_id = store.append(&batch(&[gp10("xn76urx6rc", 10 * SLOTS_PER_DAY)]), "first.json", 0).unwrap().unwrap();
+        let snapshot = store.snapshot().unwrap();
+        CentralStore::check_snapshot(&snapshot).unwrap();
+        // new records do not withdraw any
+        store.append(&batch(&[old]), "second.json", 0).unwrap();
+        store.compact().unwrap();
+        CentralStore::check_snapshot(&snapshot).unwrap();
+        store.expire(RETENTION_SECS + DAY_SECS).unwrap();
+        assert!(CentralStore::check_snapshot(&snapshot).is_err());
+
+        let snapshot = store.snapshot().unwrap();
+        assert_eq!(store.expire(RETENTION_SECS + DAY_SECS).unwrap(), 0);
+        CentralStore::check_snapshot(&snapshot).unwrap();
+        store.revoke(batch_id, 1).unwrap();
+        assert!(CentralStore::check_snapshot(&snapshot).is_err());
+        CentralStore::check_snapshot(&store.snapshot().unwrap()).unwrap();
+    }
+}
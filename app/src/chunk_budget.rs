@@ -0,0 +1,121 @@
+use std::env;
+use std::fs;
+use std::cmp;
+use dictionary::ChunkBuilder;
+use pct_core::chunk_codec::CODEC_NONE;
+use pct_core::constant::SEALED_OVERHEAD_U8;
+use pct_core::wire::FRAME_HEADER_U8;
+
+// HeapMaxSize, StackMaxSize and TCSNum of the enclave when it does not report
+// them (native backend, bin/app seal-chunks)
+pub const ENCLAVE_CONFIG_PATH: &'static str = "enclave/Enclave.config.xml";
+// usable EPC of a 128MB PRM, PCT_EPC_SIZE overrides it
+pub const DEFAULT_EPC_SIZE: usize = 93 * 1024 * 1024;
+// share of the memory one chunk and its copies may take, the rest is left
+// to the query batches, the secure channels and the allocator
+const CHUNK_MEMORY_PERCENT: usize = 50;
+
+/*
+Type ChunkBudget
+    Upper bound of the size of one chunk, so that tracing it stays within
+    the enclave heap and, to avoid EPC paging, within what the stacks of the
+    TCSNum threads (StackMaxSize each) leave of the EPC. While a chunk is
+    traced the enclave holds
+        the copy of the ECALL buffer
+        the copy the dictionary is read from (pct_core ContactTracer::trace_chunk)
+        the plaintext of a sealed chunk
+        the decompressed dictionary of a compressed chunk
+        what the dictionary builds on top of it (ChunkBuilder::expansion)
+    and as many chunks at once as threads trace them (shared_by).
+    Compressed copies are counted at the size of the dictionary, which the
+    LZ codec exceeds by less than 1/255 on incompressible data.
+*/
+#[derive(Clone, Copy, Debug)]
+pub struct ChunkBudget {
+    // bytes all copies of one chunk may take together
+    memory: usize,
+    sealed: bool,
+    compressed: bool,
+}
+
+impl ChunkBudget {
+    // stack_size is the stack of all enclave threads together
+    pub fn new(heap_size: usize, stack_size: usize, sealed: bool, codec: u8) -> Self {
+        let epc_size = epc_size_from_env().saturating_sub(stack_size);
+        ChunkBudget {
+            memory: cmp::min(heap_size, epc_size) / 100 * CHUNK_MEMORY_PERCENT,
+            sealed: sealed,
+            compressed: codec != CODEC_NONE,
+        }
+    }
+
+    /*
+        heap size and threads (TCSNum) reported by the enclave, HeapMaxSize and
+        TCSNum of ENCLAVE_CONFIG_PATH if it reports none. Every thread reserves
+        the StackMaxSize of ENCLAVE_CONFIG_PATH.
+    */
+    pub fn from_capabilities(heap_size: u64, max_threads: u32, sealed: bool, codec: u8) -> Result<Self, String> {
+        let heap_size = match heap_size {
+            0 => size_from_config(ENCLAVE_CONFIG_PATH, "HeapMaxSize")?,
+            heap_size => heap_size as usize,
+        };
+        let max_threads = match max_threads {
+            0 => size_from_config(ENCLAVE_CONFIG_PATH, "TCSNum")?,
+            max_threads => max_threads as usize,
+        };
+        let stack_size = size_from_config(ENCLAVE_CONFIG_PATH, "StackMaxSize")?;
+        Ok(ChunkBudget::new(heap_size, stack_size.saturating_mul(max_threads), sealed, codec))
+    }
+
+    // threads chunks traced at once, see backend::threads_from_env
+    pub fn shared_by(self, threads: usize) -> Self {
+        ChunkBudget {
+            memory: self.memory / cmp::max(1, threads),
+            ..self
+        }
+    }
+
+    // bytes ChunkBuilder::serialize of builder may return, leaving room for the chunk header, frame and seal
+    pub fn max_chunk_bytes(&self, builder: &dyn ChunkBuilder) -> usize {
+        let copies = (if self.sealed { 3 } else { 2 }) + (if self.compressed { 1 } else { 0 }) + builder.expansion();
+        let overhead = FRAME_HEADER_U8 + 2 + (if self.sealed { SEALED_OVERHEAD_U8 } else { 0 });
+        let max_chunk_bytes = (self.memory / copies).saturating_sub(overhead);
+        if self.compressed { max_chunk_bytes / 256 * 255 } else { max_chunk_bytes }
+    }
+}
+
+/*
+    PCT_EPC_SIZE in bytes (K, M, G suffixes), DEFAULT_EPC_SIZE if unset
+*/
+pub fn epc_size_from_env() -> usize {
+    env::var("PCT_EPC_SIZE").ok()
+        .and_then(|size| parse_size(&size))
+        .unwrap_or(DEFAULT_EPC_SIZE)
+}
+
+// <HeapMaxSize>0x90000000</HeapMaxSize>, <TCSNum>4</TCSNum>
+pub fn size_from_config(filename: &str, field: &str) -> Result<usize, String> {
+    let config = fs::read_to_string(filename)
+        .map_err(|x| format!("cannot read {}: {}", filename, x))?;
+    let open = format!("<{}>", field);
+    let close = format!("</{}>", field);
+    config.find(&open)
+        .map(|start| start + open.len())
+        .and_then(|start| config[start..].find(&close).map(|end| &config[start..start+end]))
+        .and_then(|size| parse_size(size.trim()))
+        .ok_or(format!("no {} in {}", field, filename))
+}
+
+// "0x90000000", "1048576", "93M"
+pub fn parse_size(size: &str) -> Option<usize> {
+    if size.starts_with("0x") || size.starts_with("0X") {
+        return usize::from_str_radix(&size[2..], 16).ok();
+    }
+    let (digits, unit) = match size.chars().last()? {
+        'K' | 'k' => (&size[..size.len()-1], 1024),
+        'M' | 'm' => (&size[..size.len()-1], 1024 * 1024),
+        'G' | 'g' => (&size[..size.len()-1], 1024 * 1024 * 1024),
+        _ => (size, 1),
+    };
+    digits.parse::<usize>().ok().map(|n| n * unit)
+}
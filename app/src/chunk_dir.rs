@@ -0,0 +1,313 @@
+use serde::*;
+use std::fs::{ self, File };
+use std::path::{ Path, PathBuf };
+use std::process;
+use std::time::{ SystemTime, UNIX_EPOCH };
+use memmap::Mmap;
+use hex;
+use sgx_ucrypto::rsgx_sha256_slice;
+use pct_core::wire::{ read_frame, FRAME_CHUNK };
+use encoding::Encoding;
+use central_store::{ CentralStore, StoreSnapshot };
+use chunk_pipeline::ChunkFrame;
+
+pub const CHUNK_MANIFEST: &'static str = "CHUNK_MANIFEST";
+// 2: store
+pub const CHUNK_MANIFEST_VERSION: u32 = 2;
+
+/*
+Type ChunkDir
+    Chunks built once by bin/app build-chunks (or sealed by bin/app seal-chunks)
+    and reused by every run over the same central data, one directory of
+        CHUNK_MANIFEST          what the chunks have been built with, replaced atomically (write + rename)
+        <index>-<build>.chunk   one chunk frame (pct_core/src/wire.rs)
+    A rebuild writes its chunks under a new build id next to the ones of the previous
+    build and only then replaces the manifest, so a run that has mapped the previous
+    chunks keeps reading them unchanged. The files no manifest names any more are
+    removed after that; their mappings stay valid until they are dropped.
+    The manifest names the encoding, dictionary backends and codec the enclave has
+    to support, and the records, bytes and SHA-256 of every chunk file. ChunkFiles
+    maps the files in index order and checks each one against the manifest, so a
+    truncated or modified chunk fails the run instead of tracing wrong data.
+    Chunks built from a central store name its snapshot, and ChunkFiles refuses
+    them once the store has revoked or expired records since.
+*/
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct ChunkManifest {
+    pub version: u32,
+    // Encoding::id, None without records
+    pub encoding: Option<u8>,
+    // PCT_DICTIONARY names, used round robin over the chunks
+    pub dictionaries: Vec<String>,
+    pub codec: u8,
+    // bodies sealed with the chunk key of the health authority
+    pub sealed: bool,
+    // PCT_THREADS the chunks have been sized for, see ChunkBudget::shared_by
+    pub threads: usize,
+    pub records: usize,
+    // central store the records have been read from, None for a central data file
+    pub store: Option<StoreSnapshot>,
+    pub chunks: Vec<ChunkFileEntry>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct ChunkFileEntry {
+    pub file: String,
+    pub records: u32,
+    pub bytes: usize,
+    // hex
+    pub sha256: String,
+}
+
+impl ChunkManifest {
+    pub fn encoding(&self) -> Option<Encoding> {
+        self.encoding.and_then(Encoding::from_id)
+    }
+}
+
+fn sha256_hex(bytes: &[u8]) -> Result<String, String> {
+    rsgx_sha256_slice(bytes).map(|hash| hex::encode(&hash[..])).map_err(|e| e.as_str().to_string())
+}
+
+pub struct ChunkDirWriter {
+    dir: PathBuf,
+    // tells the chunk files of this build from those of the previous one
+    build: String,
+    manifest: ChunkManifest,
+}
+
+impl ChunkDirWriter {
+    // the chunks of a previous build stay readable until finish replaces its manifest
+    pub fn create(
+        dirname: &str,
+        dictionaries: Vec<String>,
+        codec: u8,
+        sealed: bool,
+        threads: usize,
+        store: Option<StoreSnapshot>,
+    ) -> Result<Self, String> {
+        fs::create_dir_all(dirname).map_err(|e| format!("{}: {}", dirname, e))?;
+        let now = SystemTime::now().duration_since(UNIX_EPOCH).map_err(|e| e.to_string())?;
+        Ok(ChunkDirWriter {
+            dir: PathBuf::from(dirname),
+            build: format!("{:x}{:09}-{}", now.as_secs(), now.subsec_nanos(), process::id()),
+            manifest: ChunkManifest {
+                version: CHUNK_MANIFEST_VERSION,
+                encoding: None,
+                dictionaries: dictionaries,
+                codec: codec,
+                sealed: sealed,
+                threads: threads,
+                records: 0,
+                store: store,
+                chunks: Vec::new(),
+            },
+        })
+    }
+
+    // the next chunk frame, in the order it is traced
+    pub fn push(&mut self, frame: &[u8]) -> Result<(), String> {
+        let (header, _) = read_frame(frame, FRAME_CHUNK).map_err(|e| format!("invalid chunk frame: {}", e.as_str()))?;
+        let encoding = header.encoding.id();
+        match self.manifest.encoding {
+            Some(current) if current != encoding => {
+                return Err(format!("chunk {} has encoding {}, the previous ones {}", self.manifest.chunks.len(), encoding, current));
+            },
+            _ => { self.manifest.encoding = Some(encoding); }
+        }
+        let file = format!("{:06}-{}.chunk", self.manifest.chunks.len(), self.build);
+        let filename = self.dir.join(&file);
+        fs::write(&filename, frame).map_err(|e| format!("{}: {}", filename.display(), e))?;
+        self.manifest.records += header.count as usize;
+        self.manifest.chunks.push(ChunkFileEntry {
+            file: file,
+            records: header.count,
+            bytes: frame.len(),
+            sha256: sha256_hex(frame)?,
+        });
+        Ok(())
+    }
+
+    // replaces the manifest, which switches ChunkFiles over to this build,
+    // then removes the chunk files of the previous one
+    pub fn finish(self) -> Result<ChunkManifest, String> {
+        let bytes = serde_json::to_vec_pretty(&self.manifest).map_err(|e| e.to_string())?;
+        let filename = self.dir.join(CHUNK_MANIFEST);
+        let tmp_filename = self.dir.join(format!("{}.{}.tmp", CHUNK_MANIFEST, self.build));
+        fs::write(&tmp_filename, &bytes).map_err(|e| format!("{}: {}", tmp_filename.display(), e))?;
+        fs::rename(&tmp_filename, &filename).map_err(|e| format!("{}: {}", filename.display(), e))?;
+        self.remove_stale_chunks()?;
+        Ok(self.manifest)
+    }
+
+    fn remove_stale_chunks(&self) -> Result<(), String> {
+        let entries = fs::read_dir(&self.dir).map_err(|e| format!("{}: {}", self.dir.display(), e))?;
+        for entry in entries {
+            let path = entry.map_err(|e| format!("{}: {}", self.dir.display(), e))?.path();
+            let file = match path.file_name().and_then(|name| name.to_str()) {
+                Some(file) if file.ends_with(".chunk") => file.to_string(),
+                _ => { continue; }
+            };
+            if !self.manifest.chunks.iter().any(|chunk| chunk.file == file) {
+                fs::remove_file(&path).map_err(|e| format!("{}: {}", path.display(), e))?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/*
+Type ChunkFiles
+    the chunks of a chunk directory, memory mapped one by one in index order as the
+    iterator is advanced and checked against the manifest
+*/
+pub struct ChunkFiles {
+    dir: PathBuf,
+    manifest: ChunkManifest,
+    next: usize,
+}
+
+impl ChunkFiles {
+    pub fn exists(dirname: &str) -> bool {
+        Path::new(dirname).join(CHUNK_MANIFEST).is_file()
+    }
+
+    pub fn open(dirname: &str) -> Result<Self, String> {
+        let filename = Path::new(dirname).join(CHUNK_MANIFEST);
+        let bytes = fs::read(&filename).map_err(|e| format!("{}: {}", filename.display(), e))?;
+        let manifest: ChunkManifest = serde_json::from_slice(&bytes)
+            .map_err(|e| format!("{}: {}", filename.display(), e))?;
+        if manifest.version != CHUNK_MANIFEST_VERSION {
+            return Err(format!("{}: version {}, the app reads {}", filename.display(), manifest.version, CHUNK_MANIFEST_VERSION));
+        }
+        if let Some(ref snapshot) = manifest.store {
+            CentralStore::check_snapshot(snapshot).map_err(|e| format!("{}: {}, rebuild the chunks", filename.display(), e))?;
+        }
+        Ok(ChunkFiles { dir: PathBuf::from(dirname), manifest: manifest, next: 0 })
+    }
+
+    pub fn manifest(&self) -> &ChunkManifest {
+        &self.manifest
+    }
+
+    fn map_chunk(&self, entry: &ChunkFileEntry) -> Result<ChunkFrame, String> {
+        let filename = self.dir.join(&entry.file);
+        let file = File::open(&filename).map_err(|e| format!("{}: {}", filename.display(), e))?;
+        // the files of a build are never rewritten, a rebuild writes new ones, see ChunkDir
+        let frame = unsafe { Mmap::map(&file) }.map_err(|e| format!("{}: {}", filename.display(), e))?;
+        if frame.len() != entry.bytes {
+            return Err(format!("{}: {} bytes, the manifest says {}", filename.display(), frame.len(), entry.bytes));
+        }
+        if sha256_hex(&frame)? != entry.sha256 {
+            return Err(format!("{}: checksum mismatch", filename.display()));
+        }
+        let (header, _) = read_frame(&frame, FRAME_CHUNK).map_err(|e| format!("{}: {}", filename.display(), e.as_str()))?;
+        if header.count != entry.records || Some(header.encoding.id()) != self.manifest.encoding {
+            return Err(format!("{}: the chunk frame does not match the manifest", filename.display()));
+        }
+        Ok(ChunkFrame::Mapped(frame))
+    }
+}
+
+impl Iterator for ChunkFiles {
+    type Item = Result<ChunkFrame, String>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let entry = self.manifest.chunks.get(self.next)?.clone();
+        self.next += 1;
+        Some(self.map_chunk(&entry))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs::OpenOptions;
+    use std::io::{ Seek, SeekFrom, Write };
+    use pct_core::wire::{ write_frame, FRAME_HEADER_U8 };
+    use pct_core::chunk_codec::CODEC_NONE;
+    use encoding::DatasetEncoding;
+    use central_data::EncodedData;
+    use util::test_dir;
+
+    fn frames(seed: u8) -> Vec<Vec<u8>> {
+        (0..3_u8).map(|i| write_frame(FRAME_CHUNK, Encoding::Gp10, i as u32 + 1, &[seed, i, 1, 2, 3])).collect()
+    }
+
+    fn build(dirname: &str, frames: &[Vec<u8>]) -> ChunkManifest {
+        let mut writer = ChunkDirWriter::create(dirname, vec!["fsa".to_string()], CODEC_NONE, false, 1, None).unwrap();
+        for frame in frames.iter() {
+            writer.push(frame).unwrap();
+        }
+        writer.finish().unwrap()
+    }
+
+    fn read(dirname: &str) -> Result<Vec<Vec<u8>>, String> {
+        ChunkFiles::open(dirname)?.map(|frame| frame.map(|frame| frame.to_vec())).collect()
+    }
+
+    #[test]
+    fn chunk_files_are_checked_against_the_manifest() {
+        let dir = test_dir("chunk-dir");
+        let dirname = dir.to_str().unwrap();
+        assert!(!ChunkFiles::exists(dirname));
+        let manifest = build(dirname, &frames(0));
+        assert_eq!(manifest.records, 1 + 2 + 3);
+        assert_eq!(manifest.encoding(), Some(Encoding::Gp10));
+        assert_eq!(read(dirname).unwrap(), frames(0));
+
+        // same length, one byte flipped
+        let filename = dir.join(&manifest.chunks[1].file);
+        let mut file = OpenOptions::new().write(true).open(&filename).unwrap();
+        file.seek(SeekFrom::Start(FRAME_HEADER_U8 as u64)).unwrap();
+        file.write_all(&[0xff]).unwrap();
+        drop(file);
+        let error = read(dirname).unwrap_err();
+        assert!(error.contains("checksum mismatch"), "{}", error);
+
+        // truncated
+        let filename = dir.join(&manifest.chunks[2].file);
+        fs::write(&filename, &frames(0)[2][..FRAME_HEADER_U8]).unwrap();
+        let mut chunk_files = ChunkFiles::open(dirname).unwrap();
+        chunk_files.next().unwrap().unwrap();
+        assert!(chunk_files.next().unwrap().is_err());
+        assert!(chunk_files.next().unwrap().is_err());
+        assert!(chunk_files.next().is_none());
+    }
+
+    #[test]
+    fn a_rebuild_leaves_mapped_chunks_alone() {
+        let dir = test_dir("chunk-rebuild");
+        let dirname = dir.to_str().unwrap();
+        let old = build(dirname, &frames(0));
+        let mut chunk_files = ChunkFiles::open(dirname).unwrap();
+        let mapped = chunk_files.next().unwrap().unwrap();
+        assert_eq!(&mapped[..], &frames(0)[0][..]);
+
+        let new = build(dirname, &frames(1));
+        assert!(old.chunks.iter().all(|chunk| new.chunks.iter().all(|other| other.file != chunk.file)));
+        assert!(old.chunks.iter().all(|chunk| !dir.join(&chunk.file).exists()));
+        assert_eq!(&mapped[..], &frames(0)[0][..]);
+        assert_eq!(read(dirname).unwrap(), frames(1));
+        let files = fs::read_dir(&dir).unwrap().count();
+        assert_eq!(files, new.chunks.len() + 1);
+    }
+
+    #[test]
+    fn chunks_of_a_withdrawn_store_snapshot_are_refused() {
+        let store_dir = test_dir("chunk-store");
+        let mut store = CentralStore::create(store_dir.to_str().unwrap(), 0, 600).unwrap();
+        let values = vec![Encoding::Gp10.decode("xn76urx6rb0010")];
+        let batch_id = store.append(&EncodedData::from_values(Some(Encoding::Gp10), values), "batch.json", 0).unwrap().unwrap();
+
+        let dir = test_dir("chunk-snapshot");
+        let dirname = dir.to_str().unwrap();
+        let mut writer = ChunkDirWriter::create(dirname, vec!["fsa".to_string()], CODEC_NONE, false, 1, Some(store.snapshot().unwrap())).unwrap();
+        writer.push(&frames(0)[0]).unwrap();
+        writer.finish().unwrap();
+        ChunkFiles::open(dirname).unwrap();
+        store.revoke(batch_id, 1).unwrap();
+        let error = ChunkFiles::open(dirname).err().unwrap();
+        assert!(error.contains("rebuild the chunks"), "{}", error);
+    }
+}
@@ -0,0 +1,94 @@
+use std::thread;
+use std::ops::Deref;
+use std::sync::Mutex;
+use std::sync::mpsc::{ self, Receiver };
+use memmap::Mmap;
+
+// one chunk frame, built in memory (ChunkProducer) or mapped from a chunk file (ChunkFiles)
+pub enum ChunkFrame {
+    Built(Vec<u8>),
+    Mapped(Mmap),
+}
+
+impl ChunkFrame {
+    pub fn into_vec(self) -> Vec<u8> {
+        match self {
+            ChunkFrame::Built(frame) => frame,
+            ChunkFrame::Mapped(frame) => frame.to_vec(),
+        }
+    }
+}
+
+impl Deref for ChunkFrame {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        match self {
+            ChunkFrame::Built(frame) => frame,
+            ChunkFrame::Mapped(frame) => frame,
+        }
+    }
+}
+
+impl From<Vec<u8>> for ChunkFrame {
+    fn from(frame: Vec<u8>) -> Self {
+        ChunkFrame::Built(frame)
+    }
+}
+
+/*
+Type ChunkPipeline
+    Chunk frames built (ChunkProducer) or mapped (ChunkFiles) lazily on a producer
+    thread and handed to the tracing threads through a queue of depth frames. The
+    host holds the frames waiting in the queue and the ones being traced instead of
+    the whole central data, and the first chunks are ready while the queries are
+    still being uploaded.
+*/
+pub struct ChunkPipeline {
+    receiver: Mutex<Option<Receiver<(usize, ChunkFrame)>>>,
+    // number of chunks produced
+    producer: Mutex<Option<thread::JoinHandle<Result<usize, String>>>>,
+}
+
+impl ChunkPipeline {
+    pub fn spawn<I, C>(chunks: I, depth: usize) -> Self
+        where I: Iterator<Item = Result<C, String>> + Send + 'static,
+              C: Into<ChunkFrame>
+    {
+        let (sender, receiver) = mpsc::sync_channel::<(usize, ChunkFrame)>(depth);
+        let producer = thread::spawn(move || {
+            let mut index = 0;
+            for chunk in chunks {
+                // the consumer is gone after finish
+                if sender.send((index, chunk?.into())).is_err() {
+                    break;
+                }
+                index += 1;
+            }
+            Ok(index)
+        });
+        ChunkPipeline {
+            receiver: Mutex::new(Some(receiver)),
+            producer: Mutex::new(Some(producer)),
+        }
+    }
+
+    // the next chunk and its index, None once every chunk has been taken,
+    // the producer failed or the pipeline is finished
+    pub fn next(&self) -> Option<(usize, ChunkFrame)> {
+        let receiver = self.receiver.lock().unwrap();
+        receiver.as_ref().and_then(|receiver| receiver.recv().ok())
+    }
+
+    /*
+        drops the chunks not taken yet, which stops the producer, and waits for it.
+        The number of chunks it produced, or why it stopped before the last one
+    */
+    pub fn finish(&self) -> Result<usize, String> {
+        self.receiver.lock().unwrap().take();
+        match self.producer.lock().unwrap().take() {
+            Some(producer) => producer.join().unwrap_or_else(|_| Err("the chunk producer panicked".to_string())),
+            None => Err("the chunk pipeline is already finished".to_string()),
+        }
+    }
+}
@@ -0,0 +1,52 @@
+use serde::*;
+use std::fs::File;
+use std::io::BufReader;
+use pct_core;
+
+/*
+Type ContactRules
+    Rules of "risky contact" set by the health authority, uploaded to the enclave
+    with set_contact_rules in the byte layout of pct_core's ContactRules.
+*/
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ContactRules {
+    // seconds
+    pub min_exposure_duration: u32,
+    // time slots
+    pub time_tolerance_before: u32,
+    // time slots
+    pub time_tolerance_after: u32,
+    // 0 = same cell only, 1 = also the 8 neighbouring cells
+    pub spatial_tolerance: u8,
+    pub min_hits: u32,
+}
+
+impl Default for ContactRules {
+    fn default() -> Self {
+        ContactRules {
+            min_exposure_duration: 0,
+            time_tolerance_before: 0,
+            time_tolerance_after: 0,
+            spatial_tolerance: 0,
+            min_hits: 1,
+        }
+    }
+}
+
+impl ContactRules {
+    pub fn read_raw_from_file(filename: &str) -> Self {
+        let file = File::open(filename).unwrap();
+        let reader = BufReader::new(file);
+        serde_json::from_reader(reader).unwrap()
+    }
+
+    pub fn to_be_bytes(&self) -> Vec<u8> {
+        pct_core::contact_rules::ContactRules {
+            min_exposure_duration: self.min_exposure_duration,
+            time_tolerance_before: self.time_tolerance_before,
+            time_tolerance_after: self.time_tolerance_after,
+            spatial_tolerance: self.spatial_tolerance,
+            min_hits: self.min_hits,
+        }.to_be_bytes()
+    }
+}
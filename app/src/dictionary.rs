@@ -0,0 +1,118 @@
+use std::env;
+use std::collections::HashSet;
+use fst::Set;
+use bincode;
+use encoding::EncodedValue;
+use pct_core::chunk_codec::{ self, CODEC_NONE, CODEC_LZ };
+use pct_core::encoded_dictionary_buffer::{ LookupMode, DICTIONARY_FST, DICTIONARY_HASHTABLE };
+
+/*
+Trait ChunkBuilder
+    Write side of one chunk of the central data, read in the enclave by the
+    EncodedDictionary with the same id. A new backend implements both and
+    registers its name in chunk_builder_from_name. Chunks are built on the
+    producer thread of the ChunkPipeline, hence Send.
+*/
+pub trait ChunkBuilder: Send {
+    fn backend_id(&self) -> u8;
+
+    fn name(&self) -> &'static str;
+
+    // heap bytes the enclave builds per byte of the serialized dictionary, see ChunkBudget
+    fn expansion(&self) -> usize;
+
+    // sorted_values are sorted and unique
+    fn serialize(&self, sorted_values: &[EncodedValue]) -> Vec<u8>;
+
+    // backend id (1 byte) || codec (1 byte) || dictionary (serialize) compressed by codec
+    fn build_chunk(&self, dictionary: &[u8], codec: u8) -> Vec<u8> {
+        let mut chunk: Vec<u8> = vec![self.backend_id(), codec];
+        chunk.extend(chunk_codec::compress(codec, dictionary).expect("unknown chunk codec!"));
+        chunk
+    }
+}
+
+#[derive(Clone, Default, Debug)]
+pub struct FstChunkBuilder;
+
+impl ChunkBuilder for FstChunkBuilder {
+    fn backend_id(&self) -> u8 { DICTIONARY_FST }
+
+    fn name(&self) -> &'static str { "fsa" }
+
+    // searched in place
+    fn expansion(&self) -> usize { 0 }
+
+    fn serialize(&self, sorted_values: &[EncodedValue]) -> Vec<u8> {
+        Set::from_iter(sorted_values.iter()).unwrap().as_ref().as_bytes().to_vec()
+    }
+}
+
+#[derive(Clone, Default, Debug)]
+pub struct HashTableChunkBuilder;
+
+impl ChunkBuilder for HashTableChunkBuilder {
+    fn backend_id(&self) -> u8 { DICTIONARY_HASHTABLE }
+
+    fn name(&self) -> &'static str { "hashtable" }
+
+    // a Vec and a hash slot per key next to the key itself
+    fn expansion(&self) -> usize { 3 }
+
+    // raw bytes, the enclave reads every encoding into the same HashSet<Vec<u8>>
+    fn serialize(&self, sorted_values: &[EncodedValue]) -> Vec<u8> {
+        let hashset: HashSet<Vec<u8>> = sorted_values.iter().map(|value| value.as_ref().to_vec()).collect();
+        bincode::serialize(&hashset).unwrap()
+    }
+}
+
+pub fn chunk_builder_from_name(name: &str) -> Option<Box<dyn ChunkBuilder>> {
+    match name {
+        "fsa" => Some(Box::new(FstChunkBuilder)),
+        "hashtable" => Some(Box::new(HashTableChunkBuilder)),
+        _ => None,
+    }
+}
+
+/*
+    PCT_DICTIONARY selects the backend of the chunks (default "fsa"),
+    a comma separated list such as "fsa,hashtable" is used round robin over the chunks
+*/
+pub fn chunk_builders_from_env() -> Option<Vec<Box<dyn ChunkBuilder>>> {
+    let names = env::var("PCT_DICTIONARY").unwrap_or_else(|_| "fsa".to_string());
+    names.split(',').map(|name| chunk_builder_from_name(name.trim())).collect()
+}
+
+/*
+    PCT_LOOKUP selects the lookup mode, "direct" (default), "oblivious"
+    (memory accesses independent of the query values, much slower) or "indexed"
+    (responses built from the hits only, which leaks them; native backend only,
+    the sgx backend refuses it before any ECALL)
+*/
+pub fn lookup_mode_from_env() -> Option<LookupMode> {
+    let name = env::var("PCT_LOOKUP").unwrap_or_else(|_| "direct".to_string());
+    match name.as_ref() {
+        "direct" => Some(LookupMode::Direct),
+        "oblivious" => Some(LookupMode::Oblivious),
+        "indexed" => Some(LookupMode::Indexed),
+        _ => None,
+    }
+}
+
+/*
+    PCT_CHUNK_CODEC selects how the chunks are compressed, "none" (default) or "lz",
+    which pays off for the hashtable backend whose chunks are much larger than the FST ones
+*/
+pub fn chunk_codec_from_env() -> Option<u8> {
+    let name = env::var("PCT_CHUNK_CODEC").unwrap_or_else(|_| "none".to_string());
+    match name.as_ref() {
+        "none" => Some(CODEC_NONE),
+        "lz" => Some(CODEC_LZ),
+        _ => None,
+    }
+}
+
+// "fsa", "hashtable", "fsa+hashtable", ... for the result file
+pub fn chunk_builders_name(builders: &[Box<dyn ChunkBuilder>]) -> String {
+    builders.iter().map(|builder| builder.name()).collect::<Vec<&str>>().join("+")
+}
@@ -4,8 +4,74 @@ use sgx_urts::SgxEnclave;
 static ENCLAVE_FILE: &'static str = "bin/enclave.signed.so";
 
 extern {
+    pub fn start_key_exchange(
+        eid: sgx_enclave_id_t,
+        retval: *mut sgx_status_t,
+        query_id: u64,
+        client_public_key: *const sgx_ec256_public_t,
+        target_info: *const sgx_target_info_t,
+        enclave_public_key: *mut sgx_ec256_public_t,
+        report: *mut sgx_report_t,
+    ) -> sgx_status_t;
+
+    pub fn finish_key_exchange(
+        eid: sgx_enclave_id_t,
+        retval: *mut sgx_status_t,
+        query_id: u64,
+        key_confirmation: *const sgx_cmac_128bit_tag_t,
+    ) -> sgx_status_t;
+
+    pub fn get_capabilities(
+        eid: sgx_enclave_id_t,
+        retval: *mut sgx_status_t,
+        capabilities: *mut u8,
+        capabilities_size: usize,
+    ) -> sgx_status_t;
+
+    pub fn open_session(
+        eid: sgx_enclave_id_t,
+        retval: *mut sgx_status_t,
+        session_id: *mut u64,
+    ) -> sgx_status_t;
+
+    pub fn close_session(
+        eid: sgx_enclave_id_t,
+        retval: *mut sgx_status_t,
+        session_id: u64,
+    ) -> sgx_status_t;
+
+    pub fn set_contact_rules(
+        eid: sgx_enclave_id_t,
+        retval: *mut sgx_status_t,
+        session_id: u64,
+        rules_u8: *const u8,
+        rules_u8_size: usize,
+    ) -> sgx_status_t;
+
+    pub fn set_lookup_mode(
+        eid: sgx_enclave_id_t,
+        retval: *mut sgx_status_t,
+        session_id: u64,
+        mode_id: u8,
+    ) -> sgx_status_t;
+
+    pub fn finish_authority_key_exchange(
+        eid: sgx_enclave_id_t,
+        retval: *mut sgx_status_t,
+        key_confirmation: *const sgx_cmac_128bit_tag_t,
+        signature: *const sgx_ec256_signature_t,
+    ) -> sgx_status_t;
+
+    pub fn provision_chunk_key(
+        eid: sgx_enclave_id_t,
+        retval: *mut sgx_status_t,
+        sealed_key: *const u8,
+        sealed_key_size: usize,
+    ) -> sgx_status_t;
+
     pub fn upload_encoded_query_data(
         eid: sgx_enclave_id_t, retval: *mut sgx_status_t,
+        session_id: u64,
         total_query_data: * const u8, total_size: usize,
         client_size: usize, query_id_list: * const u64
     ) -> sgx_status_t;
@@ -14,6 +80,7 @@ extern {
     pub fn private_encode_contact_trace(
         eid: sgx_enclave_id_t,
         retval: *mut sgx_status_t,
+        session_id: u64,
         encoded_value_u8: * const u8,
         encoded_value_u8_size: usize,
     ) -> sgx_status_t;
@@ -21,6 +88,7 @@ extern {
     pub fn get_encoded_result(
         eid: sgx_enclave_id_t,
         retval: *mut sgx_status_t,
+        session_id: u64,
         response: *mut u8,
         response_size: usize,
     ) -> sgx_status_t;
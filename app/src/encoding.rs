@@ -0,0 +1,71 @@
+use util::{ base8decode, base8encode };
+use pct_core::gp10;
+
+pub use pct_core::encoding::Encoding;
+pub use pct_core::primitive::EncodedValue;
+pub use pct_core::constant::MAX_ENCODEDVALUE_SIZE;
+
+/*
+Trait DatasetEncoding
+    What the host does with the strings of a dataset on top of the Encoding of
+    pct_core, which only knows the decoded values.
+*/
+pub trait DatasetEncoding: Sized {
+    // encoding shared by every string, panics on a mixed or unknown dataset
+    fn detect_all<'a, I: Iterator<Item = &'a String>>(encoded_strings: I, filename: &str) -> Option<Self>;
+
+    // time slot of a gp10 value (its last 4 digits), None for TrajectoryHash,
+    // which interleaves the time with the space bits
+    fn time_slot(&self, value: &[u8]) -> Option<u32>;
+
+    fn decode(&self, encoded_string: &str) -> EncodedValue;
+
+    // the string a value has been decoded from
+    fn encode(&self, value: &EncodedValue) -> String;
+}
+
+impl DatasetEncoding for Encoding {
+    fn detect_all<'a, I: Iterator<Item = &'a String>>(encoded_strings: I, filename: &str) -> Option<Self> {
+        let mut encoding: Option<Encoding> = None;
+        for encoded_string in encoded_strings {
+            let detected = match Encoding::detect(encoded_string) {
+                Some(detected) => detected,
+                None => {
+                    println!("[Error] Unknown encoding of {} in {}!", encoded_string, filename);
+                    panic!()
+                }
+            };
+            match encoding {
+                Some(current) if current != detected => {
+                    println!("[Error] Mixed encodings {} and {} in {}!", current.name(), detected.name(), filename);
+                    panic!()
+                },
+                _ => { encoding = Some(detected); }
+            }
+        }
+        encoding
+    }
+
+    fn time_slot(&self, value: &[u8]) -> Option<u32> {
+        match *self {
+            Encoding::Gp10 => EncodedValue::from_slice(value).and_then(|value| gp10::time_slot(&value)),
+            _ => None,
+        }
+    }
+
+    fn decode(&self, encoded_string: &str) -> EncodedValue {
+        let value = match *self {
+            Encoding::Gp10 => EncodedValue::from_slice(encoded_string.as_bytes()),
+            // ascii-code
+            _ => EncodedValue::from_slice(base8decode(encoded_string.to_string()).as_slice()),
+        };
+        value.expect("detect only accepts strings of the known encodings")
+    }
+
+    fn encode(&self, value: &EncodedValue) -> String {
+        match *self {
+            Encoding::Gp10 => String::from_utf8_lossy(value.as_ref()).into_owned(),
+            _ => base8encode(value.as_ref(), self.string_size()),
+        }
+    }
+}
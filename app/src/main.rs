@@ -17,221 +17,436 @@
 
 extern crate sgx_types;
 extern crate sgx_urts;
+extern crate sgx_ucrypto;
 extern crate serde;
 extern crate serde_json;
 extern crate fst;
 extern crate bincode;
 extern crate hex;
+extern crate pct_core;
+extern crate memmap;
 
 use std::env;
+use std::cmp;
 use std::collections::HashSet;
+use std::sync::Arc;
 use sgx_types::*;
+use pct_core::primitive::QueryId;
+use pct_core::encoded_dictionary_buffer::LookupMode;
+use pct_core::chunk_codec;
 mod query_data;
 use query_data::*;
 // ecallsはnamedで呼び出す
 mod ecalls;
-use ecalls::{ 
-    upload_encoded_query_data, 
-    init_enclave,
-    private_encode_contact_trace, get_encoded_result
-};
 mod central_data;
 use central_data::*;
 mod util;
 use util::*;
-pub const QUERY_ID_SIZE_U8: usize = 8;
-pub const QUERY_RESULT_U8: usize = 1;
-pub const RESPONSE_DATA_SIZE_U8: usize = QUERY_ID_SIZE_U8 + QUERY_RESULT_U8;
+mod encoding;
+use encoding::{ Encoding, EncodedValue };
+mod dictionary;
+use dictionary::{
+    chunk_builders_from_env, chunk_builder_from_name, chunk_builders_name, lookup_mode_from_env, chunk_codec_from_env
+};
+mod secure_channel;
+mod query_result;
+mod contact_rules;
+use contact_rules::ContactRules;
+use query_result::{ RiskScore, slot_clock_from_env };
+mod backend;
+use backend::{ backend_from_env, check_capabilities, threads_from_env, usable_threads, INDEXED_IS_NATIVE_ONLY };
+mod authority;
+use authority::{ ChunkKey, chunk_key_from_env, signing_key_from_env, read_or_create_chunk_key, create_signing_key };
+mod chunk_budget;
+use chunk_budget::ChunkBudget;
+mod chunk_pipeline;
+use chunk_pipeline::ChunkPipeline;
+mod chunk_dir;
+use chunk_dir::{ ChunkFiles, ChunkDirWriter };
+mod central_store;
+use central_store::{ CentralStore, StoreSnapshot };
+mod record_file;
+use record_file::is_record_file;
 
 /*
-    args[0] = threashold of each chunk block size
+    args[0] = threashold of each chunk block size, or auto
     args[1] = query data file path
-    args[2] = central data file path
+    args[2] = central data file path, a central store (bin/app central-store)
+              or a directory of chunks built by bin/app build-chunks or sealed by bin/app seal-chunks
+    args[3] = contact rules file path (optional)
+    after the command name for show-size and non-private, skip = 2
 */
-fn _get_options() -> Vec<String> {
-    let args: Vec<String> = env::args().skip(1).collect();
-    if args.len() != 3 {
-        println!(" ERROR bin/app needs 3 arguments!");
-        println!("    args[0] = threashold of each chunk block size");
+fn _get_options(skip: usize) -> Vec<String> {
+    let args: Vec<String> = env::args().skip(skip).collect();
+    if args.len() != 3 && args.len() != 4 {
+        println!(" ERROR bin/app needs 3 or 4 arguments!");
+        println!("    args[0] = threashold of each chunk block size, or auto");
         println!("    args[1] = query data file path");
         println!("    args[2] = central data file path");
+        println!("    args[3] = contact rules file path (optional)");
         std::process::exit(-1);
     }
     args
 }
 
+// values per chunk, auto leaves it to the ChunkBudget of the enclave
+fn _get_threashould(arg: &str) -> usize {
+    if arg == "auto" {
+        return usize::MAX;
+    }
+    match arg.parse() {
+        Ok(threashould) if threashould > 0 => threashould,
+        _ => {
+            println!(" ERROR threashold has to be a positive number or auto!");
+            std::process::exit(-1);
+        }
+    }
+}
+
+// a central record file (bin/app convert-records) or the JSON central data file
+fn read_central_file(c_filename: &str) -> EncodedData {
+    if !is_record_file(c_filename) {
+        return EncodedData::read_raw_from_file(c_filename);
+    }
+    match EncodedData::read_records(c_filename) {
+        Ok(encoded_data) => encoded_data,
+        Err(x) => {
+            println!(" ERROR {}", x);
+            std::process::exit(-1);
+        }
+    }
+}
+
+// a query record file (bin/app convert-records) or the JSON query data file
+fn read_query_data(q_filename: &str) -> EncodedQueryData {
+    if !is_record_file(q_filename) {
+        return EncodedQueryData::read_raw_from_file(q_filename);
+    }
+    match EncodedQueryData::read_records(q_filename) {
+        Ok(query_data) => query_data,
+        Err(x) => {
+            println!(" ERROR {}", x);
+            std::process::exit(-1);
+        }
+    }
+}
+
+// current snapshot of a central store, or the central data file
+fn read_central_data(c_filename: &str) -> EncodedData {
+    read_central_snapshot(c_filename).0
+}
+
+// with the snapshot it has been read from if c_filename is a central store
+fn read_central_snapshot(c_filename: &str) -> (EncodedData, Option<StoreSnapshot>) {
+    if !CentralStore::exists(c_filename) {
+        return (read_central_file(c_filename), None);
+    }
+    let read = CentralStore::open(c_filename).and_then(|store| {
+        let snapshot = store.snapshot()?;
+        Ok((store.encoded_data()?, Some(snapshot)))
+    });
+    match read {
+        Ok(read) => read,
+        Err(x) => {
+            println!(" ERROR {}", x);
+            std::process::exit(-1);
+        }
+    }
+}
+
+// query and central data have to use the same encoding, an empty side follows the other
+fn dataset_encoding(query_encoding: Option<Encoding>, central_encoding: Option<Encoding>) -> Result<Encoding, String> {
+    match (query_encoding, central_encoding) {
+        (Some(q), Some(c)) if q != c => Err(format!("query data is {} but central data is {}!", q.name(), c.name())),
+        (q, c) => q.or(c).ok_or("neither query data nor central data names an encoding!".to_string()),
+    }
+}
+
 fn private_set_intersection() {
-    let args = _get_options();
+    let args = _get_options(1);
     /* parameters */
-    let threashould: usize = args[0].parse().unwrap();
+    let threashould: usize = _get_threashould(&args[0]);
     let q_filename = &args[1];
     let c_filename = &args[2];
 
     let mut clocker = Clocker::new();
 
-    /* read central data */
-    clocker.set_and_start("Read Central Data");
-    let external_data = EncodedData::read_raw_from_file(c_filename);
-    clocker.stop("Read Central Data");
-    let central_data_size = external_data.size();
-
-    /* preprocess central data */
-    clocker.set_and_start("Distribute central data");
-    #[cfg(feature = "hashtable")]
-    let mut R: CentralHashSet = CentralHashSet::from_EncodedData(external_data, threashould);
-    #[cfg(feature = "fsa")]
-    let mut R: CentralFST = CentralFST::from_EncodedData(external_data, threashould);
-    clocker.stop("Distribute central data");
-
-    /* initialize enclave */
+    let builders = match chunk_builders_from_env() {
+        Some(builders) => builders,
+        None => {
+            println!("[UNTRUSTED] Unknown dictionary, check PCT_DICTIONARY");
+            return;
+        }
+    };
+    let codec = match chunk_codec_from_env() {
+        Some(codec) => codec,
+        None => {
+            println!("[UNTRUSTED] Unknown chunk codec, check PCT_CHUNK_CODEC");
+            return;
+        }
+    };
+    let lookup_mode = match lookup_mode_from_env() {
+        Some(lookup_mode) => lookup_mode,
+        None => {
+            println!("[UNTRUSTED] Unknown lookup mode, check PCT_LOOKUP");
+            return;
+        }
+    };
+    let threads = match threads_from_env() {
+        Some(threads) => threads,
+        None => {
+            println!("[UNTRUSTED] Invalid thread count, check PCT_THREADS");
+            return;
+        }
+    };
+    /* initialize enclave (or the native backend, see PCT_BACKEND) */
     println!("init_enclave...");
     clocker.set_and_start("ECALL init_enclave");
-    let enclave = match init_enclave() {
-        Ok(r) => {
-            // println!(" Init Enclave Successful {}!", r.geteid());
-            r
-        },
+    let mut backend = match backend_from_env() {
+        Ok(backend) => backend,
         Err(x) => {
-            println!(" Init Enclave Failed {}!", x.as_str());
+            println!(" {}", x);
             return;
         },
     };
     clocker.stop("ECALL init_enclave");
+    let capabilities = match backend.capabilities() {
+        Ok(capabilities) => capabilities,
+        Err(x) => {
+            println!("[UNTRUSTED] get_capabilities Failed {}!", x.as_str());
+            return;
+        }
+    };
+    let threads = usable_threads(&capabilities, threads);
+
+    // chunks prebuilt by bin/app build-chunks or sealed by the health authority (bin/app seal-chunks)
+    let chunk_files = if ChunkFiles::exists(c_filename) {
+        match ChunkFiles::open(c_filename) {
+            Ok(chunk_files) => Some(chunk_files),
+            Err(x) => {
+                println!("[UNTRUSTED] {}", x);
+                return;
+            }
+        }
+    } else {
+        None
+    };
+    let prebuilt = chunk_files.is_some();
+    // the host can neither read nor rebuild sealed chunks
+    let sealed = chunk_files.as_ref().map_or(false, |chunk_files| chunk_files.manifest().sealed);
+    if !sealed && !backend.takes_plaintext_chunks() {
+        println!("[UNTRUSTED] The enclave only traces chunks sealed by the health authority (bin/app seal-chunks), build it with FEATURE=unsealed_chunks for plaintext ones");
+        return;
+    }
+
+    // what the chunks have been built with, named by the manifest of prebuilt chunks
+    let (builders_name, dictionary_ids, codec, threads) = match chunk_files.as_ref() {
+        Some(chunk_files) => {
+            let manifest = chunk_files.manifest();
+            let dictionary_ids: Option<Vec<u8>> = manifest.dictionaries.iter()
+                .map(|name| chunk_builder_from_name(name).map(|builder| builder.backend_id()))
+                .collect();
+            let dictionary_ids = match dictionary_ids {
+                Some(dictionary_ids) => dictionary_ids,
+                None => {
+                    println!("[UNTRUSTED] Unknown dictionary in the manifest of {}", c_filename);
+                    return;
+                }
+            };
+            // each chunk in flight takes the share of the budget it has been sized for
+            if threads > manifest.threads {
+                println!("[UNTRUSTED] {} has been built for {} threads", c_filename, manifest.threads);
+            }
+            (manifest.dictionaries.join("+"), dictionary_ids, manifest.codec, cmp::min(threads, manifest.threads))
+        },
+        None => (chunk_builders_name(&builders), builders.iter().map(|builder| builder.backend_id()).collect(), codec, threads),
+    };
+
+    let (R, central_data_size, central_encoding) = match chunk_files {
+        Some(chunk_files) => {
+            let central_data_size = chunk_files.manifest().records;
+            let central_encoding = chunk_files.manifest().encoding();
+            // mapped on the producer thread while the queries are uploaded and traced
+            (ChunkPipeline::spawn(chunk_files, threads), central_data_size, central_encoding)
+        },
+        None => {
+            /* read central data */
+            clocker.set_and_start("Read Central Data");
+            let external_data = read_central_data(c_filename);
+            clocker.stop("Read Central Data");
+            let central_data_size = external_data.size();
+            let central_encoding = external_data.encoding();
+
+            /* preprocess central data, chunks sized by the heap and threads the enclave reports */
+            let budget = match ChunkBudget::from_capabilities(capabilities.heap_size, capabilities.max_threads, false, codec) {
+                Ok(budget) => budget.shared_by(threads),
+                Err(x) => {
+                    println!("[UNTRUSTED] {}", x);
+                    return;
+                }
+            };
+            // built on the producer thread while the queries are uploaded and traced
+            let chunks = ChunkProducer::new(external_data, threashould, budget, builders, codec);
+            (ChunkPipeline::spawn(chunks, threads), central_data_size, central_encoding)
+        },
+    };
+    let R = Arc::new(R);
+
+    /* contact rules of the health authority */
+    let rules = match args.get(3) {
+        Some(r_filename) => ContactRules::read_raw_from_file(r_filename),
+        None => ContactRules::default(),
+    };
+    if let Err(x) = backend.set_contact_rules(&rules) {
+        println!("[UNTRUSTED] set_contact_rules Failed {}!", x.as_str());
+        return;
+    }
+    match backend.set_lookup_mode(lookup_mode) {
+        Ok(()) => {},
+        Err(sgx_status_t::SGX_ERROR_FEATURE_NOT_SUPPORTED) if lookup_mode == LookupMode::Indexed => {
+            println!("[UNTRUSTED] {}", INDEXED_IS_NATIVE_ONLY);
+            return;
+        },
+        Err(x) => {
+            println!("[UNTRUSTED] set_lookup_mode Failed {}!", x.as_str());
+            return;
+        }
+    }
+
+    /* chunk key of the health authority, released only to an attested enclave */
+    if sealed {
+        let chunk_key = match chunk_key_from_env() {
+            Some(chunk_key) => chunk_key,
+            None => {
+                println!("[UNTRUSTED] Sealed chunks need the chunk key, check PCT_AUTHORITY_KEY");
+                return;
+            }
+        };
+        let signing_key = match signing_key_from_env() {
+            Some(signing_key) => signing_key,
+            None => {
+                println!("[UNTRUSTED] The enclave only takes the chunk key from the authority, check PCT_AUTHORITY_SIGNING_KEY");
+                return;
+            }
+        };
+        clocker.set_and_start("ECALL provision_chunk_key");
+        if let Err(x) = backend.provision_chunk_key(&chunk_key, &signing_key) {
+            println!("[UNTRUSTED] provision_chunk_key Failed {}!", x.as_str());
+            return;
+        }
+        clocker.stop("ECALL provision_chunk_key");
+    }
 
     /* read query data */
     clocker.set_and_start("Read Query Data");
-    let query_data = EncodedQueryData::read_raw_from_file(q_filename);
+    let query_data = read_query_data(q_filename);
     clocker.stop("Read Query Data");
+    let encoding = match dataset_encoding(query_data.encoding, central_encoding) {
+        Ok(encoding) => encoding,
+        Err(x) => {
+            println!("[UNTRUSTED] {}", x);
+            return;
+        }
+    };
+
+    /* the enclave has to read this run before any client data is sent */
+    if let Err(x) = check_capabilities(&capabilities, encoding, lookup_mode, &dictionary_ids, codec) {
+        println!("[UNTRUSTED] {}", x);
+        return;
+    }
+
+    /* key exchange bound to enclave attestation */
+    clocker.set_and_start("ECALL key_exchange");
+    if let Err(x) = backend.establish_channels(&query_data.query_id_list()) {
+        println!("[UNTRUSTED] key_exchange Failed {}!", x.as_str());
+        return;
+    }
+    clocker.stop("ECALL key_exchange");
 
     /* encrypt and upload query data */
-    let total_data_vec = query_data.total_data_to_u8();
     clocker.set_and_start("ECALL upload_query_data");
-    let mut retval = sgx_status_t::SGX_SUCCESS;
-    let result = unsafe {
-        upload_encoded_query_data(
-            enclave.geteid(),
-            &mut retval,
-            total_data_vec.as_ptr() as * const u8,
-            total_data_vec.len(),
-            query_data.client_size,
-            query_data.query_id_list().as_ptr() as * const u64
-        )
-    };
-    match result {
-        sgx_status_t::SGX_SUCCESS => {
-            // println!("[UNTRUSTED] upload_query_data Succes!");
+    match backend.upload_queries(&query_data, encoding) {
+        Ok(()) => {},
+        Err(sgx_status_t::SGX_ERROR_MAC_MISMATCH) => {
+            println!("[UNTRUSTED] upload_query_data rejected a tampered query!");
+            return;
         },
-        _ => {
-            println!("[UNTRUSTED] upload_query_data Failed {}!", result.as_str());
+        Err(sgx_status_t::SGX_ERROR_INVALID_METADATA) => {
+            println!("[UNTRUSTED] upload_query_data rejected a corrupted or inconsistent queries frame!");
+            return;
+        },
+        Err(x) => {
+            println!("[UNTRUSTED] upload_query_data Failed {}!", x.as_str());
             return;
         }
     }
     clocker.stop("ECALL upload_query_data");
 
     /* main logic contact tracing */
-    let mut chunk_index: usize = 0;
-    let last = R.len() - 1;
     clocker.set_and_start("ECALL private_contact_trace");
-    while last >= chunk_index {
-        let chunk: &Vec<u8> = R.prepare_sgx_data(chunk_index);
-        let result = unsafe {
-            private_encode_contact_trace(
-                enclave.geteid(),
-                &mut retval,
-                chunk.as_ptr() as * const u8,
-                chunk.len()
-            )
-        };
-        match result {
-            sgx_status_t::SGX_SUCCESS => {
-                // print!("\r[UNTRUSTED] private_contact_trace Succes! {} th iteration", chunk_index);
-            },
-            _ => {
-                println!("[UNTRUSTED] private_contact_trace Failed {}!", result.as_str());
-                return;
-            }
-        }
-        chunk_index += 1;
+    let traced = backend.trace_chunks(R.clone(), threads);
+    let produced = R.finish();
+    if let Err((chunk_index, x)) = traced {
+        println!("[UNTRUSTED] private_contact_trace Failed {} at chunk {}!", x.as_str(), chunk_index);
+        return;
+    }
+    // a chunk that could not be built or read ends the pipeline early
+    if let Err(x) = produced {
+        println!("[UNTRUSTED] {}", x);
+        return;
     }
-    // println!("");
-    
     clocker.stop("ECALL private_contact_trace");
 
     /* response reconstruction */
     clocker.set_and_start("ECALL get_result");
-    let response_size = query_data.client_size * RESPONSE_DATA_SIZE_U8;
-    let mut response: Vec<u8> = vec![0; response_size];
-    let result = unsafe {
-        get_encoded_result(
-            enclave.geteid(),
-            &mut retval,
-            response.as_mut_ptr(),
-            response_size
-        )
-    };
-    match result {
-        sgx_status_t::SGX_SUCCESS => {
-            // println!("[UNTRUSTED] get_result Succes!");
+    let scores = match backend.get_result(&query_data, encoding) {
+        Ok(scores) => scores,
+        Err(sgx_status_t::SGX_ERROR_MAC_MISMATCH) => {
+            println!("[UNTRUSTED] a response has been tampered with!");
+            return;
         },
-        _ => {
-            println!("[UNTRUSTED] get_result Failed {}!", result.as_str());
+        Err(sgx_status_t::SGX_ERROR_INVALID_METADATA) => {
+            println!("[UNTRUSTED] the response frame is corrupted or inconsistent!");
+            return;
+        },
+        Err(x) => {
+            println!("[UNTRUSTED] get_result Failed {}!", x.as_str());
             return;
         }
-    }
+    };
     clocker.stop("ECALL get_result");
-    
-    let mut positive_queries = vec![];
-    for i in 0..query_data.client_size {
-        /* decryption for each clients using their keys */ 
-        let query_id: QueryId = query_id_from_u8(&response[i*RESPONSE_DATA_SIZE_U8..i*RESPONSE_DATA_SIZE_U8+QUERY_ID_SIZE_U8]);
-        let mut shared_key: [u8; 16] = [0; 16];
-        shared_key[..8].copy_from_slice(&query_id.to_be_bytes());
-        let counter_block: [u8; 16] = COUNTER_BLOCK;
-        let ctr_inc_bits: u32 = SGXSSL_CTR_BITS;
-        let src_len: usize = QUERY_RESULT_U8;
-        let mut result: Vec<u8> = vec![0; src_len];
-        let ret = unsafe {
-            util::sgx_aes_ctr_decrypt(
-                &shared_key,
-                response[i*RESPONSE_DATA_SIZE_U8+QUERY_ID_SIZE_U8..i*RESPONSE_DATA_SIZE_U8+RESPONSE_DATA_SIZE_U8].as_ptr() as *const u8,
-                src_len as u32,
-                &counter_block as * const u8,
-                ctr_inc_bits,
-                result.as_mut_ptr()
-            )
-        };
-        if ret < 0 { println!("Error in CTR decryption."); std::process::exit(-1); }
-        if result[0] > 0 {
-            positive_queries.push(query_id);
-        }
+
+    let positive_queries: Vec<RiskScore> = scores.into_iter().filter(|score| score.is_positive()).collect();
+    // println!("positive results: {:?}", positive_queries);
+    match slot_clock_from_env() {
+        Ok(Some(clock)) => {
+            for score in positive_queries.iter() {
+                for (start, end) in score.exposure_windows(encoding, &clock) {
+                    println!("[UNTRUSTED] query_id {} possible exposure {} - {} UTC", score.query_id, format_unixepoch(start), format_unixepoch(end));
+                }
+            }
+        },
+        Ok(None) => {},
+        Err(x) => { println!("[UNTRUSTED] {}", x); },
     }
-    // println!("positive result queryIds: {:?}", positive_queries);
 
     /* finish */
-    enclave.destroy();
+    backend.destroy();
     // println!("[UNTRUSTED] All process is successful!!");
     clocker.show_all();
     let now: String = get_timestamp();
 
-    #[cfg(feature = "th72")]
-    let method = "th72";
-    #[cfg(feature = "th48")]
-    let method = "th48";
-    #[cfg(feature = "th54")]
-    let method = "th54";
-    #[cfg(feature = "th60")]
-    let method = "th60";
-    #[cfg(feature = "gp10")]
-    let method = "gp10";
-
-    #[cfg(feature = "hashtable")]
-    let data_st = "hashtable";
-    #[cfg(feature = "fsa")]
-    let data_st = "fsa";
+    let method = encoding.name();
+
+    let data_st = if sealed { "sealed".to_string() } else { builders_name };
+    let data_st = if lookup_mode == LookupMode::Oblivious { format!("oblivious{}", data_st) } else { data_st };
+    let data_st = if lookup_mode == LookupMode::Indexed { format!("indexed{}", data_st) } else { data_st };
+    let data_st = if !sealed && codec == chunk_codec::CODEC_LZ { format!("{}lz", data_st) } else { data_st };
+    let data_st = if prebuilt && !sealed { format!("prebuilt{}", data_st) } else { data_st };
 
     write_to_file(
         format!("data/result/journal/ex1/{}-{}-{}-{}-{}-{}.txt",
-            data_st.to_string(), method.to_string(), threashould, query_data.client_size, central_data_size, now
+            data_st.to_string(), method.to_string(), args[0], query_data.client_size, central_data_size, now
         ),
         data_st.to_string(),
         method.to_string(),
@@ -239,16 +454,16 @@ fn private_set_intersection() {
         central_data_size,
         q_filename.to_string(),
         query_data.client_size,
-        1440,
-        threashould,
+        query_data.average_query_size(),
+        args[0].to_string(),
         clocker
     );
 }
 
+// $ bin/app non-private <threashold or auto> <query data file path> <central data file path>
 fn non_private_set_intersection() {
-    let args = _get_options();
+    let args = _get_options(2);
     /* parameters */
-    let threashould: usize = args[0].parse().unwrap();
     let q_filename = &args[1];
     let c_filename = &args[2];
 
@@ -256,42 +471,48 @@ fn non_private_set_intersection() {
 
     /* read central data */
     clocker.set_and_start("Read Central Data");
-    let external_data = EncodedData::read_raw_from_file(c_filename);
+    let external_data = read_central_data(c_filename);
     clocker.stop("Read Central Data");
     let central_data_size = external_data.size();
+    let central_encoding = external_data.encoding();
 
     /* preprocess central data */
+    // the first backend of PCT_DICTIONARY, the whole data is one dictionary here
+    let builders = match chunk_builders_from_env() {
+        Some(builders) => builders,
+        None => {
+            println!("[UNTRUSTED] Unknown dictionary, check PCT_DICTIONARY");
+            return;
+        }
+    };
     clocker.set_and_start("Distribute central data");
-    #[cfg(feature = "hashtable")]
-    let mut R: NonPrivateHashSet = NonPrivateHashSet::from_EncodedData(external_data);
-    #[cfg(feature = "fsa")]
-    let mut R: NonPrivateFSA = NonPrivateFSA::from_EncodedData(external_data);
+    let R = non_private_dictionary_from_name(builders[0].name(), external_data).unwrap();
     clocker.stop("Distribute central data");
 
     R.calc_memory();
 
     /* read query data */
     clocker.set_and_start("Read Query Data");
-    let query_data = EncodedQueryData::read_raw_from_file(q_filename);
+    let query_data = read_query_data(q_filename);
     clocker.stop("Read Query Data");
+    let encoding = match dataset_encoding(query_data.encoding, central_encoding) {
+        Ok(encoding) => encoding,
+        Err(x) => {
+            println!(" ERROR {}", x);
+            return;
+        }
+    };
 
-    let mut query_set: HashSet<EncodedValue> = HashSet::with_capacity(query_data.client_size*1440);
+    let mut query_set: HashSet<EncodedValue> = HashSet::with_capacity(query_data.total_query_size());
     for detail in query_data.data.iter() {
-        for hash in detail.geodata.iter() {
-            let mut encoded_value_u8: EncodedValue = [0_u8; ENCODEDVALUE_SIZE];
-            #[cfg(any(feature = "th72", feature = "th48", feature = "th54", feature = "th60"))]
-            encoded_value_u8.copy_from_slice(base8decode(hash.to_string()).as_slice());
-            #[cfg(any(feature = "gp10"))]
-            encoded_value_u8.copy_from_slice(hash.as_bytes());
-            query_set.insert(encoded_value_u8);
-        }
+        query_set.extend(detail.values.iter());
     }
 
     /* main logic contact tracing */
     clocker.set_and_start("Contact trace");
     let mut reuslt: Vec<EncodedValue> = Vec::default();
     for data in query_set.iter() {
-        if R.set.contains(data) {
+        if R.contains(data) {
             reuslt.push(*data);
         }
     }
@@ -300,13 +521,8 @@ fn non_private_set_intersection() {
     let mut positive_queries: HashSet<QueryId> = HashSet::default();
     query_data.data.iter().for_each( |query| {
         let query_id: QueryId = query.query_id;
-        let contact = query.geodata.iter().any(|hash| {
-            let mut encoded_value_u8: EncodedValue = [0_u8; ENCODEDVALUE_SIZE];
-            #[cfg(any(feature = "th72", feature = "th48", feature = "th54", feature = "th60"))]
-            encoded_value_u8.copy_from_slice(base8decode(hash.to_string()).as_slice());
-            #[cfg(any(feature = "gp10"))]
-            encoded_value_u8.copy_from_slice(hash.as_bytes());
-            R.set.contains(&encoded_value_u8)
+        let contact = query.values.iter().any(|value| {
+            R.contains(value)
         });
         if contact {
             positive_queries.insert(query_id);
@@ -317,25 +533,13 @@ fn non_private_set_intersection() {
     clocker.show_all();
     let now: String = get_timestamp();
 
-    #[cfg(feature = "th72")]
-    let method = "th72";
-    #[cfg(feature = "th48")]
-    let method = "th48";
-    #[cfg(feature = "th54")]
-    let method = "th54";
-    #[cfg(feature = "th60")]
-    let method = "th60";
-    #[cfg(feature = "gp10")]
-    let method = "gp10";
+    let method = encoding.name();
     
-    #[cfg(feature = "hashtable")]
-    let data_st = "nonprivatehashtable";
-    #[cfg(feature = "fsa")]
-    let data_st = "nonprivatefsa";
+    let data_st = format!("nonprivate{}", builders[0].name());
 
     write_to_file(
         format!("data/result/journal/ex1/{}-{}-{}-{}-{}-{}.txt",
-            data_st.to_string(), method.to_string(), threashould, query_data.client_size, central_data_size, now
+            data_st.to_string(), method.to_string(), args[0], query_data.client_size, central_data_size, now
         ),
         data_st.to_string(),
         method.to_string(),
@@ -343,37 +547,315 @@ fn non_private_set_intersection() {
         central_data_size,
         q_filename.to_string(),
         query_data.client_size,
-        1440,
-        threashould,
+        query_data.average_query_size(),
+        args[0].to_string(),
         clocker
     );
 }
 
+// $ bin/app show-size <threashold or auto> <query data file path> <central data file path>
 fn show_size() {
-    let args = _get_options();
+    let args = _get_options(2);
     /* parameters */
-    let threashould: usize = args[0].parse().unwrap();
+    let threashould: usize = _get_threashould(&args[0]);
     let q_filename = &args[1];
     let c_filename = &args[2];
 
 
     /* read central data */
-    let external_data = EncodedData::read_raw_from_file(c_filename);
+    let external_data = read_central_data(c_filename);
     let central_data_size = external_data.size();
 
     println!("central data size: {}", central_data_size);
 
     /* preprocess central data */
-    #[cfg(feature = "hashtable")]
-    let mut R: CentralHashSet = CentralHashSet::from_EncodedData(external_data, threashould);
-    #[cfg(feature = "fsa")]
-    let mut R: CentralFST = CentralFST::from_EncodedData(external_data, threashould);
+    let builders = match chunk_builders_from_env() {
+        Some(builders) => builders,
+        None => {
+            println!("[UNTRUSTED] Unknown dictionary, check PCT_DICTIONARY");
+            return;
+        }
+    };
+    let codec = match chunk_codec_from_env() {
+        Some(codec) => codec,
+        None => {
+            println!("[UNTRUSTED] Unknown chunk codec, check PCT_CHUNK_CODEC");
+            return;
+        }
+    };
+    let budget = match ChunkBudget::from_capabilities(0, 0, false, codec) {
+        Ok(budget) => budget,
+        Err(x) => {
+            println!("[UNTRUSTED] {}", x);
+            return;
+        }
+    };
+    for chunk in ChunkProducer::new(external_data, threashould, budget, builders, codec) {
+        if let Err(x) = chunk {
+            println!("[UNTRUSTED] {}", x);
+            return;
+        }
+    }
+
+}
+
+/*
+    Converts a JSON file of script/gen to a record file (record_file.rs)
+    $ bin/app convert-records <central or query> <JSON file path> <record file path>
+*/
+fn convert_records() {
+    let args: Vec<String> = env::args().skip(2).collect();
+    if args.len() != 3 {
+        println!(" ERROR bin/app convert-records needs 3 arguments!");
+        println!("    args[0] = central or query");
+        println!("    args[1] = JSON file path");
+        println!("    args[2] = record file path");
+        std::process::exit(-1);
+    }
+    let written = match args[0].as_str() {
+        "central" => EncodedData::read_raw_from_file(&args[1]).write_records(&args[2]),
+        "query" => EncodedQueryData::read_raw_from_file(&args[1]).write_records(&args[2]),
+        _ => Err(format!("{} is neither central nor query", args[0])),
+    };
+    match written {
+        Ok(count) => println!("{} {} records written to {}", args[0], count, args[2]),
+        Err(x) => println!(" ERROR {}", x),
+    }
+}
+
+/*
+    Builds the chunks of the central data once, for every run over the same data
+    $ bin/app build-chunks <threashold or auto> <central data file path> <output directory>
+*/
+fn build_central_chunks() {
+    let args: Vec<String> = env::args().skip(2).collect();
+    if args.len() != 3 {
+        println!(" ERROR bin/app build-chunks needs 3 arguments!");
+        println!("    args[0] = threashold of each chunk block size, or auto");
+        println!("    args[1] = central data file path or central store");
+        println!("    args[2] = output directory");
+        std::process::exit(-1);
+    }
+    write_chunk_dir("[UNTRUSTED]", &args[0], &args[1], &args[2], None);
+}
+
+/*
+    Health authority tool, seals the chunks of the central data
+    $ bin/app seal-chunks <threashold or auto> <central data file path> <chunk key file path> <output directory>
+    the chunk key file is created if it does not exist yet
+*/
+fn seal_central_chunks() {
+    let args: Vec<String> = env::args().skip(2).collect();
+    if args.len() != 4 {
+        println!(" ERROR bin/app seal-chunks needs 4 arguments!");
+        println!("    args[0] = threashold of each chunk block size, or auto");
+        println!("    args[1] = central data file path or central store");
+        println!("    args[2] = chunk key file path");
+        println!("    args[3] = output directory");
+        std::process::exit(-1);
+    }
+    let chunk_key = read_or_create_chunk_key(&args[2]);
+    write_chunk_dir("[AUTHORITY]", &args[0], &args[1], &args[3], Some(chunk_key));
+}
 
+/*
+    Health authority tool, creates the key it signs its key exchange with
+    $ bin/app authority-keygen <signing key file path> [public key file path, <signing key>.pub]
+    the enclave has to be built with the written public key, see AUTHORITY_PUBLIC_KEY of the Makefile
+*/
+fn authority_keygen() {
+    let args: Vec<String> = env::args().skip(2).collect();
+    if args.is_empty() || args.len() > 2 {
+        println!(" ERROR bin/app authority-keygen needs 1 or 2 arguments!");
+        println!("    args[0] = signing key file path");
+        println!("    args[1] = public key file path, the signing key file path + .pub by default");
+        std::process::exit(-1);
+    }
+    let public_key_filename = args.get(1).cloned().unwrap_or(format!("{}.pub", args[0]));
+    match create_signing_key(&args[0], &public_key_filename) {
+        Ok(()) => println!("[AUTHORITY] signing key {}, build the enclave with AUTHORITY_PUBLIC_KEY={}", args[0], public_key_filename),
+        Err(x) => println!("[AUTHORITY] authority-keygen Failed {}!", x),
+    }
+}
+
+// chunks of the central data written to dirname as they are built, sealed when a chunk key is given
+fn write_chunk_dir(tag: &str, threashold: &str, c_filename: &str, dirname: &str, chunk_key: Option<ChunkKey>) {
+    let threashould: usize = _get_threashould(threashold);
+
+    let builders = match chunk_builders_from_env() {
+        Some(builders) => builders,
+        None => {
+            println!("{} Unknown dictionary, check PCT_DICTIONARY", tag);
+            return;
+        }
+    };
+    let codec = match chunk_codec_from_env() {
+        Some(codec) => codec,
+        None => {
+            println!("{} Unknown chunk codec, check PCT_CHUNK_CODEC", tag);
+            return;
+        }
+    };
+    let (external_data, snapshot) = read_central_snapshot(c_filename);
+    // no enclave here, the chunks are sized by HeapMaxSize of the enclave config
+    // and the PCT_THREADS the service will trace them with
+    let threads = match threads_from_env() {
+        Some(threads) => threads,
+        None => {
+            println!("{} Invalid thread count, check PCT_THREADS", tag);
+            return;
+        }
+    };
+    let budget = match ChunkBudget::from_capabilities(0, 0, chunk_key.is_some(), codec) {
+        Ok(budget) => budget.shared_by(threads),
+        Err(x) => {
+            println!("{} {}", tag, x);
+            return;
+        }
+    };
+    let dictionaries: Vec<String> = builders.iter().map(|builder| builder.name().to_string()).collect();
+    let mut writer = match ChunkDirWriter::create(dirname, dictionaries, codec, chunk_key.is_some(), threads, snapshot) {
+        Ok(writer) => writer,
+        Err(x) => {
+            println!("{} {}", tag, x);
+            return;
+        }
+    };
+    // written as they are built, one chunk in memory at a time
+    for chunk in ChunkProducer::new(external_data, threashould, budget, builders, codec) {
+        let chunk = match chunk {
+            Ok(chunk) => chunk,
+            Err(x) => {
+                println!("{} {}", tag, x);
+                return;
+            }
+        };
+        let chunk = match chunk_key {
+            Some(ref chunk_key) => match seal_chunk_frame(chunk_key, &chunk) {
+                Ok(sealed_chunk) => sealed_chunk,
+                Err(x) => {
+                    println!("{} seal_chunk Failed {}!", tag, x.as_str());
+                    return;
+                }
+            },
+            None => chunk,
+        };
+        if let Err(x) = writer.push(&chunk) {
+            println!("{} {}", tag, x);
+            return;
+        }
+    }
+    match writer.finish() {
+        Ok(manifest) => println!("{} {} chunks of {} records written to {}", tag, manifest.chunks.len(), manifest.records, dirname),
+        Err(x) => println!("{} {}", tag, x),
+    }
 }
 
+/*
+    Health authority tool, keeps the central data in a central store
+    $ bin/app central-store init <store directory> <unix time of time slot 0> <seconds per time slot>
+    $ bin/app central-store append <store directory> <central data file path>
+    $ bin/app central-store expire <store directory> [unix time, now by default]
+    $ bin/app central-store compact <store directory>
+    $ bin/app central-store batches <store directory>
+    $ bin/app central-store revoke <store directory> <batch id>
+*/
+fn central_store_command() {
+    let args: Vec<String> = env::args().skip(2).collect();
+    let now = get_timestamp().parse::<u64>().unwrap();
+    let result = match (args.get(0).map(|command| command.as_str()), args.len()) {
+        (Some("init"), 4) => {
+            match (args[2].parse::<u64>(), args[3].parse::<u64>()) {
+                (Ok(slot_origin), Ok(slot_seconds)) => CentralStore::create(&args[1], slot_origin, slot_seconds)
+                    .map(|_| format!("created {}", args[1])),
+                _ => Err("time slot origin and seconds have to be numbers".to_string()),
+            }
+        },
+        (Some("append"), 3) => {
+            let batch = read_central_file(&args[2]);
+            CentralStore::open(&args[1]).and_then(|mut store| {
+                let batch_id = store.append(&batch, &args[2], now)?;
+                Ok(format!("{} records appended as batch {}, {} in snapshot {}",
+                    batch.size(), batch_id.map_or("-".to_string(), |id| id.to_string()), store.size(), store.manifest().snapshot))
+            })
+        },
+        (Some("expire"), 2) | (Some("expire"), 3) => {
+            match args.get(2).map_or(Ok(now), |unixepoch| unixepoch.parse::<u64>()) {
+                Ok(unixepoch) => CentralStore::open(&args[1]).and_then(|mut store| {
+                    let dropped = store.expire(unixepoch)?;
+                    Ok(format!("{} records expired, {} in snapshot {}", dropped, store.size(), store.manifest().snapshot))
+                }),
+                Err(_) => Err("unix time has to be a number".to_string()),
+            }
+        },
+        (Some("compact"), 2) => {
+            CentralStore::open(&args[1]).and_then(|mut store| {
+                let segments = store.compact()?;
+                Ok(format!("{} segments in snapshot {}", segments, store.manifest().snapshot))
+            })
+        },
+        (Some("batches"), 2) => {
+            CentralStore::open(&args[1]).map(|store| {
+                store.manifest().batches.iter().map(|batch| {
+                    format!("\n    batch {} added {} records {} from {}{}", batch.id, batch.added, batch.count, batch.source,
+                        batch.revoked.map_or(String::new(), |revoked| format!(" revoked {}", revoked)))
+                }).collect::<Vec<String>>().concat()
+            })
+        },
+        (Some("revoke"), 3) => {
+            match args[2].parse::<u64>() {
+                Ok(batch_id) => CentralStore::open(&args[1]).and_then(|mut store| {
+                    let tombstoned = store.revoke(batch_id, now)?;
+                    Ok(format!("batch {} revoked, {} records withdrawn in snapshot {}", batch_id, tombstoned, store.manifest().snapshot))
+                }),
+                Err(_) => Err("batch id has to be a number".to_string()),
+            }
+        },
+        _ => {
+            println!(" ERROR unknown central-store command!");
+            println!("    init <store directory> <unix time of time slot 0> <seconds per time slot>");
+            println!("    append <store directory> <central data file path>");
+            println!("    expire <store directory> [unix time]");
+            println!("    compact <store directory>");
+            println!("    batches <store directory>");
+            println!("    revoke <store directory> <batch id>");
+            std::process::exit(-1);
+        }
+    };
+    match result {
+        Ok(x) => println!("[AUTHORITY] {}", x),
+        Err(x) => println!("[AUTHORITY] central-store Failed {}!", x),
+    }
+}
 
 fn main() {
-    // private_set_intersection()
-    // non_private_set_intersection()
-    show_size()
+    if env::args().nth(1).map_or(false, |command| command == "convert-records") {
+        convert_records();
+        return;
+    }
+    if env::args().nth(1).map_or(false, |command| command == "build-chunks") {
+        build_central_chunks();
+        return;
+    }
+    if env::args().nth(1).map_or(false, |command| command == "seal-chunks") {
+        seal_central_chunks();
+        return;
+    }
+    if env::args().nth(1).map_or(false, |command| command == "authority-keygen") {
+        authority_keygen();
+        return;
+    }
+    if env::args().nth(1).map_or(false, |command| command == "central-store") {
+        central_store_command();
+        return;
+    }
+    if env::args().nth(1).map_or(false, |command| command == "show-size") {
+        show_size();
+        return;
+    }
+    if env::args().nth(1).map_or(false, |command| command == "non-private") {
+        non_private_set_intersection();
+        return;
+    }
+    private_set_intersection()
 }
\ No newline at end of file
@@ -2,38 +2,103 @@ use serde::*;
 use std::fs::File;
 use std::io::BufReader;
 use hex;
-use util::*;
-
-// バファリングするクエリはせいぜい10000なので64bitで余裕
-pub type QueryId = u64;
+use encoding::{ Encoding, EncodedValue, DatasetEncoding };
+use record_file::{ RecordReader, RecordWriter, RECORDS_QUERY };
+use secure_channel::{ SessionKeys, seal };
+use pct_core::primitive::{ QueryId, SessionKey };
+use pct_core::constant::DIRECTION_QUERY;
+use pct_core::wire::{ write_frame, FRAME_QUERIES };
 
 // query data sholud be no compressioned...
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Debug)]
 pub struct EncodedQueryData {
     pub data: Vec<EncodedQueryDataDetail>,
     pub client_size: usize,
+    // of every value, None if there is no point at all
+    pub encoding: Option<Encoding>,
+}
+
+// query file written by script/gen/generate-client-*.py
+#[derive(Serialize, Deserialize, Debug)]
+struct EncodedQueryDataJson {
+    data: Vec<EncodedQueryDataDetailJson>,
+    client_size: usize,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+struct EncodedQueryDataDetailJson {
+    query_id: QueryId,
+    geodata: Vec<String>,
+    query_size: usize,
 }
 
 impl EncodedQueryData { 
+    // the encoding is detected from the strings, which are decoded once here
     pub fn read_raw_from_file(filename: &str) -> Self {
         let file = File::open(filename).unwrap();
         let reader = BufReader::new(file);
-        let query_data: EncodedQueryData = serde_json::from_reader(reader).unwrap();
+        let query_data: EncodedQueryDataJson = serde_json::from_reader(reader).unwrap();
         if query_data.client_size != query_data.data.len() {
             println!("[Error] Invalid data format from {}!", filename);
             panic!()
         }
-        query_data
+        if query_data.data.iter().any(|detail| detail.query_size != detail.geodata.len()) {
+            println!("[Error] query_size does not match the length of geodata in {}!", filename);
+            panic!()
+        }
+        let encoding = Encoding::detect_all(
+            query_data.data.iter().flat_map(|detail| detail.geodata.iter()), filename
+        );
+        let data: Vec<EncodedQueryDataDetail> = query_data.data.into_iter().map(|detail| EncodedQueryDataDetail {
+            query_id: detail.query_id,
+            // no value to decode without an encoding
+            values: detail.geodata.iter().map(|hash| encoding.unwrap_or_default().decode(hash)).collect(),
+            query_size: detail.query_size,
+        }).collect();
+        EncodedQueryData { data: data, client_size: query_data.client_size, encoding: encoding }
+    }
+
+    // query record file, see record_file.rs
+    pub fn read_records(filename: &str) -> Result<Self, String> {
+        let mut reader = RecordReader::open(filename, RECORDS_QUERY)?;
+        let mut data: Vec<EncodedQueryDataDetail> = Vec::with_capacity(reader.count().min(1 << 20) as usize);
+        while let Some(query) = reader.next_query() {
+            let (query_id, values) = query?;
+            data.push(EncodedQueryDataDetail { query_id: query_id, query_size: values.len(), values: values });
+        }
+        Ok(EncodedQueryData { client_size: data.len(), data: data, encoding: reader.encoding() })
     }
 
-    pub fn total_data_to_u8(&self) -> Vec<u8> {
+    pub fn write_records(&self, filename: &str) -> Result<u64, String> {
+        let mut writer = RecordWriter::create(filename, RECORDS_QUERY, self.encoding)?;
+        for detail in self.data.iter() {
+            writer.push_query(detail.query_id, &detail.values)?;
+        }
+        writer.finish()
+    }
+
+    // queries frame of pct_core/src/wire.rs
+    pub fn total_data_to_u8(&self, encoding: Encoding, session_keys: &SessionKeys) -> Vec<u8> {
         let mut u8_vec_list: Vec<Vec<u8>> = Vec::with_capacity(self.client_size);
         self.data.iter().for_each(|detail| {
             // encrypt by session key as secure channel to enclave.
-            u8_vec_list.push(encryptAsSecureChannel(detail));
+            let session_key = session_keys.get(&detail.query_id)
+                .expect("key exchange has not been done for this client!");
+            // each client record is prefixed by its number of points
+            let mut record: Vec<u8> = (detail.query_size as u32).to_be_bytes().to_vec();
+            record.extend(encryptAsSecureChannel(detail, encoding, session_key));
+            u8_vec_list.push(record);
         });
         let total_u8_vec: Vec<u8> = flatten(u8_vec_list);
-        total_u8_vec
+        write_frame(FRAME_QUERIES, encoding, self.client_size as u32, &total_u8_vec)
+    }
+
+    pub fn total_query_size(&self) -> usize {
+        self.data.iter().map(|d| d.query_size).sum()
+    }
+
+    pub fn average_query_size(&self) -> usize {
+        if self.client_size == 0 { 0 } else { self.total_query_size() / self.client_size }
     }
 
     pub fn query_id_list(&self) -> Vec<u64> {
@@ -41,62 +106,37 @@ impl EncodedQueryData {
     }
 }
 
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Debug)]
 pub struct EncodedQueryDataDetail {
     pub query_id: QueryId,
-    pub geodata: Vec<String>,
+    // decoded points, see Encoding::encode for their strings
+    pub values: Vec<EncodedValue>,
     pub query_size: usize,
 }
 
-fn encryptAsSecureChannel(detail: &EncodedQueryDataDetail) -> Vec<u8> {
-    /* Remote Attestation Mock up */
-    // Remote attestation is done and session (shared) key has been exchanged.
-    // Here, suppose that shared key is simply derived from their query_id.
-   
-    #[cfg(any(feature = "th72", feature = "th48", feature = "th54", feature = "th60"))]
-    let mut u8_vec: Vec<u8> = Vec::with_capacity(detail.query_size);
-    #[cfg(any(feature = "th72", feature = "th48", feature = "th54", feature = "th60"))]
-    for base8 in detail.geodata.iter() {
-        u8_vec.extend_from_slice(base8decode(base8.to_string()).as_slice());
+impl EncodedQueryDataDetail {
+    // plaintext query as the enclave reads it, query_size values of encoding.value_size() bytes
+    pub fn to_u8(&self, encoding: Encoding) -> Vec<u8> {
+        let mut u8_vec: Vec<u8> = Vec::with_capacity(self.query_size*encoding.value_size());
+        for value in self.values.iter() {
+            u8_vec.extend_from_slice(value.as_ref());
+        }
+        u8_vec
     }
+}
+
+fn encryptAsSecureChannel(detail: &EncodedQueryDataDetail, encoding: Encoding, session_key: &SessionKey) -> Vec<u8> {
+    // session_key has been exchanged with the enclave by secure_channel::establish_secure_channels
+    let u8_vec: Vec<u8> = detail.to_u8(encoding);
 
-    let mut shared_key: [u8; 16] = [0; 16];
-    shared_key[..8].copy_from_slice(&detail.query_id.to_be_bytes());
-    let counter_block: [u8; 16] = COUNTER_BLOCK;
-    let ctr_inc_bits: u32 = SGXSSL_CTR_BITS;
-    let src_len: usize = detail.query_size*ENCODEDVALUE_SIZE;
-    let mut encrypted_buf: Vec<u8> = vec![0; src_len];
-
-    #[cfg(any(feature = "th72", feature = "th48", feature = "th54", feature = "th60"))]
-    let ret = unsafe { 
-        sgx_aes_ctr_encrypt(
-            &shared_key,
-            u8_vec.as_ptr() as * const u8,
-            src_len as u32,
-            &counter_block as * const u8,
-            ctr_inc_bits,
-            encrypted_buf.as_mut_ptr()
-        )
-    };
-
-    #[cfg(any(feature = "gp10"))]
-    let ret = unsafe { 
-        sgx_aes_ctr_encrypt(
-            &shared_key,
-            detail.geodata.join("").as_bytes().as_ptr() as * const u8,
-            src_len as u32,
-            &counter_block as * const u8,
-            ctr_inc_bits,
-            encrypted_buf.as_mut_ptr()
-        )
-    };
-
-
-    if ret < 0 {
-        println!("Error in CTR encryption.");
-        std::process::exit(-1);
+    // nonce || ciphertext || tag
+    match seal(session_key, detail.query_id, DIRECTION_QUERY, &u8_vec) {
+        Ok(sealed) => sealed,
+        Err(x) => {
+            println!("Error in GCM encryption {}.", x.as_str());
+            std::process::exit(-1);
+        }
     }
-    encrypted_buf
 }
 
 fn flatten<T>(nested: Vec<Vec<T>>) -> Vec<T> {
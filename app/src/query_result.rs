@@ -0,0 +1,108 @@
+use std::env;
+use sgx_types::sgx_status_t;
+use pct_core::primitive::QueryId;
+use pct_core::query_result::QueryResult;
+use encoding::{ Encoding, EncodedValue, DatasetEncoding };
+
+/* 
+Type RiskScore
+    decrypted result of one client, see pct_core/src/query_result.rs
+*/
+#[derive(Clone, Default, Debug, PartialEq)]
+pub struct RiskScore {
+    pub query_id: QueryId,
+    pub risk_level: u8,
+    // hits in the same cell
+    pub matched_slots: u32,
+    // hits only in one of the 8 neighbouring cells
+    pub neighbour_slots: u32,
+    // seconds
+    pub exposure_duration: u32,
+    // seconds
+    pub longest_exposure: u32,
+    // indices of the points of the query that hit, in the same or a neighbouring cell
+    pub matched_points: Vec<usize>,
+    // geodata of those points, filled by with_values
+    pub matched_values: Vec<String>,
+}
+
+impl RiskScore {
+    pub fn from_be_bytes(query_id: QueryId, bytes: &[u8], query_size: usize) -> Result<Self, String> {
+        let result = match QueryResult::from_be_bytes(query_id, bytes, query_size) {
+            Ok(result) => result,
+            Err(sgx_status_t::SGX_ERROR_FEATURE_NOT_SUPPORTED) => {
+                return Err(format!("unsupported response format version {}", bytes[0]));
+            },
+            Err(_) => { return Err(format!("invalid result length {}", bytes.len())); }
+        };
+        Ok(RiskScore {
+            query_id: query_id,
+            risk_level: result.risk_level,
+            matched_slots: result.matched_slots,
+            neighbour_slots: result.neighbour_slots,
+            exposure_duration: result.exposure_duration,
+            longest_exposure: result.longest_exposure,
+            matched_points: result.matched_point_indices(query_size),
+            matched_values: Vec::new(),
+        })
+    }
+
+    // maps the matched points back to the geodata the client uploaded
+    pub fn with_values(mut self, values: &[EncodedValue], encoding: Encoding) -> Self {
+        self.matched_values = self.matched_points.iter().map(|&i| encoding.encode(&values[i])).collect();
+        self
+    }
+
+    pub fn is_positive(&self) -> bool {
+        self.risk_level > 0
+    }
+
+    // [start, end) unix times of the matched gp10 slots, consecutive slots merged,
+    // empty for TrajectoryHash whose values do not give the slot back
+    pub fn exposure_windows(&self, encoding: Encoding, clock: &SlotClock) -> Vec<(u64, u64)> {
+        let mut slots: Vec<u64> = self.matched_values.iter()
+            .filter_map(|value| encoding.time_slot(value.as_bytes()))
+            .map(|slot| slot as u64)
+            .collect();
+        slots.sort();
+        slots.dedup();
+        let mut windows: Vec<(u64, u64)> = Vec::new();
+        for slot in slots {
+            let start = clock.origin + slot * clock.seconds;
+            match windows.last_mut() {
+                Some(window) if window.1 == start => { window.1 = start + clock.seconds; },
+                _ => { windows.push((start, start + clock.seconds)); }
+            }
+        }
+        windows
+    }
+}
+
+/*
+Type SlotClock
+    what a gp10 time slot means, slot i starts at origin + i * seconds (unix time)
+*/
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct SlotClock {
+    pub origin: u64,
+    pub seconds: u64,
+}
+
+/*
+    PCT_SLOT_ORIGIN (unix time) and PCT_SLOT_SECONDS (default 600) give the exposure
+    windows of the positive clients, None if PCT_SLOT_ORIGIN is not set
+*/
+pub fn slot_clock_from_env() -> Result<Option<SlotClock>, String> {
+    let origin = match env::var("PCT_SLOT_ORIGIN") {
+        Ok(origin) => origin.parse::<u64>().map_err(|_| format!("invalid PCT_SLOT_ORIGIN {}", origin))?,
+        Err(_) => { return Ok(None); }
+    };
+    let seconds = match env::var("PCT_SLOT_SECONDS") {
+        Ok(seconds) => match seconds.parse::<u64>() {
+            Ok(value) if value > 0 => value,
+            _ => { return Err(format!("invalid PCT_SLOT_SECONDS {}", seconds)); }
+        },
+        Err(_) => 600,
+    };
+    Ok(Some(SlotClock { origin: origin, seconds: seconds }))
+}
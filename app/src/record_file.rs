@@ -0,0 +1,299 @@
+use std::fs::File;
+use std::io::{ BufReader, BufWriter, Read, Write, Seek, SeekFrom, ErrorKind };
+use encoding::{ Encoding, EncodedValue, MAX_ENCODEDVALUE_SIZE };
+use pct_core::primitive::QueryId;
+
+/*
+Record files
+    Binary form of the central and query data, decoded values instead of the
+    strings of the JSON files (bin/app convert-records), read and written one
+    record at a time.
+        header     magic "PCTR" || version || kind || Encoding::id (0 without records)
+                   || value size || number of records (u64), 16 bytes
+        central    value, value size bytes each
+        query      query_id (u64) || number of points (u32) || values of its points
+    Integers are big endian like the wire format of pct_core.
+*/
+pub const RECORD_MAGIC: &'static [u8; 4] = b"PCTR";
+pub const RECORD_FORMAT_VERSION: u8 = 1;
+pub const RECORDS_CENTRAL: u8 = 1;
+pub const RECORDS_QUERY: u8 = 2;
+const RECORD_HEADER_U8: usize = 16;
+// offset of the number of records, written last
+const RECORD_COUNT_OFFSET: u64 = 8;
+
+// whether filename starts like a record file, anything else is read as JSON
+pub fn is_record_file(filename: &str) -> bool {
+    let mut magic = [0_u8; 4];
+    File::open(filename).and_then(|mut file| file.read_exact(&mut magic)).is_ok() && &magic == RECORD_MAGIC
+}
+
+fn kind_name(kind: u8) -> &'static str {
+    if kind == RECORDS_QUERY { "query" } else { "central" }
+}
+
+pub struct RecordReader {
+    filename: String,
+    reader: BufReader<File>,
+    encoding: Option<Encoding>,
+    count: u64,
+    read: u64,
+}
+
+impl RecordReader {
+    pub fn open(filename: &str, kind: u8) -> Result<Self, String> {
+        let file = File::open(filename).map_err(|e| format!("{}: {}", filename, e))?;
+        let mut reader = BufReader::new(file);
+        let mut header = [0_u8; RECORD_HEADER_U8];
+        reader.read_exact(&mut header).map_err(|e| format!("{}: {}", filename, e))?;
+        if &header[..4] != RECORD_MAGIC {
+            return Err(format!("{}: not a record file", filename));
+        }
+        if header[4] != RECORD_FORMAT_VERSION {
+            return Err(format!("{}: record format version {}, the app reads {}", filename, header[4], RECORD_FORMAT_VERSION));
+        }
+        if header[5] != kind {
+            return Err(format!("{}: {} records where {} records were expected", filename, kind_name(header[5]), kind_name(kind)));
+        }
+        let encoding = match (header[6], Encoding::from_id(header[6])) {
+            (0, _) => None,
+            (_, Some(encoding)) if encoding.value_size() == header[7] as usize => Some(encoding),
+            _ => { return Err(format!("{}: unknown encoding {} of {} bytes", filename, header[6], header[7])); }
+        };
+        let mut count = [0_u8; 8];
+        count.copy_from_slice(&header[8..]);
+        let count = u64::from_be_bytes(count);
+        if encoding.is_none() && count > 0 {
+            return Err(format!("{}: {} records without an encoding", filename, count));
+        }
+        Ok(RecordReader { filename: filename.to_string(), reader: reader, encoding: encoding, count: count, read: 0 })
+    }
+
+    pub fn encoding(&self) -> Option<Encoding> {
+        self.encoding
+    }
+
+    // number of records named by the header
+    pub fn count(&self) -> u64 {
+        self.count
+    }
+
+    fn read_bytes(&mut self, bytes: &mut [u8]) -> Result<(), String> {
+        self.reader.read_exact(bytes).map_err(|e| match e.kind() {
+            ErrorKind::UnexpectedEof => format!("{}: truncated after {} records", self.filename, self.read),
+            _ => format!("{}: {}", self.filename, e),
+        })
+    }
+
+    fn read_value(&mut self) -> Result<EncodedValue, String> {
+        let mut value = [0_u8; MAX_ENCODEDVALUE_SIZE];
+        let value_size = self.encoding.map_or(0, |encoding| encoding.value_size());
+        self.read_bytes(&mut value[..value_size])?;
+        EncodedValue::from_slice(&value[..value_size]).ok_or_else(|| format!("{}: invalid value", self.filename))
+    }
+
+    // the next value of a central record file, None after the last one
+    pub fn next_value(&mut self) -> Option<Result<EncodedValue, String>> {
+        if self.read >= self.count {
+            return None;
+        }
+        let value = self.read_value();
+        self.read += 1;
+        Some(value)
+    }
+
+    // the next client of a query record file and the values of its points, None after the last one
+    pub fn next_query(&mut self) -> Option<Result<(QueryId, Vec<EncodedValue>), String>> {
+        if self.read >= self.count {
+            return None;
+        }
+        let query = self.read_query();
+        self.read += 1;
+        Some(query)
+    }
+
+    fn read_query(&mut self) -> Result<(QueryId, Vec<EncodedValue>), String> {
+        let mut query_id = [0_u8; 8];
+        self.read_bytes(&mut query_id)?;
+        let mut query_size = [0_u8; 4];
+        self.read_bytes(&mut query_size)?;
+        let query_size = u32::from_be_bytes(query_size) as usize;
+        // a forged size fails on the truncated file instead of allocating it up front
+        let mut values: Vec<EncodedValue> = Vec::with_capacity(query_size.min(1 << 16));
+        for _ in 0..query_size {
+            values.push(self.read_value()?);
+        }
+        Ok((u64::from_be_bytes(query_id), values))
+    }
+}
+
+pub struct RecordWriter {
+    filename: String,
+    writer: BufWriter<File>,
+    kind: u8,
+    encoding: Option<Encoding>,
+    count: u64,
+}
+
+impl RecordWriter {
+    // the header is rewritten with the number of records by finish
+    pub fn create(filename: &str, kind: u8, encoding: Option<Encoding>) -> Result<Self, String> {
+        let file = File::create(filename).map_err(|e| format!("{}: {}", filename, e))?;
+        let mut this = RecordWriter { filename: filename.to_string(), writer: BufWriter::new(file), kind: kind, encoding: encoding, count: 0 };
+        let mut header: Vec<u8> = Vec::with_capacity(RECORD_HEADER_U8);
+        header.extend_from_slice(RECORD_MAGIC);
+        header.push(RECORD_FORMAT_VERSION);
+        header.push(kind);
+        header.push(encoding.map_or(0, |encoding| encoding.id()));
+        header.push(encoding.map_or(0, |encoding| encoding.value_size() as u8));
+        header.extend_from_slice(&0_u64.to_be_bytes());
+        this.write_bytes(&header)?;
+        Ok(this)
+    }
+
+    fn write_bytes(&mut self, bytes: &[u8]) -> Result<(), String> {
+        self.writer.write_all(bytes).map_err(|e| format!("{}: {}", self.filename, e))
+    }
+
+    fn write_value(&mut self, value: &EncodedValue) -> Result<(), String> {
+        match self.encoding {
+            Some(encoding) if encoding.value_size() == value.len() => self.write_bytes(value.as_ref()),
+            _ => Err(format!("{}: a value of {} bytes does not match the encoding of the file", self.filename, value.len())),
+        }
+    }
+
+    pub fn push_value(&mut self, value: &EncodedValue) -> Result<(), String> {
+        if self.kind != RECORDS_CENTRAL {
+            return Err(format!("{}: not a central record file", self.filename));
+        }
+        self.write_value(value)?;
+        self.count += 1;
+        Ok(())
+    }
+
+    pub fn push_query(&mut self, query_id: QueryId, values: &[EncodedValue]) -> Result<(), String> {
+        if self.kind != RECORDS_QUERY {
+            return Err(format!("{}: not a query record file", self.filename));
+        }
+        self.write_bytes(&query_id.to_be_bytes())?;
+        self.write_bytes(&(values.len() as u32).to_be_bytes())?;
+        for value in values.iter() {
+            self.write_value(value)?;
+        }
+        self.count += 1;
+        Ok(())
+    }
+
+    // the number of records written
+    pub fn finish(mut self) -> Result<u64, String> {
+        let filename = self.filename.clone();
+        let count = self.count;
+        self.writer.flush().map_err(|e| format!("{}: {}", filename, e))?;
+        let mut file = self.writer.into_inner().map_err(|e| format!("{}: {}", filename, e))?;
+        file.seek(SeekFrom::Start(RECORD_COUNT_OFFSET))
+            .and_then(|_| file.write_all(&count.to_be_bytes()))
+            .and_then(|_| file.sync_all())
+            .map_err(|e| format!("{}: {}", filename, e))?;
+        Ok(count)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use encoding::DatasetEncoding;
+    use util::test_dir;
+
+    fn values(encoding: Encoding, strings: &[&str]) -> Vec<EncodedValue> {
+        strings.iter().map(|string| encoding.decode(string)).collect()
+    }
+
+    fn read_values(filename: &str) -> Result<Vec<EncodedValue>, String> {
+        let mut reader = RecordReader::open(filename, RECORDS_CENTRAL)?;
+        let mut values: Vec<EncodedValue> = Vec::new();
+        while let Some(value) = reader.next_value() {
+            values.push(value?);
+        }
+        Ok(values)
+    }
+
+    #[test]
+    fn records_round_trip() {
+        let dir = test_dir("records");
+        let central = dir.join("central.bin");
+        let central = central.to_str().unwrap();
+        let central_values = values(Encoding::Gp10, &["xn76urx6rb0010", "xn76urx6rc0011"]);
+        let mut writer = RecordWriter::create(central, RECORDS_CENTRAL, Some(Encoding::Gp10)).unwrap();
+        for value in central_values.iter() {
+            writer.push_value(value).unwrap();
+        }
+        assert!(writer.push_query(1, &central_values).is_err());
+        assert_eq!(writer.finish().unwrap(), 2);
+        assert!(is_record_file(central));
+        assert_eq!(RecordReader::open(central, RECORDS_CENTRAL).unwrap().encoding(), Some(Encoding::Gp10));
+        assert_eq!(read_values(central).unwrap(), central_values);
+
+        let query = dir.join("query.bin");
+        let query = query.to_str().unwrap();
+        let queries = vec![
+            (7, values(Encoding::Th48, &["0123456701234567", "7654321076543210"])),
+            (8, Vec::new()),
+            (u64::MAX, values(Encoding::Th48, &["0000000000000001"])),
+        ];
+        let mut writer = RecordWriter::create(query, RECORDS_QUERY, Some(Encoding::Th48)).unwrap();
+        for &(query_id, ref query_values) in queries.iter() {
+            writer.push_query(query_id, query_values).unwrap();
+        }
+        // a value of another encoding
+        assert!(writer.push_query(9, &values(Encoding::Gp10, &["xn76urx6rb0010"])).is_err());
+        writer.finish().unwrap();
+        let mut reader = RecordReader::open(query, RECORDS_QUERY).unwrap();
+        assert_eq!(reader.count(), 3);
+        let mut read: Vec<(QueryId, Vec<EncodedValue>)> = Vec::new();
+        while let Some(query) = reader.next_query() {
+            read.push(query.unwrap());
+        }
+        assert_eq!(read, queries);
+
+        let empty = dir.join("empty.bin");
+        let empty = empty.to_str().unwrap();
+        assert_eq!(RecordWriter::create(empty, RECORDS_CENTRAL, None).unwrap().finish().unwrap(), 0);
+        assert_eq!(read_values(empty).unwrap(), Vec::new());
+    }
+
+    #[test]
+    fn bad_headers_are_rejected() {
+        let dir = test_dir("records-header");
+        let filename = dir.join("central.bin");
+        let filename = filename.to_str().unwrap();
+        let mut writer = RecordWriter::create(filename, RECORDS_CENTRAL, Some(Encoding::Gp10)).unwrap();
+        for value in values(Encoding::Gp10, &["xn76urx6rb0010", "xn76urx6rc0011"]).iter() {
+            writer.push_value(value).unwrap();
+        }
+        writer.finish().unwrap();
+        let bytes = fs::read(filename).unwrap();
+        let modified = |offset: usize, byte: u8| -> String {
+            let mut bytes = bytes.clone();
+            bytes[offset] = byte;
+            let filename = dir.join(format!("modified-{}-{}.bin", offset, byte));
+            fs::write(&filename, &bytes).unwrap();
+            filename.to_str().unwrap().to_string()
+        };
+
+        let not_records = modified(0, b'X');
+        assert!(!is_record_file(&not_records));
+        assert!(RecordReader::open(&not_records, RECORDS_CENTRAL).err().unwrap().contains("not a record file"));
+        assert!(RecordReader::open(&modified(4, RECORD_FORMAT_VERSION + 1), RECORDS_CENTRAL).err().unwrap().contains("version"));
+        assert!(RecordReader::open(filename, RECORDS_QUERY).err().unwrap().contains("where query records were expected"));
+        assert!(RecordReader::open(&modified(6, 0xff), RECORDS_CENTRAL).err().unwrap().contains("unknown encoding"));
+        // the value size has to be the one of the encoding
+        assert!(RecordReader::open(&modified(7, 6), RECORDS_CENTRAL).err().unwrap().contains("unknown encoding"));
+        assert!(RecordReader::open(&modified(6, 0), RECORDS_CENTRAL).err().unwrap().contains("without an encoding"));
+        // more records than the file has
+        assert!(read_values(&modified(15, 3)).err().unwrap().contains("truncated after 2 records"));
+        let short = dir.join("short.bin");
+        fs::write(&short, &bytes[..RECORD_HEADER_U8 - 1]).unwrap();
+        assert!(RecordReader::open(short.to_str().unwrap(), RECORDS_CENTRAL).is_err());
+    }
+}
@@ -0,0 +1,286 @@
+use std::env;
+use std::collections::HashMap;
+use sgx_types::*;
+use sgx_ucrypto::*;
+use hex;
+use ecalls::{ start_key_exchange, finish_key_exchange, finish_authority_key_exchange };
+use pct_core::primitive::{ QueryId, SessionKey };
+use pct_core::constant::{ SESSION_KEY_LABEL, CONFIRMATION_KEY_LABEL, NONCE_SIZE_U8, TAG_SIZE_U8, SEALED_OVERHEAD_U8 };
+use util::fill_random;
+
+// secure channel key of every client
+pub type SessionKeys = HashMap<QueryId, SessionKey>;
+
+/*
+Trait QuoteVerifier
+    Decides whether the report returned by start_key_exchange comes from a
+    trusted enclave. A production deployment plugs in an IAS / DCAP backed
+    verifier here; MockQuoteVerifier only checks the handshake binding and is
+    meant for SGX simulation mode.
+*/
+pub trait QuoteVerifier {
+    // target of the enclave report (the quoting enclave in hardware mode)
+    fn target_info(&self) -> sgx_target_info_t;
+
+    fn verify(&self, report: &sgx_report_t, expected_report_data: &sgx_report_data_t) -> SgxResult<()>;
+}
+
+#[derive(Clone, Default, Debug)]
+pub struct MockQuoteVerifier {
+    expected_mr_enclave: Option<sgx_measurement_t>,
+}
+
+impl MockQuoteVerifier {
+    pub fn new(expected_mr_enclave: Option<sgx_measurement_t>) -> Self {
+        MockQuoteVerifier { expected_mr_enclave: expected_mr_enclave }
+    }
+}
+
+impl QuoteVerifier for MockQuoteVerifier {
+    fn target_info(&self) -> sgx_target_info_t {
+        sgx_target_info_t::default()
+    }
+
+    fn verify(&self, report: &sgx_report_t, expected_report_data: &sgx_report_data_t) -> SgxResult<()> {
+        if report.body.report_data.d[..] != expected_report_data.d[..] {
+            return Err(sgx_status_t::SGX_ERROR_INVALID_SIGNATURE);
+        }
+        match self.expected_mr_enclave {
+            Some(ref mr_enclave) if mr_enclave.m != report.body.mr_enclave.m => {
+                Err(sgx_status_t::SGX_ERROR_INVALID_SIGNATURE)
+            },
+            _ => Ok(()),
+        }
+    }
+}
+
+/*
+    PCT_QUOTE_VERIFIER selects the verifier (only "mock" for now)
+    PCT_MRENCLAVE optionally pins the expected MRENCLAVE (hex)
+*/
+pub fn quote_verifier_from_env() -> Option<Box<dyn QuoteVerifier>> {
+    let name = env::var("PCT_QUOTE_VERIFIER").unwrap_or_else(|_| "mock".to_string());
+    match name.as_ref() {
+        "mock" => {
+            let expected_mr_enclave = env::var("PCT_MRENCLAVE").ok().map(|mr_enclave_hex| {
+                let decoded = hex::decode(mr_enclave_hex).expect("PCT_MRENCLAVE must be a hex string!");
+                let mut mr_enclave = sgx_measurement_t::default();
+                mr_enclave.m.copy_from_slice(&decoded);
+                mr_enclave
+            });
+            Some(Box::new(MockQuoteVerifier::new(expected_mr_enclave)))
+        },
+        _ => None,
+    }
+}
+
+/*
+Type ClientSession
+    Client side of the key exchange in enclave/src/secure_channel.rs
+*/
+pub struct ClientSession {
+    query_id: QueryId,
+    private_key: sgx_ec256_private_t,
+    public_key: sgx_ec256_public_t,
+}
+
+impl ClientSession {
+    pub fn new(query_id: QueryId) -> SgxResult<Self> {
+        let ecc_handle = SgxEccHandle::new();
+        ecc_handle.open()?;
+        let (private_key, public_key) = ecc_handle.create_key_pair()?;
+        ecc_handle.close()?;
+        Ok(ClientSession {
+            query_id: query_id,
+            private_key: private_key,
+            public_key: public_key,
+        })
+    }
+
+    pub fn public_key(&self) -> &sgx_ec256_public_t {
+        &self.public_key
+    }
+
+    // verify the enclave report, then derive (session key, key confirmation tag)
+    pub fn complete(
+        &mut self,
+        enclave_public_key: &sgx_ec256_public_t,
+        report: &sgx_report_t,
+        verifier: &dyn QuoteVerifier,
+    ) -> SgxResult<(SessionKey, sgx_cmac_128bit_tag_t)> {
+        let transcript = self.transcript(enclave_public_key);
+        let hash = rsgx_sha256_slice(&transcript)?;
+        let mut expected_report_data = sgx_report_data_t::default();
+        expected_report_data.d[..SGX_SHA256_HASH_SIZE].copy_from_slice(&hash);
+        verifier.verify(report, &expected_report_data)?;
+
+        let ecc_handle = SgxEccHandle::new();
+        ecc_handle.open()?;
+        let shared_key = ecc_handle.compute_shared_dhkey(&self.private_key, enclave_public_key);
+        self.private_key.r = [0; SGX_ECP256_KEY_SIZE];
+        ecc_handle.close()?;
+        let shared_key = shared_key?;
+
+        let session_key = derive_key(&shared_key, SESSION_KEY_LABEL)?;
+        let confirmation_key = derive_key(&shared_key, CONFIRMATION_KEY_LABEL)?;
+        let key_confirmation = rsgx_rijndael128_cmac_slice(&confirmation_key, &transcript)?;
+        Ok((session_key, key_confirmation))
+    }
+
+    // ECDSA signature over the handshake transcript, see SecureChannelStore::finish_authority
+    pub fn sign(&self, enclave_public_key: &sgx_ec256_public_t, signing_key: &sgx_ec256_private_t) -> SgxResult<sgx_ec256_signature_t> {
+        let ecc_handle = SgxEccHandle::new();
+        ecc_handle.open()?;
+        let signature = ecc_handle.ecdsa_sign_slice(&self.transcript(enclave_public_key), signing_key);
+        ecc_handle.close()?;
+        signature
+    }
+
+    fn transcript(&self, enclave_public_key: &sgx_ec256_public_t) -> Vec<u8> {
+        let mut transcript: Vec<u8> = Vec::with_capacity(8 + 4*SGX_ECP256_KEY_SIZE);
+        transcript.extend_from_slice(&self.query_id.to_be_bytes());
+        transcript.extend_from_slice(&self.public_key.gx);
+        transcript.extend_from_slice(&self.public_key.gy);
+        transcript.extend_from_slice(&enclave_public_key.gx);
+        transcript.extend_from_slice(&enclave_public_key.gy);
+        transcript
+    }
+}
+
+fn derive_key(shared_key: &sgx_ec256_dh_shared_t, label: &[u8]) -> SgxResult<SessionKey> {
+    let cmac_key: sgx_cmac_128bit_key_t = [0; SGX_CMAC_KEY_SIZE];
+    let key_derivation_key = rsgx_rijndael128_cmac_slice(&cmac_key, &shared_key.s)?;
+
+    let mut derivation_buffer: Vec<u8> = Vec::with_capacity(label.len() + 4);
+    derivation_buffer.push(0x01);
+    derivation_buffer.extend_from_slice(label);
+    derivation_buffer.extend_from_slice(&[0x00, 0x80, 0x00]);
+    rsgx_rijndael128_cmac_slice(&key_derivation_key, &derivation_buffer)
+}
+
+// In this prototype the host plays every client, so it runs all handshakes itself.
+pub fn establish_secure_channels(
+    eid: sgx_enclave_id_t,
+    query_id_list: &Vec<QueryId>,
+    verifier: &dyn QuoteVerifier,
+) -> SgxResult<SessionKeys> {
+    let mut session_keys: SessionKeys = HashMap::with_capacity(query_id_list.len());
+    let target_info = verifier.target_info();
+    for query_id in query_id_list.iter() {
+        let session_key = establish_secure_channel(eid, *query_id, &target_info, verifier, None)?;
+        session_keys.insert(*query_id, session_key);
+    }
+    Ok(session_keys)
+}
+
+// one handshake, also run by the health authority under AUTHORITY_ID, which signs
+// the transcript with signing_key (see app/src/authority.rs)
+pub fn establish_secure_channel(
+    eid: sgx_enclave_id_t,
+    query_id: QueryId,
+    target_info: &sgx_target_info_t,
+    verifier: &dyn QuoteVerifier,
+    signing_key: Option<&sgx_ec256_private_t>,
+) -> SgxResult<SessionKey> {
+    let mut session = ClientSession::new(query_id)?;
+
+    let mut retval = sgx_status_t::SGX_SUCCESS;
+    let mut enclave_public_key = sgx_ec256_public_t::default();
+    let mut report = sgx_report_t::default();
+    let result = unsafe {
+        start_key_exchange(
+            eid,
+            &mut retval,
+            query_id,
+            session.public_key() as * const sgx_ec256_public_t,
+            target_info as * const sgx_target_info_t,
+            &mut enclave_public_key as * mut sgx_ec256_public_t,
+            &mut report as * mut sgx_report_t
+        )
+    };
+    if result != sgx_status_t::SGX_SUCCESS { return Err(result); }
+    if retval != sgx_status_t::SGX_SUCCESS { return Err(retval); }
+
+    let (session_key, key_confirmation) = session.complete(&enclave_public_key, &report, verifier)?;
+    let result = match signing_key {
+        Some(signing_key) => {
+            let signature = session.sign(&enclave_public_key, signing_key)?;
+            unsafe {
+                finish_authority_key_exchange(
+                    eid,
+                    &mut retval,
+                    &key_confirmation as * const sgx_cmac_128bit_tag_t,
+                    &signature as * const sgx_ec256_signature_t
+                )
+            }
+        },
+        None => unsafe {
+            finish_key_exchange(
+                eid,
+                &mut retval,
+                query_id,
+                &key_confirmation as * const sgx_cmac_128bit_tag_t
+            )
+        },
+    };
+    if result != sgx_status_t::SGX_SUCCESS { return Err(result); }
+    if retval != sgx_status_t::SGX_SUCCESS { return Err(retval); }
+
+    Ok(session_key)
+}
+
+fn channel_aad(query_id: QueryId, direction: u8) -> [u8; 9] {
+    let mut aad = [0_u8; 9];
+    aad[..8].copy_from_slice(&query_id.to_be_bytes());
+    aad[8] = direction;
+    aad
+}
+
+// nonce || AES-GCM(plaintext) || tag, same framing as the enclave side
+pub fn seal(
+    session_key: &SessionKey,
+    query_id: QueryId,
+    direction: u8,
+    plaintext: &[u8],
+) -> SgxResult<Vec<u8>> {
+    let mut nonce = [0_u8; NONCE_SIZE_U8];
+    fill_random(&mut nonce);
+    let mut sealed: Vec<u8> = vec![0; SEALED_OVERHEAD_U8 + plaintext.len()];
+    let mut tag: sgx_aes_gcm_128bit_tag_t = [0; TAG_SIZE_U8];
+    rsgx_rijndael128GCM_encrypt(
+        session_key,
+        plaintext,
+        &nonce,
+        &channel_aad(query_id, direction),
+        &mut sealed[NONCE_SIZE_U8..NONCE_SIZE_U8 + plaintext.len()],
+        &mut tag
+    )?;
+    sealed[..NONCE_SIZE_U8].copy_from_slice(&nonce);
+    sealed[NONCE_SIZE_U8 + plaintext.len()..].copy_from_slice(&tag);
+    Ok(sealed)
+}
+
+// returns SGX_ERROR_MAC_MISMATCH if the message has been modified on the way
+pub fn open(
+    session_key: &SessionKey,
+    query_id: QueryId,
+    direction: u8,
+    sealed: &[u8],
+) -> SgxResult<Vec<u8>> {
+    if sealed.len() < SEALED_OVERHEAD_U8 {
+        return Err(sgx_status_t::SGX_ERROR_INVALID_PARAMETER);
+    }
+    let body_end = sealed.len() - TAG_SIZE_U8;
+    let mut tag: sgx_aes_gcm_128bit_tag_t = [0; TAG_SIZE_U8];
+    tag.copy_from_slice(&sealed[body_end..]);
+    let mut plaintext: Vec<u8> = vec![0; body_end - NONCE_SIZE_U8];
+    rsgx_rijndael128GCM_decrypt(
+        session_key,
+        &sealed[NONCE_SIZE_U8..body_end],
+        &sealed[..NONCE_SIZE_U8],
+        &channel_aad(query_id, direction),
+        &tag,
+        &mut plaintext
+    )?;
+    Ok(plaintext)
+}
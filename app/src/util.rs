@@ -1,47 +1,8 @@
 use std::fs::File;
-use std::io::{Write};
-use sgx_types::*;
+use std::io::{Read, Write};
 use std::time::{Duration, Instant};
 use std::collections::HashMap;
 
-#[cfg(feature = "gp10")]
-pub const ENCODEDVALUE_SIZE: usize = 14;
-
-#[cfg(feature = "th72")]
-pub const ENCODEDVALUE_SIZE: usize = 9;
-
-#[cfg(feature = "th48")]
-pub const ENCODEDVALUE_SIZE: usize = 6;
-
-#[cfg(feature = "th54")]
-pub const ENCODEDVALUE_SIZE: usize = 7;
-
-#[cfg(feature = "th60")]
-pub const ENCODEDVALUE_SIZE: usize = 8;
-
-pub const SGXSSL_CTR_BITS: u32 = 128;
-pub const COUNTER_BLOCK: [u8; 16] = [0; 16];
-
-
-pub type sgx_aes_ctr_128bit_key_t = [uint8_t; 16];
-extern "C" {
-    pub fn sgx_aes_ctr_decrypt(
-        p_key: *const sgx_aes_ctr_128bit_key_t,
-        p_src: *const uint8_t,
-        src_len: uint32_t,
-        p_ctr: *const uint8_t,
-        ctr_inc_bits: uint32_t,
-        p_dst: *mut uint8_t) -> u32;
-        
-    pub fn sgx_aes_ctr_encrypt(
-        p_key: *const sgx_aes_ctr_128bit_key_t,
-        p_src: *const uint8_t,
-        src_len: uint32_t,
-        p_ctr: *const uint8_t,
-        ctr_inc_bits: uint32_t,
-        p_dst: *mut uint8_t) -> u32;
-}
-
 #[derive(Clone, Default, Debug)]
 pub struct Clocker<'a> {
     data: HashMap<&'a str, Instant>,
@@ -93,7 +54,7 @@ pub fn write_to_file(
     query_data_file: String,
     client_size: usize,
     query_size: usize,
-    threashould: usize,
+    threashould: String,
     clocker: Clocker,
 ) {
     let mut file = File::create(file_name).unwrap();
@@ -135,6 +96,27 @@ pub fn get_timestamp() -> String {
     }
 }
 
+// "YYYY-MM-DD hh:mm" in UTC, days to civil date as in H. Hinnant's chrono algorithms
+pub fn format_unixepoch(unixepoch: u64) -> String {
+    let days = (unixepoch / 86400) as i64;
+    let seconds = unixepoch % 86400;
+    let z = days + 719468;
+    let era = z / 146097;
+    let doe = z - era * 146097;
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+    format!("{:04}-{:02}-{:02} {:02}:{:02}", year, month, day, seconds / 3600, seconds % 3600 / 60)
+}
+
+pub fn fill_random(buf: &mut [u8]) {
+    let mut urandom = File::open("/dev/urandom").unwrap();
+    urandom.read_exact(buf).unwrap();
+}
+
 pub fn query_id_from_u8(query_id: &[u8]) -> u64 {
     let mut array: [u8; 8] = [0; 8];
     array.copy_from_slice(query_id);
@@ -161,6 +143,12 @@ pub fn base8decode(base8: String) -> Vec<u8> {
     vec
 }
 
+// inverse of base8decode, the first chars * 3 bits of bytes as base8 digits
+pub fn base8encode(bytes: &[u8], chars: usize) -> String {
+    let bit = |i: usize| (bytes[i / 8] >> (i % 8)) & 1;
+    (0..chars).map(|c| (b'0' + (bit(c*3) << 2 | bit(c*3+1) << 1 | bit(c*3+2))) as char).collect()
+}
+
 fn bitVecToByte(bitvec: &[u8]) -> u8 {
     assert_eq!(bitvec.len() % 8, 0);
     let byte: u8 = 
@@ -189,3 +177,12 @@ fn base8map(byte: u8) -> Vec<u8> {
     }
 }
 
+
+// an empty directory of its own for one test, under the temp directory
+#[cfg(test)]
+pub fn test_dir(name: &str) -> ::std::path::PathBuf {
+    let dir = ::std::env::temp_dir().join(format!("pct-app-{}-{}", ::std::process::id(), name));
+    let _ = ::std::fs::remove_dir_all(&dir);
+    ::std::fs::create_dir_all(&dir).unwrap();
+    dir
+}
@@ -0,0 +1,15 @@
+use sgx_types::*;
+
+// ECDSA P-256 key the health authority signs its key exchange with, gx || gy little endian
+// as written by bin/app authority-keygen. Read from the file PCT_AUTHORITY_PUBLIC_KEY names
+// when the enclave is built (see Makefile), so it is part of MRENCLAVE and the enclave only
+// takes a chunk key from the authority it has been built for. The signing key stays with
+// the authority and is never part of the build.
+const AUTHORITY_PUBLIC_KEY_U8: &[u8; 2 * SGX_ECP256_KEY_SIZE] = include_bytes!(env!("PCT_AUTHORITY_PUBLIC_KEY"));
+
+pub fn authority_public_key() -> sgx_ec256_public_t {
+    let mut public_key = sgx_ec256_public_t::default();
+    public_key.gx.copy_from_slice(&AUTHORITY_PUBLIC_KEY_U8[..SGX_ECP256_KEY_SIZE]);
+    public_key.gy.copy_from_slice(&AUTHORITY_PUBLIC_KEY_U8[SGX_ECP256_KEY_SIZE..]);
+    public_key
+}
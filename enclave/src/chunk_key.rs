@@ -0,0 +1,48 @@
+use std::vec::Vec;
+use sgx_types::*;
+use sgx_tcrypto::*;
+use pct_core::constant::*;
+use pct_core::encoded_dictionary_buffer::ChunkCipher;
+
+/*
+Type ChunkKey
+    AES-128-GCM key the health authority seals its chunks with
+    (app/src/authority.rs). It only reaches the enclave through the authority's
+    own secure channel, after the authority has verified the enclave report.
+*/
+pub struct ChunkKey {
+    key: sgx_aes_gcm_128bit_key_t,
+}
+
+impl ChunkKey {
+    pub fn new(key: sgx_aes_gcm_128bit_key_t) -> Self {
+        ChunkKey { key: key }
+    }
+}
+
+impl ChunkCipher for ChunkKey {
+    fn open(&self, sealed_chunk: &[u8]) -> SgxResult<Vec<u8>> {
+        if sealed_chunk.len() < SEALED_OVERHEAD_U8 {
+            return Err(sgx_status_t::SGX_ERROR_INVALID_PARAMETER);
+        }
+        let body_end = sealed_chunk.len() - TAG_SIZE_U8;
+        let mut tag: sgx_aes_gcm_128bit_tag_t = [0; TAG_SIZE_U8];
+        tag.copy_from_slice(&sealed_chunk[body_end..]);
+        let mut chunk: Vec<u8> = vec![0; body_end - NONCE_SIZE_U8];
+        rsgx_rijndael128GCM_decrypt(
+            &self.key,
+            &sealed_chunk[NONCE_SIZE_U8..body_end],
+            &sealed_chunk[..NONCE_SIZE_U8],
+            CHUNK_AAD,
+            &tag,
+            &mut chunk
+        )?;
+        Ok(chunk)
+    }
+}
+
+impl Drop for ChunkKey {
+    fn drop(&mut self) {
+        self.key = [0; CHUNK_KEY_SIZE];
+    }
+}
@@ -26,48 +26,47 @@ extern crate sgx_trts;
 #[cfg(not(target_env = "sgx"))]
 #[macro_use]
 extern crate sgx_tstd as std;
-extern crate sgx_fst as fst;
 extern crate sgx_tcrypto;
-extern crate bincode;
+extern crate sgx_tse;
+extern crate pct_core;
 
 use sgx_types::*;
-use sgx_tcrypto::*;
 use std::vec::Vec;
-use std::cell::RefCell;
 use std::slice;
 use std::boxed::Box;
 use std::time::{Instant};
 use std::untrusted::time::InstantEx;
 use std::sync::atomic::{AtomicPtr, Ordering};
-
-mod constant;
-mod primitive;
-mod utils;
-mod encoded_query_rep;
-mod period;
-mod query_result;
-mod encoded_query_buffer;
-mod encoded_result_buffer;
-mod mapped_encoded_query_buffer;
-mod encoded_dictionary_buffer;
-mod encoded_hash_table;
-mod encode_finite_state_transducer;
-
-use constant::*;
-use primitive::*;
-use encoded_query_buffer::EncodedQueryBuffer;
-use encoded_result_buffer::EncodedResultBuffer;
-use mapped_encoded_query_buffer::MappedEncodedQueryBuffer;
-use encoded_dictionary_buffer::EncodedDictionaryBuffer;
+use std::sync::{SgxMutex, SgxMutexGuard};
+use sgx_trts::enclave::SgxGlobalData;
+
+mod secure_channel;
+mod chunk_key;
+mod authority_key;
+
+// the tracing logic itself lives in pct_core, shared with the native backend of the app
+use pct_core::constant::*;
+use pct_core::primitive::*;
+use pct_core::encoding::Encoding;
+use pct_core::utils::zeroize;
+use pct_core::contact_tracer::ContactTracer;
+use pct_core::query_result::plain_response_size;
+use pct_core::session::{ SessionTable, SessionId };
+use pct_core::encoded_dictionary_buffer::LookupMode;
+use pct_core::wire::{ read_frame, write_frame, Capabilities, CAPABILITIES_U8, FRAME_QUERIES, FRAME_RESPONSE };
+use secure_channel::SecureChannelStore;
+use chunk_key::ChunkKey;
 
 /* 
 SGXのステート
     ステートは全部グローバル変数に持ってヒープにメモリを確保する
 */
 
-pub static ENCODED_QUERY_BUFFER: AtomicPtr<()> = AtomicPtr::new(0 as * mut ());
-pub fn get_ref_encoded_query_buffer() -> Option<&'static RefCell<EncodedQueryBuffer>> {
-    let ptr = ENCODED_QUERY_BUFFER.load(Ordering::SeqCst) as * mut RefCell<EncodedQueryBuffer>;
+// one query batch, result and contact rules per session, see pct_core::session.
+// Behind a mutex, as private_encode_contact_trace runs on several TCS at once
+pub static SESSION_TABLE: AtomicPtr<()> = AtomicPtr::new(0 as * mut ());
+pub fn get_ref_session_table() -> Option<&'static SgxMutex<SessionTable>> {
+    let ptr = SESSION_TABLE.load(Ordering::SeqCst) as * mut SgxMutex<SessionTable>;
     if ptr.is_null() {
         None
     } else {
@@ -75,19 +74,87 @@ pub fn get_ref_encoded_query_buffer() -> Option<&'static RefCell<EncodedQueryBuf
     }
 }
 
-pub static MAPPED_ENCODED_QUERY_BUFFER: AtomicPtr<()> = AtomicPtr::new(0 as * mut ());
-pub fn get_ref_mapped_encoded_query_buffer() -> Option<&'static RefCell<MappedEncodedQueryBuffer>> {
-    let ptr = MAPPED_ENCODED_QUERY_BUFFER.load(Ordering::SeqCst) as * mut RefCell<MappedEncodedQueryBuffer>;
-    if ptr.is_null() {
-        None
-    } else {
-        Some(unsafe { &* ptr })
+// initialized by the first open_session, before any chunk is traced
+fn _init_session_table() {
+    if get_ref_session_table().is_some() {
+        return;
+    }
+    // chunks have to be sealed by the health authority, plaintext ones are for development
+    #[cfg(feature = "unsealed_chunks")]
+    let table = SessionTable::with_plaintext_chunks();
+    #[cfg(not(feature = "unsealed_chunks"))]
+    let table = SessionTable::new();
+    let table_box = Box::new(SgxMutex::<SessionTable>::new(table));
+    let table_ptr = Box::into_raw(table_box);
+    // another TCS may have been first
+    if SESSION_TABLE.compare_exchange(0 as * mut (), table_ptr as *mut (), Ordering::SeqCst, Ordering::SeqCst).is_err() {
+        drop(unsafe { Box::from_raw(table_ptr) });
+    }
+}
+
+// SGX_ERROR_INVALID_PARAMETER before the first open_session
+fn lock_session_table() -> SgxResult<SgxMutexGuard<'static, SessionTable>> {
+    match get_ref_session_table() {
+        Some(table) => table.lock().map_err(|_| sgx_status_t::SGX_ERROR_UNEXPECTED),
+        None => Err(sgx_status_t::SGX_ERROR_INVALID_PARAMETER),
+    }
+}
+
+// runs f on the batch of session_id, SGX_ERROR_INVALID_PARAMETER if open_session has not returned it
+fn with_session<F>(session_id: SessionId, f: F) -> sgx_status_t
+    where F: FnOnce(&mut ContactTracer) -> SgxResult<()>
+{
+    let mut table = match lock_session_table() {
+        Ok(table) => table,
+        Err(e) => { return e; }
+    };
+    match table.session(session_id).and_then(f) {
+        Ok(()) => sgx_status_t::SGX_SUCCESS,
+        Err(e) => e,
+    }
+}
+
+/*
+    Sessions
+    every batch of queries lives in its own session until close_session,
+    which zeroizes and frees its decrypted queries and results
+*/
+#[no_mangle]
+pub extern "C" fn open_session(
+    session_id: *mut u64,
+) -> sgx_status_t {
+    if session_id.is_null() {
+        return sgx_status_t::SGX_ERROR_INVALID_PARAMETER;
+    }
+    _init_session_table();
+    let opened = lock_session_table().and_then(|mut table| table.open());
+    match opened {
+        Ok(id) => {
+            unsafe { *session_id = id; }
+            sgx_status_t::SGX_SUCCESS
+        },
+        Err(e) => e,
+    }
+}
+
+#[no_mangle]
+pub extern "C" fn close_session(
+    session_id: u64,
+) -> sgx_status_t {
+    let mut table = match lock_session_table() {
+        Ok(table) => table,
+        Err(e) => { return e; }
+    };
+    match table.close(session_id) {
+        Ok(()) => sgx_status_t::SGX_SUCCESS,
+        Err(e) => e,
     }
 }
 
-pub static ENCODED_RESULT_BUFFER: AtomicPtr<()> = AtomicPtr::new(0 as * mut ());
-pub fn get_ref_encoded_result_buffer() -> Option<&'static RefCell<EncodedResultBuffer>> {
-    let ptr = ENCODED_RESULT_BUFFER.load(Ordering::SeqCst) as * mut RefCell<EncodedResultBuffer>;
+// session keys outlive query batches, so this is initialized once on the first handshake
+pub static SECURE_CHANNEL_STORE: AtomicPtr<()> = AtomicPtr::new(0 as * mut ());
+pub fn get_ref_secure_channel_store() -> Option<&'static SgxMutex<SecureChannelStore>> {
+    let ptr = SECURE_CHANNEL_STORE.load(Ordering::SeqCst) as * mut SgxMutex<SecureChannelStore>;
     if ptr.is_null() {
         None
     } else {
@@ -95,18 +162,214 @@ pub fn get_ref_encoded_result_buffer() -> Option<&'static RefCell<EncodedResultB
     }
 }
 
+fn _init_secure_channel_store() {
+    if get_ref_secure_channel_store().is_some() {
+        return;
+    }
+    let store = SecureChannelStore::new();
+    let store_box = Box::new(SgxMutex::<SecureChannelStore>::new(store));
+    let store_ptr = Box::into_raw(store_box);
+    if SECURE_CHANNEL_STORE.compare_exchange(0 as * mut (), store_ptr as *mut (), Ordering::SeqCst, Ordering::SeqCst).is_err() {
+        drop(unsafe { Box::from_raw(store_ptr) });
+    }
+}
+
+// SGX_ERROR_INVALID_STATE before the first handshake
+fn lock_secure_channel_store() -> SgxResult<SgxMutexGuard<'static, SecureChannelStore>> {
+    match get_ref_secure_channel_store() {
+        Some(store) => store.lock().map_err(|_| sgx_status_t::SGX_ERROR_UNEXPECTED),
+        None => Err(sgx_status_t::SGX_ERROR_INVALID_STATE),
+    }
+}
+
+/*
+    Capabilities
+    wire format version, what this build reads, its heap size and TCSNum, which the
+    host sizes the chunks and the threads tracing them by, see pct_core::wire
+*/
+#[no_mangle]
+pub extern "C" fn get_capabilities(
+    capabilities: *mut u8,
+    capabilities_size: usize,
+) -> sgx_status_t {
+    if capabilities.is_null() || capabilities_size != CAPABILITIES_U8 {
+        return sgx_status_t::SGX_ERROR_INVALID_PARAMETER;
+    }
+    let mut current = Capabilities::current();
+    let global_data = SgxGlobalData::new();
+    current.heap_size = global_data.get_heap_size() as u64;
+    current.max_threads = global_data.get_tcs_max_num();
+    let bytes = current.to_be_bytes();
+    unsafe {
+        for i in 0..CAPABILITIES_U8 {
+            *capabilities.offset(i as isize) = bytes[i];
+        }
+    }
+    sgx_status_t::SGX_SUCCESS
+}
+
+/*
+    Contact rules
+    can be changed between runs without rebuilding or re-signing the enclave
+*/
+#[no_mangle]
+pub extern "C" fn set_contact_rules(
+    session_id: u64,
+    rules_u8: *const u8,
+    rules_u8_size: usize,
+) -> sgx_status_t {
+    let rules_vec: Vec<u8> = unsafe {
+        slice::from_raw_parts(rules_u8, rules_u8_size)
+    }.to_vec();
+    with_session(session_id, |tracer| tracer.set_contact_rules(&rules_vec))
+}
+
+/*
+    Lookup mode
+    oblivious trades speed for memory accesses that do not depend on the query values.
+    indexed builds the responses in time that depends on the hits, it is native-only
+    (not in Capabilities::current) and refused with SGX_ERROR_FEATURE_NOT_SUPPORTED
+*/
+#[no_mangle]
+pub extern "C" fn set_lookup_mode(
+    session_id: u64,
+    mode_id: u8,
+) -> sgx_status_t {
+    let mode = match LookupMode::from_id(mode_id) {
+        Some(LookupMode::Indexed) => { return sgx_status_t::SGX_ERROR_FEATURE_NOT_SUPPORTED; }
+        None => { return sgx_status_t::SGX_ERROR_INVALID_PARAMETER; }
+        Some(mode) => mode,
+    };
+    with_session(session_id, |tracer| {
+        tracer.set_lookup_mode(mode);
+        Ok(())
+    })
+}
+
+/*
+    Key exchange
+    1. client sends its ephemeral ECDH public key
+    2. enclave answers with its ephemeral public key and a report binding both keys to query_id
+       (the host turns the report into a quote, the client verifies it)
+    3. client sends a CMAC over the handshake transcript to prove it derived the same key
+*/
+#[no_mangle]
+pub extern "C" fn start_key_exchange(
+    query_id: u64,
+    client_public_key: *const sgx_ec256_public_t,
+    target_info: *const sgx_target_info_t,
+    enclave_public_key: *mut sgx_ec256_public_t,
+    report: *mut sgx_report_t,
+) -> sgx_status_t {
+    if client_public_key.is_null() || target_info.is_null() || enclave_public_key.is_null() || report.is_null() {
+        return sgx_status_t::SGX_ERROR_INVALID_PARAMETER;
+    }
+    _init_secure_channel_store();
+    let mut store = match lock_secure_channel_store() {
+        Ok(store) => store,
+        Err(e) => { return e; }
+    };
+    let ret = unsafe { store.start(query_id, &*client_public_key, &*target_info) };
+    match ret {
+        Ok((public_key, created_report)) => {
+            unsafe {
+                *enclave_public_key = public_key;
+                *report = created_report;
+            }
+            sgx_status_t::SGX_SUCCESS
+        },
+        Err(e) => e,
+    }
+}
+
+#[no_mangle]
+pub extern "C" fn finish_key_exchange(
+    query_id: u64,
+    key_confirmation: *const sgx_cmac_128bit_tag_t,
+) -> sgx_status_t {
+    if key_confirmation.is_null() {
+        return sgx_status_t::SGX_ERROR_INVALID_PARAMETER;
+    }
+    let mut store = match lock_secure_channel_store() {
+        Ok(store) => store,
+        Err(e) => { return e; }
+    };
+    match unsafe { store.finish(query_id, &*key_confirmation) } {
+        Ok(()) => sgx_status_t::SGX_SUCCESS,
+        Err(e) => e,
+    }
+}
+
+// the handshake of AUTHORITY_ID, signed by the health authority (see SecureChannelStore::finish_authority)
+#[no_mangle]
+pub extern "C" fn finish_authority_key_exchange(
+    key_confirmation: *const sgx_cmac_128bit_tag_t,
+    signature: *const sgx_ec256_signature_t,
+) -> sgx_status_t {
+    if key_confirmation.is_null() || signature.is_null() {
+        return sgx_status_t::SGX_ERROR_INVALID_PARAMETER;
+    }
+    let mut store = match lock_secure_channel_store() {
+        Ok(store) => store,
+        Err(e) => { return e; }
+    };
+    match unsafe { store.finish_authority(&*key_confirmation, &*signature) } {
+        Ok(()) => sgx_status_t::SGX_SUCCESS,
+        Err(e) => e,
+    }
+}
+
+/*
+    Chunk key of the health authority
+    sealed under the session of AUTHORITY_ID, which only the authority holding the
+    key pinned in authority_key.rs can finish, after verifying the enclave report.
+    From then on every chunk has to be sealed with it, so the host never handles
+    patient trajectories in the clear. The key is shared by every session and only
+    replaced if the authority sets CHUNK_KEY_REPLACE, SGX_ERROR_INVALID_STATE otherwise.
+*/
+#[no_mangle]
+pub extern "C" fn provision_chunk_key(
+    sealed_key: *const u8,
+    sealed_key_size: usize,
+) -> sgx_status_t {
+    let sealed_key_vec: Vec<u8> = unsafe {
+        slice::from_raw_parts(sealed_key, sealed_key_size)
+    }.to_vec();
+    let shared_key: SessionKey = match lock_secure_channel_store().and_then(|store| store.session_key(AUTHORITY_ID)) {
+        Ok(key) => key,
+        Err(e) => { return e; }
+    };
+
+    let mut message = [0_u8; CHUNK_KEY_MESSAGE_U8];
+    let ret = secure_channel::open(&shared_key, AUTHORITY_ID, DIRECTION_CHUNK_KEY, &sealed_key_vec, &mut message);
+    match ret { Ok(()) => {}, Err(e) => { return e; } }
+    let replace = message[0] & CHUNK_KEY_REPLACE != 0;
+    let mut key = [0_u8; CHUNK_KEY_SIZE];
+    key.copy_from_slice(&message[1..]);
+    zeroize(&mut message);
+
+    _init_session_table();
+    let ret = lock_session_table().and_then(|mut table| table.set_chunk_cipher(Box::new(ChunkKey::new(key)), replace));
+    zeroize(&mut key);
+    match ret {
+        Ok(()) => sgx_status_t::SGX_SUCCESS,
+        Err(e) => e,
+    }
+}
+
 #[no_mangle]
 pub extern "C" fn upload_encoded_query_data(
+    session_id: u64,
     total_query_data: *const u8,
     total_size       : usize,
     client_size     : usize,
     query_id_list   : *const u64,
 ) -> sgx_status_t {
-    let whole_start = Instant::now();
-    let start = Instant::now();
-    _init_encoded_buffers();
-    let end = start.elapsed();
-    println!("[SGX CLOCK] {}:  {}.{:06} seconds", "buffers initialize", end.as_secs(), end.subsec_nanos() / 1_000);
+    // unknown sessions are rejected before anything is decrypted
+    let ret = with_session(session_id, |_| Ok(()));
+    if ret != sgx_status_t::SGX_SUCCESS {
+        return ret;
+    }
 
     let start = Instant::now();
     let total_query_data_vec: Vec<u8> = unsafe {
@@ -115,6 +378,18 @@ pub extern "C" fn upload_encoded_query_data(
     if total_query_data_vec.len() != total_size {
         return sgx_status_t::SGX_ERROR_INVALID_PARAMETER;
     }
+    // queries frame, see pct_core::wire
+    let (header, records) = match read_frame(&total_query_data_vec, FRAME_QUERIES) {
+        Ok(frame) => frame,
+        Err(e) => {
+            println!("[SGX] malformed queries frame: {}", e.as_str());
+            return e;
+        }
+    };
+    if header.count as usize != client_size {
+        return sgx_status_t::SGX_ERROR_INVALID_METADATA;
+    }
+    let encoding = header.encoding;
 
     let query_id_list_vec: Vec<u64> = unsafe {
         slice::from_raw_parts(query_id_list, client_size)
@@ -122,30 +397,23 @@ pub extern "C" fn upload_encoded_query_data(
     if query_id_list_vec.len() != client_size {
         return sgx_status_t::SGX_ERROR_INVALID_PARAMETER;
     }
+    // the session of the health authority is not a client
+    if query_id_list_vec.contains(&AUTHORITY_ID) {
+        return sgx_status_t::SGX_ERROR_INVALID_PARAMETER;
+    }
     let end = start.elapsed();
     println!("[SGX CLOCK] {}:  {}.{:06} seconds", "reading", end.as_secs(), end.subsec_nanos() / 1_000);
 
     /* decryption */
     let start = Instant::now();
 
-    let mut decrypted_query_data_vec: Vec<u8> = vec![1; total_query_data_vec.len()];
-    for (i, query_id) in query_id_list_vec.iter().enumerate() {
-        let counter_block: [u8; 16] = COUNTER_BLOCK;
-        let ctr_inc_bits: u32 = SGXSSL_CTR_BITS;
-
-        // Originally shared_key is derived by following Remote Attestation protocol.
-        // This is mock of shared key-based encryption.
-        let mut shared_key: [u8; 16] = [0; 16];
-        shared_key[..8].copy_from_slice(&query_id.to_be_bytes());
-        let current_cursor = i*QUERY_BYTES;
-        let ret = rsgx_aes_ctr_decrypt(
-            &shared_key,
-            &total_query_data_vec[current_cursor..current_cursor+QUERY_BYTES],
-            &counter_block,
-            ctr_inc_bits,
-            &mut decrypted_query_data_vec[current_cursor..current_cursor+QUERY_BYTES]
-        );
-        match ret { Ok(()) => {}, Err(_) => { return sgx_status_t::SGX_ERROR_UNEXPECTED; } }    
+    let mut decrypted_queries: Vec<Vec<u8>> = Vec::with_capacity(client_size);
+    let ret = open_queries(encoding, records, &query_id_list_vec, &mut decrypted_queries);
+    if let Err(e) = ret {
+        for decrypted_query in decrypted_queries.iter_mut() {
+            zeroize(decrypted_query);
+        }
+        return e;
     }
     let end = start.elapsed();
     println!("[SGX CLOCK] {}:  {}.{:06} seconds", "decrypt each queries", end.as_secs(), end.subsec_nanos() / 1_000);
@@ -153,42 +421,76 @@ pub extern "C" fn upload_encoded_query_data(
 
     /* for more optiizaton this part can be conducted in decryption phase together, but to measure each part */
     let start = Instant::now();
-    let mut query_buffer = get_ref_encoded_query_buffer().unwrap().borrow_mut();
-    query_buffer.build_query_buffer(decrypted_query_data_vec, query_id_list_vec);
-    let end = start.elapsed();
-    println!("[SGX CLOCK] {}:  {}.{:06} seconds", "store queies", end.as_secs(), end.subsec_nanos() / 1_000);
-
-    let start = Instant::now();
-    let mut mapped_query_buffer = get_ref_mapped_encoded_query_buffer().unwrap().borrow_mut();
-    mapped_query_buffer.mapping(&query_buffer);
+    // decrypted_queries are zeroized once they are in the buffer of the session
+    let ret = with_session(session_id, |tracer| tracer.upload_queries(encoding, decrypted_queries, query_id_list_vec));
+    if ret != sgx_status_t::SGX_SUCCESS {
+        return ret;
+    }
     let end = start.elapsed();
-    println!("[SGX CLOCK] {}:  {}.{:06} seconds", "merge queries to Q", end.as_secs(), end.subsec_nanos() / 1_000);
+    println!("[SGX CLOCK] {}:  {}.{:06} seconds", "store and merge queries to Q", end.as_secs(), end.subsec_nanos() / 1_000);
 
-    let end = whole_start.elapsed();
-    // println!("[SGX CLOCK] {}:  {}.{:06} seconds", "whole", end.as_secs(), end.subsec_nanos() / 1_000);
-    
     sgx_status_t::SGX_SUCCESS
 }
 
-fn _init_encoded_buffers() {
-
-    // initialize query buffer
-    let query_buffer = EncodedQueryBuffer::new();
-    let query_buffer_box = Box::new(RefCell::<EncodedQueryBuffer>::new(query_buffer));
-    let query_buffer_ptr = Box::into_raw(query_buffer_box);
-    ENCODED_QUERY_BUFFER.store(query_buffer_ptr as *mut (), Ordering::SeqCst);
-
-    // initialize mapped query buffer
-    let mapped_query_buffer = MappedEncodedQueryBuffer::new();
-    let mapped_query_buffer_box = Box::new(RefCell::<MappedEncodedQueryBuffer>::new(mapped_query_buffer));
-    let mapped_query_buffer_ptr = Box::into_raw(mapped_query_buffer_box);
-    MAPPED_ENCODED_QUERY_BUFFER.store(mapped_query_buffer_ptr as *mut (), Ordering::SeqCst);
-
-    // initialize result buffer
-    let result_buffer = EncodedResultBuffer::new();
-    let result_buffer_box = Box::new(RefCell::<EncodedResultBuffer>::new(result_buffer));
-    let result_buffer_ptr = Box::into_raw(result_buffer_box);
-    ENCODED_RESULT_BUFFER.store(result_buffer_ptr as *mut (), Ordering::SeqCst);
+// client records of the queries frame opened with the session key of each client, in order
+fn open_queries(
+    encoding: Encoding,
+    total_query_data_vec: &[u8],
+    query_id_list_vec: &Vec<u64>,
+    decrypted_queries: &mut Vec<Vec<u8>>,
+) -> SgxResult<()> {
+    let total_size = total_query_data_vec.len();
+    let channel_store = lock_secure_channel_store()?;
+    let mut cursor: usize = 0;
+    for query_id in query_id_list_vec.iter() {
+        // shared_key has been negotiated by start_key_exchange / finish_key_exchange
+        let shared_key: SessionKey = channel_store.session_key(*query_id)?;
+        let query_size = match read_query_size(total_query_data_vec, cursor) {
+            Some(query_size) => query_size,
+            None => { return Err(sgx_status_t::SGX_ERROR_INVALID_PARAMETER); }
+        };
+        cursor += QUERY_LENGTH_PREFIX_U8;
+        let query_bytes = query_size * encoding.value_size();
+        let sealed_end = cursor + SEALED_OVERHEAD_U8 + query_bytes;
+        if sealed_end > total_size {
+            return Err(sgx_status_t::SGX_ERROR_INVALID_PARAMETER);
+        }
+
+        let mut decrypted_query: Vec<u8> = vec![0; query_bytes];
+        // SGX_ERROR_MAC_MISMATCH tells the host that this query has been tampered with
+        let ret = secure_channel::open(
+            &shared_key,
+            *query_id,
+            DIRECTION_QUERY,
+            &total_query_data_vec[cursor..sealed_end],
+            &mut decrypted_query
+        );
+        if let Err(e) = ret {
+            zeroize(&mut decrypted_query);
+            return Err(e);
+        }
+        decrypted_queries.push(decrypted_query);
+        cursor = sealed_end;
+    }
+    // every byte has to belong to exactly one client record
+    if cursor != total_size {
+        return Err(sgx_status_t::SGX_ERROR_INVALID_PARAMETER);
+    }
+    Ok(())
+}
+
+// length prefix of the client record starting at cursor, None if it is out of bounds
+fn read_query_size(total_query_data: &[u8], cursor: usize) -> Option<usize> {
+    if cursor + QUERY_LENGTH_PREFIX_U8 > total_query_data.len() {
+        return None;
+    }
+    let mut prefix = [0_u8; QUERY_LENGTH_PREFIX_U8];
+    prefix.copy_from_slice(&total_query_data[cursor..cursor+QUERY_LENGTH_PREFIX_U8]);
+    let query_size = u32::from_be_bytes(prefix) as usize;
+    if query_size > MAX_QUERY_SIZE {
+        return None;
+    }
+    Some(query_size)
 }
 
 /*
@@ -196,61 +498,100 @@ fn _init_encoded_buffers() {
 */
 #[no_mangle]
 pub extern "C" fn private_encode_contact_trace(
+    session_id: u64,
     encoded_value_u8: *const u8,
     encoded_value_u8_size: usize,
 ) -> sgx_status_t {
-    let mut dictionary_buffer = EncodedDictionaryBuffer::new();
     let encoded_value_vec: Vec<u8> = unsafe {
         slice::from_raw_parts(encoded_value_u8, encoded_value_u8_size)
     }.to_vec();
     if encoded_value_vec.len() != encoded_value_u8_size {
         return sgx_status_t::SGX_ERROR_INVALID_PARAMETER;
     }
-    dictionary_buffer.build_dictionary_buffer(encoded_value_vec);
-    let mapped_query_buffer = get_ref_mapped_encoded_query_buffer().unwrap().borrow_mut();
-    let mut result_buffer = get_ref_encoded_result_buffer().unwrap().borrow_mut();
-
-    dictionary_buffer.intersect(&mapped_query_buffer, &mut result_buffer);
-    
-    sgx_status_t::SGX_SUCCESS
+    // the table is only locked to take the job and to merge the hits, so chunks
+    // of the session can be traced on every TCS of the enclave at once;
+    // SGX_ERROR_INVALID_STATE before the queries of the session are uploaded
+    let (job, cipher) = match lock_session_table().and_then(|table| table.chunk_job(session_id)) {
+        Ok(job) => job,
+        Err(e) => { return e; }
+    };
+    // decrypted with the chunk key, SGX_ERROR_INVALID_STATE above if none has been
+    // provisioned (see SessionTable::chunk_job), then the backend is chosen by the
+    // header byte of the chunk
+    let hits = match job.trace(encoded_value_vec, cipher.as_deref()) {
+        Ok(hits) => hits,
+        Err(e) => { return e; }
+    };
+    match lock_session_table().and_then(|mut table| table.merge(session_id, &job, hits)) {
+        Ok(()) => sgx_status_t::SGX_SUCCESS,
+        Err(e) => e,
+    }
 }
 
 // Response construction
 #[no_mangle]
 pub extern "C" fn get_encoded_result(
+    session_id: u64,
     response: *mut u8,
     response_size: usize,
 ) -> sgx_status_t {
-    let result_buffer = get_ref_encoded_result_buffer().unwrap().borrow_mut();
-    let query_buffer = get_ref_encoded_query_buffer().unwrap().borrow_mut();
-    let mut response_vec: Vec<u8> = Vec::with_capacity(response_size);
-
-    result_buffer.build_query_response(&query_buffer, &mut response_vec);
-
-    /* encryption */
-    let mut encrypted_response_vec: Vec<u8> = response_vec.clone();
-    for (i, query_rep) in query_buffer.queries.iter().enumerate() {
-        let counter_block: [u8; 16] = COUNTER_BLOCK;
-        let ctr_inc_bits: u32 = SGXSSL_CTR_BITS;
-
-        // Originally shared_key is derived by following Remote Attestation protocol.
-        // This is mock of shared key-based encryption.
-        let mut shared_key: [u8; 16] = [0; 16];
-        shared_key[..8].copy_from_slice(&query_rep.id.to_be_bytes());
-        let current_cursor = i*RESPONSE_DATA_SIZE_U8;
-        
+    let mut table = match lock_session_table() {
+        Ok(table) => table,
+        Err(e) => { return e; }
+    };
+    let tracer = match table.session(session_id) {
+        Ok(tracer) => tracer,
+        Err(e) => { return e; }
+    };
+    let mut response_vec: Vec<u8> = match tracer.build_response() {
+        Ok(response_vec) => response_vec,
+        Err(e) => { return e; }
+    };
+    let ret = seal_response(tracer, &response_vec, response, response_size);
+    zeroize(&mut response_vec);
+    ret
+}
+
+// response frame: the result of every client encrypted with its session key, query ids stay in the clear
+fn seal_response(
+    tracer: &ContactTracer,
+    response_vec: &Vec<u8>,
+    response: *mut u8,
+    response_size: usize,
+) -> sgx_status_t {
+    let query_buffer = tracer.query_buffer();
+    let channel_store = match lock_secure_channel_store() {
+        Ok(store) => store,
+        Err(e) => { return e; }
+    };
+    let mut encrypted_response_vec: Vec<u8> = Vec::with_capacity(response_size);
+    let mut current_cursor = 0;
+    for query_rep in query_buffer.queries.iter() {
+        let shared_key: SessionKey = match channel_store.session_key(query_rep.id) {
+            Ok(key) => key,
+            Err(e) => { return e; }
+        };
+        let record_size = plain_response_size(query_rep.parameters.len());
+
         // Encrypt only sensitive part, result. query_id should not be encrypted.
-        let ret = rsgx_aes_ctr_encrypt(
+        let sealed = secure_channel::seal(
             &shared_key,
-            &response_vec[current_cursor+QUERY_ID_SIZE_U8..current_cursor+RESPONSE_DATA_SIZE_U8],
-            &counter_block,
-            ctr_inc_bits,
-            &mut encrypted_response_vec[current_cursor+QUERY_ID_SIZE_U8..current_cursor+RESPONSE_DATA_SIZE_U8]
+            query_rep.id,
+            DIRECTION_RESPONSE,
+            &response_vec[current_cursor+QUERY_ID_SIZE_U8..current_cursor+record_size]
         );
-        match ret { Ok(()) => {}, Err(_) => { return sgx_status_t::SGX_ERROR_UNEXPECTED; } }    
+        let sealed = match sealed { Ok(sealed) => sealed, Err(e) => { return e; } };
+        encrypted_response_vec.extend_from_slice(&response_vec[current_cursor..current_cursor+QUERY_ID_SIZE_U8]);
+        encrypted_response_vec.extend_from_slice(&sealed);
+        current_cursor += record_size;
+    }
+    let count = query_buffer.queries.len() as u32;
+    let mut frame = write_frame(FRAME_RESPONSE, query_buffer.encoding, count, &encrypted_response_vec);
+    if frame.len() != response_size {
+        return sgx_status_t::SGX_ERROR_INVALID_PARAMETER;
     }
 
-    let slice = encrypted_response_vec.as_mut_slice();
+    let slice = frame.as_mut_slice();
     unsafe {
         for i in 0..response_size {
             *response.offset(i as isize) = slice[i];
@@ -258,4 +599,4 @@ pub extern "C" fn get_encoded_result(
     }
 
     sgx_status_t::SGX_SUCCESS
-}
\ No newline at end of file
+}
@@ -0,0 +1,244 @@
+use std::vec::Vec;
+use std::collections::HashMap;
+use sgx_types::*;
+use sgx_tcrypto::*;
+use sgx_tse::rsgx_create_report;
+use sgx_trts::memeq::ConsttimeMemEq;
+use sgx_trts::trts::rsgx_read_rand;
+use pct_core::primitive::*;
+use pct_core::constant::*;
+use authority_key::authority_public_key;
+
+/*
+Type SecureChannelStore
+    Per-client session keys established by an ECDH handshake that is bound to
+    an enclave report. The client verifies the report (or the quote made from it)
+    before trusting the enclave public key, then proves knowledge of the derived
+    key in finish_key_exchange.
+    The session of AUTHORITY_ID releases the chunk key, so anyone could otherwise
+    open it in the authority's name: it is only finished by finish_authority, with
+    a signature over the handshake transcript by the pinned authority_public_key.
+*/
+#[derive(Clone, Default)]
+pub struct SecureChannelStore {
+    pending: HashMap<QueryId, PendingHandshake>,
+    keys: HashMap<QueryId, SessionKey>,
+}
+
+#[derive(Clone, Default)]
+struct PendingHandshake {
+    private_key: sgx_ec256_private_t,
+    client_public_key: sgx_ec256_public_t,
+    enclave_public_key: sgx_ec256_public_t,
+}
+
+impl SecureChannelStore {
+    pub fn new() -> Self {
+        SecureChannelStore::default()
+    }
+
+    // generate an ephemeral key pair and a report whose report_data commits to
+    // (query_id, client public key, enclave public key). A client's previous key is
+    // dropped here, the authority's is only replaced once finish_authority has verified
+    // the signature, as anyone can start a handshake under AUTHORITY_ID
+    pub fn start(
+        &mut self,
+        query_id: QueryId,
+        client_public_key: &sgx_ec256_public_t,
+        target_info: &sgx_target_info_t,
+    ) -> SgxResult<(sgx_ec256_public_t, sgx_report_t)> {
+        let ecc_handle = SgxEccHandle::new();
+        ecc_handle.open()?;
+        let (private_key, enclave_public_key) = ecc_handle.create_key_pair()?;
+        ecc_handle.close()?;
+
+        let report_data = handshake_report_data(query_id, client_public_key, &enclave_public_key)?;
+        let report = rsgx_create_report(target_info, &report_data)?;
+
+        if query_id != AUTHORITY_ID {
+            self.keys.remove(&query_id);
+        }
+        self.pending.insert(query_id, PendingHandshake {
+            private_key: private_key,
+            client_public_key: *client_public_key,
+            enclave_public_key: enclave_public_key,
+        });
+        Ok((enclave_public_key, report))
+    }
+
+    // derive the session key and accept it only if the client derived the same one
+    pub fn finish(
+        &mut self,
+        query_id: QueryId,
+        key_confirmation: &sgx_cmac_128bit_tag_t,
+    ) -> SgxResult<()> {
+        if query_id == AUTHORITY_ID {
+            return Err(sgx_status_t::SGX_ERROR_INVALID_PARAMETER);
+        }
+        self.finish_handshake(query_id, key_confirmation, None)
+    }
+
+    // same for the health authority, whose signature over the transcript has to verify
+    // with authority_public_key as well
+    pub fn finish_authority(
+        &mut self,
+        key_confirmation: &sgx_cmac_128bit_tag_t,
+        signature: &sgx_ec256_signature_t,
+    ) -> SgxResult<()> {
+        self.finish_handshake(AUTHORITY_ID, key_confirmation, Some(signature))
+    }
+
+    fn finish_handshake(
+        &mut self,
+        query_id: QueryId,
+        key_confirmation: &sgx_cmac_128bit_tag_t,
+        signature: Option<&sgx_ec256_signature_t>,
+    ) -> SgxResult<()> {
+        let mut pending = match self.pending.remove(&query_id) {
+            Some(pending) => pending,
+            None => { return Err(sgx_status_t::SGX_ERROR_INVALID_STATE); }
+        };
+        if let Some(signature) = signature {
+            let transcript = handshake_transcript(query_id, &pending.client_public_key, &pending.enclave_public_key);
+            let ecc_handle = SgxEccHandle::new();
+            ecc_handle.open()?;
+            // rejected keys and signatures are errors of their own, all of them fail the handshake
+            let verified = ecc_handle.ecdsa_verify_slice(&transcript, &authority_public_key(), signature);
+            ecc_handle.close()?;
+            if verified != Ok(true) {
+                pending.private_key.r = [0; SGX_ECP256_KEY_SIZE];
+                return Err(sgx_status_t::SGX_ERROR_INVALID_SIGNATURE);
+            }
+        }
+
+        let ecc_handle = SgxEccHandle::new();
+        ecc_handle.open()?;
+        let shared_key = ecc_handle.compute_shared_dhkey(&pending.private_key, &pending.client_public_key);
+        pending.private_key.r = [0; SGX_ECP256_KEY_SIZE];
+        ecc_handle.close()?;
+        let shared_key = shared_key?;
+
+        let session_key = derive_key(&shared_key, SESSION_KEY_LABEL)?;
+        let confirmation_key = derive_key(&shared_key, CONFIRMATION_KEY_LABEL)?;
+        let expected = key_confirmation_tag(
+            &confirmation_key, query_id, &pending.client_public_key, &pending.enclave_public_key
+        )?;
+        if !expected[..].consttime_memeq(&key_confirmation[..]) {
+            return Err(sgx_status_t::SGX_ERROR_MAC_MISMATCH);
+        }
+
+        self.keys.insert(query_id, session_key);
+        Ok(())
+    }
+
+    pub fn session_key(&self, query_id: QueryId) -> SgxResult<SessionKey> {
+        match self.keys.get(&query_id) {
+            Some(key) => Ok(*key),
+            None => Err(sgx_status_t::SGX_ERROR_INVALID_STATE),
+        }
+    }
+}
+
+// KDF of the Intel remote attestation sample:
+//   KDK = AES-CMAC(0^128, g^ab.x), key = AES-CMAC(KDK, 0x01 || label || 0x00 || 0x80 || 0x00)
+// the client side (app/src/secure_channel.rs) must stay in sync with this.
+fn derive_key(shared_key: &sgx_ec256_dh_shared_t, label: &[u8]) -> SgxResult<SessionKey> {
+    let cmac_key: sgx_cmac_128bit_key_t = [0; SGX_CMAC_KEY_SIZE];
+    let key_derivation_key = rsgx_rijndael128_cmac_slice(&cmac_key, &shared_key.s)?;
+
+    let mut derivation_buffer: Vec<u8> = Vec::with_capacity(label.len() + 4);
+    derivation_buffer.push(0x01);
+    derivation_buffer.extend_from_slice(label);
+    derivation_buffer.extend_from_slice(&[0x00, 0x80, 0x00]);
+    rsgx_rijndael128_cmac_slice(&key_derivation_key, &derivation_buffer)
+}
+
+fn handshake_transcript(
+    query_id: QueryId,
+    client_public_key: &sgx_ec256_public_t,
+    enclave_public_key: &sgx_ec256_public_t,
+) -> Vec<u8> {
+    let mut transcript: Vec<u8> = Vec::with_capacity(QUERY_ID_SIZE_U8 + 4*SGX_ECP256_KEY_SIZE);
+    transcript.extend_from_slice(&query_id.to_be_bytes());
+    transcript.extend_from_slice(&client_public_key.gx);
+    transcript.extend_from_slice(&client_public_key.gy);
+    transcript.extend_from_slice(&enclave_public_key.gx);
+    transcript.extend_from_slice(&enclave_public_key.gy);
+    transcript
+}
+
+// report_data = SHA-256(transcript) || 0^32
+fn handshake_report_data(
+    query_id: QueryId,
+    client_public_key: &sgx_ec256_public_t,
+    enclave_public_key: &sgx_ec256_public_t,
+) -> SgxResult<sgx_report_data_t> {
+    let hash = rsgx_sha256_slice(&handshake_transcript(query_id, client_public_key, enclave_public_key))?;
+    let mut report_data = sgx_report_data_t::default();
+    report_data.d[..SGX_SHA256_HASH_SIZE].copy_from_slice(&hash);
+    Ok(report_data)
+}
+
+fn key_confirmation_tag(
+    confirmation_key: &SessionKey,
+    query_id: QueryId,
+    client_public_key: &sgx_ec256_public_t,
+    enclave_public_key: &sgx_ec256_public_t,
+) -> SgxResult<sgx_cmac_128bit_tag_t> {
+    rsgx_rijndael128_cmac_slice(confirmation_key, &handshake_transcript(query_id, client_public_key, enclave_public_key))
+}
+
+fn channel_aad(query_id: QueryId, direction: u8) -> [u8; QUERY_ID_SIZE_U8 + 1] {
+    let mut aad = [0_u8; QUERY_ID_SIZE_U8 + 1];
+    aad[..QUERY_ID_SIZE_U8].copy_from_slice(&query_id.to_be_bytes());
+    aad[QUERY_ID_SIZE_U8] = direction;
+    aad
+}
+
+// nonce || AES-GCM(plaintext) || tag, with a fresh random nonce for every message
+pub fn seal(
+    session_key: &SessionKey,
+    query_id: QueryId,
+    direction: u8,
+    plaintext: &[u8],
+) -> SgxResult<Vec<u8>> {
+    let mut nonce = [0_u8; NONCE_SIZE_U8];
+    rsgx_read_rand(&mut nonce)?;
+    let mut sealed: Vec<u8> = vec![0; SEALED_OVERHEAD_U8 + plaintext.len()];
+    let mut tag: sgx_aes_gcm_128bit_tag_t = [0; TAG_SIZE_U8];
+    rsgx_rijndael128GCM_encrypt(
+        session_key,
+        plaintext,
+        &nonce,
+        &channel_aad(query_id, direction),
+        &mut sealed[NONCE_SIZE_U8..NONCE_SIZE_U8 + plaintext.len()],
+        &mut tag
+    )?;
+    sealed[..NONCE_SIZE_U8].copy_from_slice(&nonce);
+    sealed[NONCE_SIZE_U8 + plaintext.len()..].copy_from_slice(&tag);
+    Ok(sealed)
+}
+
+// inverse of seal, tampered messages are rejected with SGX_ERROR_MAC_MISMATCH
+pub fn open(
+    session_key: &SessionKey,
+    query_id: QueryId,
+    direction: u8,
+    sealed: &[u8],
+    plaintext: &mut [u8],
+) -> SgxResult<()> {
+    if sealed.len() != SEALED_OVERHEAD_U8 + plaintext.len() {
+        return Err(sgx_status_t::SGX_ERROR_INVALID_PARAMETER);
+    }
+    let body_end = sealed.len() - TAG_SIZE_U8;
+    let mut tag: sgx_aes_gcm_128bit_tag_t = [0; TAG_SIZE_U8];
+    tag.copy_from_slice(&sealed[body_end..]);
+    rsgx_rijndael128GCM_decrypt(
+        session_key,
+        &sealed[NONCE_SIZE_U8..body_end],
+        &sealed[..NONCE_SIZE_U8],
+        &channel_aad(query_id, direction),
+        &tag,
+        plaintext
+    )
+}
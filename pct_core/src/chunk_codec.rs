@@ -0,0 +1,169 @@
+use std::vec::Vec;
+use sgx_types::*;
+
+/*
+Chunk codecs
+    Second byte of every chunk, after the backend id, names how the dictionary
+    that follows is compressed. The host compresses with the same code it is
+    decoded with here.
+*/
+pub const CODEC_NONE: u8 = 0;
+pub const CODEC_LZ: u8 = 1;
+
+/*
+LZ codec
+    original length (u32) || sequences, a byte oriented LZ77 in the spirit of LZ4
+    that only needs Vec, so it builds under sgx_tstd. A sequence is
+        token      literal length (high nibble) || match length - LZ_MIN_MATCH (low nibble),
+                   15 in a nibble continues in the following bytes, 255 meaning more
+        literals
+        offset     u16, back into the output, omitted in the last sequence
+        match length continued
+    A match can overlap its own output (runs). Every length and offset is checked,
+    so a malformed payload is SGX_ERROR_INVALID_PARAMETER and never reads or
+    writes out of bounds.
+*/
+const LZ_MIN_MATCH: usize = 4;
+const LZ_MAX_OFFSET: usize = 65535;
+const LZ_HASH_BITS: u32 = 14;
+// largest output per payload byte, bounds what a forged original length can allocate
+const LZ_MAX_RATIO: usize = 255;
+
+pub fn compress(codec: u8, bytes: &[u8]) -> SgxResult<Vec<u8>> {
+    match codec {
+        CODEC_NONE => Ok(bytes.to_vec()),
+        CODEC_LZ => Ok(lz_compress(bytes)),
+        _ => Err(sgx_status_t::SGX_ERROR_FEATURE_NOT_SUPPORTED),
+    }
+}
+
+// payload of the given codec -> dictionary, CODEC_NONE passes it through without a copy
+pub fn decompress(codec: u8, payload: Vec<u8>) -> SgxResult<Vec<u8>> {
+    match codec {
+        CODEC_NONE => Ok(payload),
+        CODEC_LZ => lz_decompress(&payload),
+        _ => Err(sgx_status_t::SGX_ERROR_FEATURE_NOT_SUPPORTED),
+    }
+}
+
+fn lz_hash(bytes: &[u8]) -> usize {
+    let v = (bytes[0] as u32) | (bytes[1] as u32) << 8 | (bytes[2] as u32) << 16 | (bytes[3] as u32) << 24;
+    (v.wrapping_mul(2654435761) >> (32 - LZ_HASH_BITS)) as usize
+}
+
+fn lz_push_length(out: &mut Vec<u8>, mut length: usize) {
+    while length >= 255 {
+        out.push(255);
+        length -= 255;
+    }
+    out.push(length as u8);
+}
+
+fn lz_push_sequence(out: &mut Vec<u8>, literals: &[u8], matched: Option<(usize, usize)>) {
+    let literal_nibble = if literals.len() >= 15 { 15 } else { literals.len() };
+    let match_length = matched.map_or(0, |(_, length)| length - LZ_MIN_MATCH);
+    let match_nibble = if match_length >= 15 { 15 } else { match_length };
+    out.push((literal_nibble << 4 | match_nibble) as u8);
+    if literal_nibble == 15 {
+        lz_push_length(out, literals.len() - 15);
+    }
+    out.extend_from_slice(literals);
+    if let Some((offset, _)) = matched {
+        out.extend_from_slice(&(offset as u16).to_be_bytes());
+        if match_nibble == 15 {
+            lz_push_length(out, match_length - 15);
+        }
+    }
+}
+
+// greedy, one candidate per hash of the next LZ_MIN_MATCH bytes
+fn lz_compress(bytes: &[u8]) -> Vec<u8> {
+    let mut out: Vec<u8> = Vec::with_capacity(4 + bytes.len() / 2);
+    out.extend_from_slice(&(bytes.len() as u32).to_be_bytes());
+    // position + 1 of the last occurrence, 0 for none
+    let mut table: Vec<usize> = vec![0; 1 << LZ_HASH_BITS];
+    let mut anchor = 0;
+    let mut i = 0;
+    while i + LZ_MIN_MATCH <= bytes.len() {
+        let hash = lz_hash(&bytes[i..]);
+        let candidate = table[hash];
+        table[hash] = i + 1;
+        if candidate > 0 && i - (candidate - 1) <= LZ_MAX_OFFSET
+            && bytes[candidate - 1..candidate - 1 + LZ_MIN_MATCH] == bytes[i..i + LZ_MIN_MATCH] {
+            let start = candidate - 1;
+            let mut length = LZ_MIN_MATCH;
+            while i + length < bytes.len() && bytes[start + length] == bytes[i + length] {
+                length += 1;
+            }
+            lz_push_sequence(&mut out, &bytes[anchor..i], Some((i - start, length)));
+            i += length;
+            anchor = i;
+        } else {
+            i += 1;
+        }
+    }
+    lz_push_sequence(&mut out, &bytes[anchor..], None);
+    out
+}
+
+fn lz_read_length(payload: &[u8], cursor: &mut usize, nibble: usize) -> SgxResult<usize> {
+    let mut length = nibble;
+    if nibble < 15 {
+        return Ok(length);
+    }
+    loop {
+        let byte = *payload.get(*cursor).ok_or(sgx_status_t::SGX_ERROR_INVALID_PARAMETER)?;
+        *cursor += 1;
+        length += byte as usize;
+        if byte != 255 {
+            return Ok(length);
+        }
+    }
+}
+
+fn lz_decompress(payload: &[u8]) -> SgxResult<Vec<u8>> {
+    if payload.len() < 4 {
+        return Err(sgx_status_t::SGX_ERROR_INVALID_PARAMETER);
+    }
+    let mut length = [0_u8; 4];
+    length.copy_from_slice(&payload[..4]);
+    let original_len = u32::from_be_bytes(length) as usize;
+    if original_len > payload.len().saturating_mul(LZ_MAX_RATIO) {
+        return Err(sgx_status_t::SGX_ERROR_INVALID_PARAMETER);
+    }
+    let mut out: Vec<u8> = Vec::with_capacity(original_len);
+    let mut cursor = 4;
+    // ends with a sequence of literals only, anything else has been truncated
+    loop {
+        let token = *payload.get(cursor).ok_or(sgx_status_t::SGX_ERROR_INVALID_PARAMETER)? as usize;
+        cursor += 1;
+        let literal_len = lz_read_length(payload, &mut cursor, token >> 4)?;
+        if literal_len > payload.len() - cursor || literal_len > original_len - out.len() {
+            return Err(sgx_status_t::SGX_ERROR_INVALID_PARAMETER);
+        }
+        out.extend_from_slice(&payload[cursor..cursor + literal_len]);
+        cursor += literal_len;
+        // the last sequence has no match
+        if cursor == payload.len() {
+            break;
+        }
+        if payload.len() - cursor < 2 {
+            return Err(sgx_status_t::SGX_ERROR_INVALID_PARAMETER);
+        }
+        let offset = (payload[cursor] as usize) << 8 | payload[cursor + 1] as usize;
+        cursor += 2;
+        let match_len = lz_read_length(payload, &mut cursor, token & 0x0f)? + LZ_MIN_MATCH;
+        if offset == 0 || offset > out.len() || match_len > original_len - out.len() {
+            return Err(sgx_status_t::SGX_ERROR_INVALID_PARAMETER);
+        }
+        let start = out.len() - offset;
+        for j in 0..match_len {
+            let byte = out[start + j];
+            out.push(byte);
+        }
+    }
+    if out.len() != original_len {
+        return Err(sgx_status_t::SGX_ERROR_INVALID_PARAMETER);
+    }
+    Ok(out)
+}
@@ -0,0 +1,67 @@
+/* Constants */
+
+pub const UNIXEPOCH_U8_SIZE: usize = 10;
+pub const GEOHASH_U8_SIZE: usize = 10;
+pub const QUERY_U8_SIZE: usize = UNIXEPOCH_U8_SIZE + GEOHASH_U8_SIZE;
+// qeuryId + result
+// result = version 1バイト + risk_level 1バイト
+//          + matched_slots + neighbour_slots + exposure_duration + longest_exposure (u32 each)
+//          + matched points, one bit per point of the client (see query_result::matched_points_u8)
+// the sizes below are those of a client without points
+pub const QUERY_ID_SIZE_U8: usize = 8;
+pub const RESPONSE_FORMAT_VERSION: u8 = 4;
+pub const QUERY_RESULT_U8: usize = 1 + 1 + 4 + 4 + 4 + 4;
+pub const PLAIN_RESPONSE_DATA_SIZE_U8: usize = QUERY_ID_SIZE_U8 + QUERY_RESULT_U8;
+// queryId (in clear) + nonce + encrypted result + tag
+pub const RESPONSE_DATA_SIZE_U8: usize = QUERY_ID_SIZE_U8 + SEALED_OVERHEAD_U8 + QUERY_RESULT_U8;
+
+pub const THREASHOLD: usize = 100000;
+
+// contact rules (see contact_rules.rs)
+pub const CONTACT_RULES_U8: usize = 4 + 4 + 4 + 1 + 4;
+pub const MAX_SPATIAL_TOLERANCE: u8 = 1;
+// one day of 10 minute slots
+pub const MAX_TIME_TOLERANCE: u32 = 144;
+
+// UNIX EPOCH INTERVAL OF THE GPS DATA
+pub const TIME_INTERVAL: u64 = 600;
+
+// largest value size of all encodings (gp10), see encoding.rs
+pub const MAX_ENCODEDVALUE_SIZE: usize = 14;
+
+// upper bound of points per client (two weeks of TIME_INTERVAL slots)
+pub const MAX_QUERY_SIZE: usize = (14*24*3600 / TIME_INTERVAL) as usize;
+
+// for optimization
+pub const CLIENT_SIZE: usize = 4500;
+
+
+// for secure channel encryption (AES-128-GCM, nonce || ciphertext || tag)
+pub const NONCE_SIZE_U8: usize = 12;
+pub const TAG_SIZE_U8: usize = 16;
+pub const SEALED_OVERHEAD_U8: usize = NONCE_SIZE_U8 + TAG_SIZE_U8;
+// additional authenticated data is queryId + direction, so a message can not be
+// replayed for another client nor reflected back to the sender
+pub const DIRECTION_QUERY: u8 = 0;
+pub const DIRECTION_RESPONSE: u8 = 1;
+// chunk key of the health authority, sealed under its own session
+pub const DIRECTION_CHUNK_KEY: u8 = 2;
+
+// each client record of the queries frame (wire.rs) is query_size (u32, big endian) || nonce || ciphertext || tag
+pub const QUERY_LENGTH_PREFIX_U8: usize = 4;
+
+// for key exchange
+pub const SESSION_KEY_SIZE: usize = 16;
+pub const SESSION_KEY_LABEL: &[u8] = b"SK";
+pub const CONFIRMATION_KEY_LABEL: &[u8] = b"MK";
+
+// health authority: it attests the enclave through the same key exchange as the
+// clients under this reserved queryId, signed with its own key, then provisions the
+// key of its chunks as flags (1) || key
+pub const AUTHORITY_ID: u64 = u64::MAX;
+pub const CHUNK_KEY_SIZE: usize = 16;
+pub const CHUNK_KEY_MESSAGE_U8: usize = 1 + CHUNK_KEY_SIZE;
+// the authority allows the key to replace one that is already installed
+pub const CHUNK_KEY_REPLACE: u8 = 1;
+// sealed chunk = nonce || AES-GCM(backend id || codec || dictionary) || tag
+pub const CHUNK_AAD: &[u8] = b"PCT chunk";
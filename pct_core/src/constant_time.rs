@@ -0,0 +1,104 @@
+use std::vec::Vec;
+use constant::*;
+
+/*
+Branch free helpers
+    for the paths whose running time and memory accesses must not depend on
+    query contents (oblivious.rs, response construction). Flags are 0 or 1.
+*/
+
+// indices in the order a constant time routine touches them, only recorded when asked for
+pub type AccessTrace = Vec<usize>;
+
+pub fn record(trace: &mut Option<&mut AccessTrace>, i: usize) {
+    if let Some(trace) = trace.as_mut() {
+        trace.push(i);
+    }
+}
+
+// 1 if a < b
+pub fn ct_lt(a: u32, b: u32) -> u32 {
+    ((a as u64).wrapping_sub(b as u64) >> 63) as u32
+}
+
+pub fn ct_eq(a: u32, b: u32) -> u32 {
+    (((a ^ b) as u64).wrapping_sub(1) >> 63) as u32
+}
+
+pub fn ct_select(flag: u32, a: u32, b: u32) -> u32 {
+    let mask = 0_u32.wrapping_sub(flag);
+    (a & mask) | (b & !mask)
+}
+
+pub fn ct_max(a: u32, b: u32) -> u32 {
+    ct_select(ct_lt(a, b), b, a)
+}
+
+pub fn ct_select_bytes(flag: u32, from: &[u8; MAX_ENCODEDVALUE_SIZE], to: &mut [u8; MAX_ENCODEDVALUE_SIZE]) {
+    let mask = 0_u8.wrapping_sub(flag as u8);
+    for i in 0..MAX_ENCODEDVALUE_SIZE {
+        to[i] = (from[i] & mask) | (to[i] & !mask);
+    }
+}
+
+// (a < b, a == b) in lexicographic order, always reading every byte
+pub fn ct_cmp_bytes(a: &[u8; MAX_ENCODEDVALUE_SIZE], b: &[u8; MAX_ENCODEDVALUE_SIZE]) -> (u32, u32) {
+    let mut less: u32 = 0;
+    let mut equal: u32 = 1;
+    for i in 0..MAX_ENCODEDVALUE_SIZE {
+        less |= equal & ct_lt(a[i] as u32, b[i] as u32);
+        equal &= ct_eq(a[i] as u32, b[i] as u32);
+    }
+    (less, equal)
+}
+
+// swaps a and b if mask is all ones, keeps them if it is zero
+pub fn ct_swap_u32(mask: u32, a: &mut u32, b: &mut u32) {
+    let t = (*a ^ *b) & mask;
+    *a ^= t;
+    *b ^= t;
+}
+
+pub fn ct_swap_bytes(mask: u32, a: &mut [u8; MAX_ENCODEDVALUE_SIZE], b: &mut [u8; MAX_ENCODEDVALUE_SIZE]) {
+    let byte_mask = mask as u8;
+    for i in 0..MAX_ENCODEDVALUE_SIZE {
+        let t = (a[i] ^ b[i]) & byte_mask;
+        a[i] ^= t;
+        b[i] ^= t;
+    }
+}
+
+/*
+    bitonic sort of items, whose length has to be a power of two. The compare-exchange
+    network only depends on the length, greater (1 if a sorts after b) and
+    conditional_swap (swaps if the flag is 1) have to be branch free on the contents.
+    trace records both indices of every compare-exchange.
+*/
+pub fn bitonic_sort<T>(
+    items: &mut [T],
+    greater: fn(&T, &T) -> u32,
+    conditional_swap: fn(u32, &mut T, &mut T),
+    trace: &mut Option<&mut AccessTrace>,
+) {
+    let n = items.len();
+    let mut k = 2;
+    while k <= n {
+        let mut j = k / 2;
+        while j > 0 {
+            for i in 0..n {
+                let l = i ^ j;
+                if l > i {
+                    record(trace, i);
+                    record(trace, l);
+                    let (head, tail) = items.split_at_mut(l);
+                    let a = &mut head[i];
+                    let b = &mut tail[0];
+                    let swap = if i & k == 0 { greater(a, b) } else { greater(b, a) };
+                    conditional_swap(swap, a, b);
+                }
+            }
+            j /= 2;
+        }
+        k *= 2;
+    }
+}
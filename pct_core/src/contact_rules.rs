@@ -0,0 +1,102 @@
+use std::vec::Vec;
+use sgx_types::*;
+use constant::*;
+use encoding::Encoding;
+use constant_time::ct_lt;
+
+/*
+Type ContactRules
+    Rules of "risky contact" decided by the health authority at runtime.
+    wire format (big endian, CONTACT_RULES_U8 bytes)
+        min_exposure_duration u32 (seconds)
+        time_tolerance_before u32 (time slots)
+        time_tolerance_after  u32 (time slots)
+        spatial_tolerance     u8  (0 = same cell only, 1 = also the 8 neighbouring cells)
+        min_hits              u32 (matched points)
+*/
+#[derive(Clone, Debug, PartialEq)]
+pub struct ContactRules {
+    pub min_exposure_duration: u32,
+    pub time_tolerance_before: u32,
+    pub time_tolerance_after: u32,
+    pub spatial_tolerance: u8,
+    pub min_hits: u32,
+}
+
+impl Default for ContactRules {
+    // exact match, a single hit is risky (the behaviour before rules were configurable)
+    fn default() -> Self {
+        ContactRules {
+            min_exposure_duration: 0,
+            time_tolerance_before: 0,
+            time_tolerance_after: 0,
+            spatial_tolerance: 0,
+            min_hits: 1,
+        }
+    }
+}
+
+impl ContactRules {
+    pub fn new() -> Self {
+        ContactRules::default()
+    }
+
+    pub fn from_be_bytes(bytes: &[u8]) -> SgxResult<Self> {
+        if bytes.len() != CONTACT_RULES_U8 {
+            return Err(sgx_status_t::SGX_ERROR_INVALID_PARAMETER);
+        }
+        let rules = ContactRules {
+            min_exposure_duration: u32_from_be(&bytes[0..4]),
+            time_tolerance_before: u32_from_be(&bytes[4..8]),
+            time_tolerance_after: u32_from_be(&bytes[8..12]),
+            spatial_tolerance: bytes[12],
+            min_hits: u32_from_be(&bytes[13..17]),
+        };
+        rules.validate()?;
+        Ok(rules)
+    }
+
+    pub fn to_be_bytes(&self) -> Vec<u8> {
+        let mut bytes: Vec<u8> = Vec::with_capacity(CONTACT_RULES_U8);
+        bytes.extend_from_slice(&self.min_exposure_duration.to_be_bytes());
+        bytes.extend_from_slice(&self.time_tolerance_before.to_be_bytes());
+        bytes.extend_from_slice(&self.time_tolerance_after.to_be_bytes());
+        bytes.push(self.spatial_tolerance);
+        bytes.extend_from_slice(&self.min_hits.to_be_bytes());
+        bytes
+    }
+
+    fn validate(&self) -> SgxResult<()> {
+        if self.min_hits == 0 || self.spatial_tolerance > MAX_SPATIAL_TOLERANCE {
+            return Err(sgx_status_t::SGX_ERROR_INVALID_PARAMETER);
+        }
+        if self.time_tolerance_before > MAX_TIME_TOLERANCE || self.time_tolerance_after > MAX_TIME_TOLERANCE {
+            return Err(sgx_status_t::SGX_ERROR_INVALID_PARAMETER);
+        }
+        Ok(())
+    }
+
+    // time and space are interleaved in TrajectoryHash encodings,
+    // only gp10 can widen the time window or look at neighbouring cells
+    pub fn check_encoding(&self, encoding: Encoding) -> SgxResult<()> {
+        if (self.has_time_tolerance() || self.spatial_tolerance > 0) && !encoding.is_gp10() {
+            return Err(sgx_status_t::SGX_ERROR_FEATURE_NOT_SUPPORTED);
+        }
+        Ok(())
+    }
+
+    pub fn has_time_tolerance(&self) -> bool {
+        self.time_tolerance_before > 0 || self.time_tolerance_after > 0
+    }
+
+    // without short circuit, it runs on every client's result
+    pub fn is_risky(&self, matched_slots: u32, exposure_duration: u32) -> bool {
+        (ct_lt(matched_slots, self.min_hits) | ct_lt(exposure_duration, self.min_exposure_duration)) == 0
+    }
+}
+
+fn u32_from_be(bytes: &[u8]) -> u32 {
+    let mut array: [u8; 4] = [0; 4];
+    array.copy_from_slice(bytes);
+    u32::from_be_bytes(array)
+}
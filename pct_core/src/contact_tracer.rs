@@ -0,0 +1,222 @@
+use std::vec::Vec;
+use std::sync::Arc;
+use sgx_types::*;
+use primitive::*;
+use utils::zeroize;
+use encoding::Encoding;
+use contact_rules::ContactRules;
+use encoded_query_buffer::EncodedQueryBuffer;
+use mapped_encoded_query_buffer::MappedEncodedQueryBuffer;
+use encoded_result_buffer::EncodedResultBuffer;
+use query_result::plain_response_size;
+use encoded_dictionary_buffer::{ EncodedDictionaryBuffer, ChunkCipher, LookupMode };
+use constant_time::AccessTrace;
+use wire::{ read_frame, FRAME_CHUNK, FRAME_HEADER_U8 };
+
+/*
+Type ContactTracer
+    Whole state of one batch of queries, i.e. of one session of the enclave
+    (see session.rs) or of the native backend of the app:
+        set_contact_rules -> upload_queries -> trace_chunk (for every chunk) -> build_response
+    Tracing or building a response before the queries are uploaded is SGX_ERROR_INVALID_STATE.
+    The decrypted queries and the results are zeroized when the batch is replaced or dropped.
+    trace_chunk is chunk_job -> ChunkJob::trace -> merge, which callers can split to
+    trace several chunks of the batch on their own threads.
+*/
+#[derive(Default)]
+pub struct ContactTracer {
+    // rules stay in effect until the health authority uploads new ones
+    rules: ContactRules,
+    query_buffer: EncodedQueryBuffer,
+    // shared with the ChunkJobs of the batch
+    mapped_query_buffer: Arc<MappedEncodedQueryBuffer>,
+    result_buffer: EncodedResultBuffer,
+    lookup_mode: LookupMode,
+    uploaded: bool,
+    // counts the uploaded batches, so the hits of a replaced batch are not merged
+    batch: u64,
+}
+
+/*
+Type ChunkJob
+    What tracing one chunk reads: the query values of the batch, the rules and the
+    lookup mode at the time it was taken. It only reads, so chunks can be traced on
+    several threads at once, each into hit flags of its own that ContactTracer::merge
+    ORs into the batch.
+*/
+#[derive(Clone)]
+pub struct ChunkJob {
+    batch: u64,
+    mapped_query_buffer: Arc<MappedEncodedQueryBuffer>,
+    rules: ContactRules,
+    lookup_mode: LookupMode,
+}
+
+impl ChunkJob {
+    // hit flags of the query values against the chunk alone, see ContactTracer::trace_chunk
+    pub fn trace(&self, mut frame: Vec<u8>, cipher: Option<&dyn ChunkCipher>) -> SgxResult<EncodedResultBuffer> {
+        let (header, _) = read_frame(&frame, FRAME_CHUNK)?;
+        // keys of another encoding could never match
+        if header.encoding != self.mapped_query_buffer.encoding {
+            return Err(sgx_status_t::SGX_ERROR_INVALID_PARAMETER);
+        }
+        // in place, the chunk is the largest buffer of the enclave
+        frame.drain(..FRAME_HEADER_U8);
+        let dictionary_buffer = EncodedDictionaryBuffer::build_dictionary_buffer(frame, cipher)?;
+        if dictionary_buffer.len() != header.count as usize {
+            return Err(sgx_status_t::SGX_ERROR_INVALID_METADATA);
+        }
+        let mut hits = EncodedResultBuffer::with_size(self.mapped_query_buffer.positions.len());
+        dictionary_buffer.intersect(self.lookup_mode, &self.mapped_query_buffer, &self.rules, &mut hits);
+        Ok(hits)
+    }
+}
+
+impl ContactTracer {
+    pub fn new() -> Self {
+        ContactTracer::default()
+    }
+
+    pub fn set_contact_rules(&mut self, rules_u8: &[u8]) -> SgxResult<()> {
+        self.rules = ContactRules::from_be_bytes(rules_u8)?;
+        Ok(())
+    }
+
+    pub fn rules(&self) -> &ContactRules {
+        &self.rules
+    }
+
+    // applies from the next chunk on, and to how the response is built
+    pub fn set_lookup_mode(&mut self, mode: LookupMode) {
+        self.lookup_mode = mode;
+    }
+
+    pub fn lookup_mode(&self) -> LookupMode {
+        self.lookup_mode
+    }
+
+    pub fn query_buffer(&self) -> &EncodedQueryBuffer {
+        &self.query_buffer
+    }
+
+    // plaintext queries[i] of client query_id_list[i], replaces the previous batch;
+    // queries are zeroized once they are copied into the buffer
+    pub fn upload_queries(
+        &mut self,
+        encoding: Encoding,
+        mut queries: Vec<Vec<u8>>,
+        query_id_list: Vec<QueryId>,
+    ) -> SgxResult<()> {
+        let ret = self.store_queries(encoding, &queries, query_id_list);
+        for query in queries.iter_mut() {
+            zeroize(query);
+        }
+        ret
+    }
+
+    fn store_queries(
+        &mut self,
+        encoding: Encoding,
+        queries: &[Vec<u8>],
+        query_id_list: Vec<QueryId>,
+    ) -> SgxResult<()> {
+        self.rules.check_encoding(encoding)?;
+        self.clear_batch();
+        if let Err(e) = self.query_buffer.build_query_buffer(encoding, queries, query_id_list) {
+            self.clear_batch();
+            return Err(e);
+        }
+        let mut mapped_query_buffer = MappedEncodedQueryBuffer::new();
+        mapped_query_buffer.mapping(&self.query_buffer);
+        self.result_buffer = EncodedResultBuffer::with_size(mapped_query_buffer.positions.len());
+        self.mapped_query_buffer = Arc::new(mapped_query_buffer);
+        self.batch += 1;
+        self.uploaded = true;
+        Ok(())
+    }
+
+    // to trace a chunk of the current batch without holding the tracer, see ChunkJob
+    pub fn chunk_job(&self) -> SgxResult<ChunkJob> {
+        if !self.uploaded {
+            return Err(sgx_status_t::SGX_ERROR_INVALID_STATE);
+        }
+        // the rules may have been replaced after the queries were uploaded
+        self.rules.check_encoding(self.mapped_query_buffer.encoding)?;
+        Ok(ChunkJob {
+            batch: self.batch,
+            mapped_query_buffer: self.mapped_query_buffer.clone(),
+            rules: self.rules.clone(),
+            lookup_mode: self.lookup_mode,
+        })
+    }
+
+    // ORs the hits of a chunk traced by job into the batch, in any order;
+    // SGX_ERROR_INVALID_STATE if the batch has been replaced since the job was taken
+    pub fn merge(&mut self, job: &ChunkJob, hits: EncodedResultBuffer) -> SgxResult<()> {
+        if !self.uploaded || job.batch != self.batch {
+            return Err(sgx_status_t::SGX_ERROR_INVALID_STATE);
+        }
+        self.result_buffer.merge(hits)
+    }
+
+    // chunk frame of wire.rs, body = backend id || codec || dictionary (see EncodedDictionaryBuffer)
+    pub fn trace_chunk(&mut self, frame: Vec<u8>) -> SgxResult<()> {
+        self.trace(frame, None)
+    }
+
+    // same, the body sealed as a whole by the health authority
+    pub fn trace_sealed_chunk(&mut self, frame: Vec<u8>, cipher: &dyn ChunkCipher) -> SgxResult<()> {
+        self.trace(frame, Some(cipher))
+    }
+
+    fn trace(&mut self, frame: Vec<u8>, cipher: Option<&dyn ChunkCipher>) -> SgxResult<()> {
+        let job = self.chunk_job()?;
+        let hits = job.trace(frame, cipher)?;
+        self.merge(&job, hits)
+    }
+
+    // query_result::plain_response_size bytes per client in upload order
+    pub fn build_response(&self) -> SgxResult<Vec<u8>> {
+        self.build_response_with_trace(None)
+    }
+
+    // same, recording the indices read by the constant time construction of the direct and
+    // oblivious modes (see EncodedResultBuffer::build_query_response). The indexed mode builds
+    // it from the inverted index of the queries and records nothing
+    pub fn build_response_with_trace(&self, trace: Option<&mut AccessTrace>) -> SgxResult<Vec<u8>> {
+        if !self.uploaded {
+            return Err(sgx_status_t::SGX_ERROR_INVALID_STATE);
+        }
+        let response_size = self.query_buffer.queries.iter().map(|query| plain_response_size(query.parameters.len())).sum();
+        let mut response_vec: Vec<u8> = Vec::with_capacity(response_size);
+        match self.lookup_mode {
+            LookupMode::Direct | LookupMode::Oblivious => {
+                self.result_buffer.build_query_response(&self.query_buffer, &self.mapped_query_buffer, &self.rules, &mut response_vec, trace);
+            },
+            LookupMode::Indexed => {
+                self.result_buffer.build_query_response_from_index(&self.query_buffer, &self.mapped_query_buffer, &self.rules, &mut response_vec);
+            },
+        }
+        Ok(response_vec)
+    }
+
+    // zeroizes and frees the queries and results, rules and lookup mode are kept
+    pub fn clear_batch(&mut self) {
+        for query in self.query_buffer.queries.iter_mut() {
+            zeroize(&mut query.parameters);
+        }
+        zeroize(&mut self.result_buffer.exact_hits);
+        zeroize(&mut self.result_buffer.neighbour_hits);
+        self.query_buffer = EncodedQueryBuffer::new();
+        // zeroized once no ChunkJob holds it any more
+        self.mapped_query_buffer = Arc::new(MappedEncodedQueryBuffer::new());
+        self.result_buffer = EncodedResultBuffer::new();
+        self.uploaded = false;
+    }
+}
+
+impl Drop for ContactTracer {
+    fn drop(&mut self) {
+        self.clear_batch();
+    }
+}
@@ -0,0 +1,89 @@
+use fst::{Set, IntoStreamer, Streamer};
+use std::vec::Vec;
+use sgx_types::*;
+
+use primitive::*;
+use encoded_dictionary_buffer::EncodedDictionary;
+use contact_rules::ContactRules;
+use encoding::Encoding;
+use gp10;
+
+
+#[derive(Clone, Debug, Default)]
+pub struct FstValue { pub value: EncodedValue }
+
+impl FstValue {
+    pub fn new(vec: EncodedValue) -> Self {
+        FstValue { value: vec }
+    }
+}
+
+impl AsRef<[u8]> for FstValue {
+    #[inline]
+    fn as_ref(&self) -> &[u8] {
+        self.value.as_ref()
+    }
+}
+
+// queryデータの方に使います．
+#[derive(Clone, Debug)]
+pub struct EncodedFiniteStateTransducer {
+    pub map: Set<Vec<u8>>,
+}
+
+impl Default for EncodedFiniteStateTransducer {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl EncodedFiniteStateTransducer {
+    pub fn new() -> Self {
+        EncodedFiniteStateTransducer {
+            map: Set::from_iter(Vec::<FstValue>::new()).unwrap()
+        }
+    }
+
+    pub fn build_dictionary_buffer(
+        bytes: Vec<u8>,
+    ) -> SgxResult<Self> {
+        // Set::from_bytes panics on a malformed header
+        match Set::new(bytes) {
+            Ok(map) => Ok(EncodedFiniteStateTransducer { map }),
+            Err(_) => Err(sgx_status_t::SGX_ERROR_INVALID_PARAMETER),
+        }
+    }
+}
+
+impl EncodedDictionary for EncodedFiniteStateTransducer {
+    // answered by one range lookup instead of one lookup per time slot
+    fn contains_within(&self, encoded_value: &EncodedValue, rules: &ContactRules) -> bool {
+        if !rules.has_time_tolerance() {
+            return self.map.contains(encoded_value);
+        }
+        match gp10::time_window(encoded_value, rules) {
+            Some((start, end)) => self.map.range().ge(start).le(end).into_stream().next().is_some(),
+            None => self.map.contains(encoded_value),
+        }
+    }
+
+    fn len(&self) -> usize {
+        self.map.len()
+    }
+
+    // the whole set in key order
+    fn keys(&self, encoding: Encoding) -> Vec<EncodedValue> {
+        let mut keys: Vec<EncodedValue> = Vec::with_capacity(self.map.len());
+        let mut stream = self.map.stream();
+        while let Some(key) = stream.next() {
+            if key.len() == encoding.value_size() {
+                keys.push(EncodedValue::from_slice(key).unwrap());
+            }
+        }
+        keys
+    }
+
+    fn calc_memory(&self) {
+        println!("[FSA] r_i size = {} bytes", self.map.as_ref().size());
+    }
+}
@@ -0,0 +1,175 @@
+use std::vec::Vec;
+use std::boxed::Box;
+use sgx_types::*;
+
+use primitive::*;
+use mapped_encoded_query_buffer::MappedEncodedQueryBuffer;
+use encoded_result_buffer::EncodedResultBuffer;
+use encoded_hash_table::EncodedHashTable;
+use encode_finite_state_transducer::EncodedFiniteStateTransducer;
+use contact_rules::ContactRules;
+use encoding::Encoding;
+use oblivious;
+use gp10;
+use chunk_codec;
+
+/*
+Trait EncodedDictionary
+    Read side of one chunk of the central data. The host builds the chunk with
+    the ChunkBuilder of the same id (app/src/dictionary.rs).
+*/
+pub trait EncodedDictionary {
+    // a query point matches if the same cell has a key in [t - before, t + after]
+    fn contains_within(&self, encoded_value: &EncodedValue, rules: &ContactRules) -> bool;
+
+    fn calc_memory(&self);
+
+    // number of keys, the count of the chunk frame (see wire.rs)
+    fn len(&self) -> usize;
+
+    fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    // every key of the chunk of the given encoding, in an order that only depends on the chunk
+    fn keys(&self, encoding: Encoding) -> Vec<EncodedValue>;
+
+    // ContactRules::check_encoding only lets a spatial tolerance through for gp10
+    fn neighbour_contains_within(&self, encoded_value: &EncodedValue, rules: &ContactRules) -> bool {
+        if rules.spatial_tolerance == 0 {
+            return false;
+        }
+        gp10::neighbours(encoded_value).iter().any(|neighbour| self.contains_within(neighbour, rules))
+    }
+
+    fn intersect(&self, mapped_query_buffer: &MappedEncodedQueryBuffer, rules: &ContactRules, result: &mut EncodedResultBuffer) {
+        for (i, encoded_value_vec) in mapped_query_buffer.map.iter().enumerate() {
+            if self.contains_within(encoded_value_vec, rules) {
+                result.exact_hits[i] = 1;
+            } else if self.neighbour_contains_within(encoded_value_vec, rules) {
+                result.neighbour_hits[i] = 1;
+            }
+        }
+    }
+}
+
+// first byte of every chunk, written by the ChunkBuilders of the app
+pub const DICTIONARY_FST: u8 = 1;
+pub const DICTIONARY_HASHTABLE: u8 = 2;
+
+/*
+Type LookupMode
+    Direct looks every query value up in the dictionary of the chunk, so the
+    pages and cache lines it touches depend on the cells clients visited.
+    Oblivious merges the query values with all keys of the chunk (see oblivious.rs),
+    its memory accesses only depend on the number of query values and keys.
+    Both build the responses in constant time (EncodedResultBuffer::build_query_response).
+    Indexed looks the chunks up like Direct and builds the responses from an inverted
+    index of the query values, visiting only the points of values that hit. Its work
+    and memory accesses give away which points matched, so it is not oblivious and
+    the enclave refuses it; it is meant for the native backend.
+*/
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum LookupMode {
+    Direct,
+    Oblivious,
+    Indexed,
+}
+
+// #[default] on the variant is newer than the toolchain of the Rust SGX SDK
+#[allow(clippy::derivable_impls)]
+impl Default for LookupMode {
+    fn default() -> Self {
+        LookupMode::Direct
+    }
+}
+
+impl LookupMode {
+    pub fn from_id(id: u8) -> Option<Self> {
+        match id {
+            1 => Some(LookupMode::Direct),
+            2 => Some(LookupMode::Oblivious),
+            3 => Some(LookupMode::Indexed),
+            _ => None,
+        }
+    }
+
+    pub fn id(&self) -> u8 {
+        match *self {
+            LookupMode::Direct => 1,
+            LookupMode::Oblivious => 2,
+            LookupMode::Indexed => 3,
+        }
+    }
+}
+
+/*
+Trait ChunkCipher
+    Decrypts and authenticates a chunk sealed by the health authority, so the
+    host only ever handles ciphertext. pct_core has no crypto of its own, the
+    enclave implements this with the provisioned chunk key.
+*/
+pub trait ChunkCipher {
+    // sealed chunk -> backend id || codec || dictionary, SGX_ERROR_MAC_MISMATCH if it has been modified
+    fn open(&self, sealed_chunk: &[u8]) -> SgxResult<Vec<u8>>;
+}
+
+pub struct EncodedDictionaryBuffer {
+    pub data: Box<dyn EncodedDictionary>,
+}
+
+impl EncodedDictionaryBuffer {
+    /*
+        chunk = backend id (1 byte) || codec (1 byte) || serialized dictionary compressed by codec
+        (see chunk_codec.rs), sealed as a whole if cipher is given
+    */
+    pub fn build_dictionary_buffer(
+        chunk: Vec<u8>,
+        cipher: Option<&dyn ChunkCipher>,
+    ) -> SgxResult<Self> {
+        let mut chunk = match cipher {
+            Some(cipher) => cipher.open(&chunk)?,
+            None => chunk,
+        };
+        if chunk.len() < 2 {
+            return Err(sgx_status_t::SGX_ERROR_INVALID_PARAMETER);
+        }
+        let (backend_id, codec) = (chunk[0], chunk[1]);
+        chunk.drain(..2);
+        let chunk = chunk_codec::decompress(codec, chunk)?;
+        let data: Box<dyn EncodedDictionary> = match backend_id {
+            DICTIONARY_FST => Box::new(EncodedFiniteStateTransducer::build_dictionary_buffer(chunk)?),
+            DICTIONARY_HASHTABLE => Box::new(EncodedHashTable::build_dictionary_buffer(chunk)?),
+            _ => { return Err(sgx_status_t::SGX_ERROR_FEATURE_NOT_SUPPORTED); }
+        };
+        Ok(EncodedDictionaryBuffer { data })
+    }
+
+    pub fn intersect(
+        &self,
+        mode: LookupMode,
+        mapped_query_buffer: &MappedEncodedQueryBuffer,
+        rules: &ContactRules,
+        result: &mut EncodedResultBuffer,
+    ) {
+        match mode {
+            LookupMode::Direct | LookupMode::Indexed => self.data.intersect(mapped_query_buffer, rules, result),
+            LookupMode::Oblivious => {
+                let keys = self.data.keys(mapped_query_buffer.encoding);
+                oblivious::intersect(&keys, mapped_query_buffer, rules, result, None);
+            },
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.data.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.data.is_empty()
+    }
+
+    pub fn show_size(&self) {
+        self.data.calc_memory();
+    }
+}
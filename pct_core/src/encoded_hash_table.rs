@@ -0,0 +1,59 @@
+use std::vec::Vec;
+use std::collections::HashSet;
+use std::mem;
+use sgx_types::*;
+use bincode;
+use primitive::*;
+use constant::*;
+use encoded_dictionary_buffer::EncodedDictionary;
+use contact_rules::ContactRules;
+use encoding::Encoding;
+use gp10;
+
+// keys are raw bytes because their length depends on the encoding of the chunk
+#[derive(Clone, Default, Debug)]
+pub struct EncodedHashTable {
+    pub map: HashSet<Vec<u8>>,
+}
+impl EncodedHashTable {
+    pub fn new() -> Self {
+        EncodedHashTable {
+            map: HashSet::with_capacity(THREASHOLD)
+        }
+    }
+
+    pub fn build_dictionary_buffer(
+        bytes: Vec<u8>,
+    ) -> SgxResult<Self> {
+        match bincode::deserialize(&bytes[..]) {
+            Ok(map) => Ok(EncodedHashTable { map }),
+            Err(_) => Err(sgx_status_t::SGX_ERROR_INVALID_PARAMETER),
+        }
+    }
+}
+
+impl EncodedDictionary for EncodedHashTable {
+    // no range lookups on a hash table, so every slot of the window is probed
+    fn contains_within(&self, encoded_value: &EncodedValue, rules: &ContactRules) -> bool {
+        if !rules.has_time_tolerance() {
+            return self.map.contains(encoded_value.as_ref());
+        }
+        gp10::time_window_values(encoded_value, rules).iter().any(|value| self.map.contains(value.as_ref()))
+    }
+
+    fn len(&self) -> usize {
+        self.map.len()
+    }
+
+    // iteration order of the table, which only depends on the keys of the chunk
+    fn keys(&self, encoding: Encoding) -> Vec<EncodedValue> {
+        self.map.iter()
+            .filter(|key| key.len() == encoding.value_size())
+            .map(|key| EncodedValue::from_slice(key).unwrap())
+            .collect()
+    }
+
+    fn calc_memory(&self) {
+        println!("[HashTable] r_i size = {} bytes", (self.map.capacity() * 11 / 10) * (mem::size_of::<Vec<u8>>() + MAX_ENCODEDVALUE_SIZE + mem::size_of::<()>() + mem::size_of::<u64>()));
+    }
+}
@@ -0,0 +1,50 @@
+use encoded_query_rep::EncodedQueryRep;
+use primitive::EncodedValue;
+use encoding::Encoding;
+use constant::MAX_QUERY_SIZE;
+use sgx_types::*;
+use std::vec::Vec;
+
+#[derive(Clone, Default, Debug)]
+pub struct EncodedQueryBuffer {
+    pub encoding: Encoding,
+    pub queries: Vec<EncodedQueryRep>,
+}
+
+impl EncodedQueryBuffer {
+    pub fn new() -> Self {
+        EncodedQueryBuffer::default()
+    }
+
+    // queryを個々に組み立ててbufferに保持する
+    // decrypted_queries[i] is the plaintext of client query_id_list_vec[i], at most
+    // MAX_QUERY_SIZE values of encoding.value_size() bytes, nothing is kept otherwise
+    pub fn build_query_buffer(
+        &mut self,
+        encoding: Encoding,
+        decrypted_queries: &[Vec<u8>],
+        query_id_list_vec: Vec<u64>,
+    ) -> SgxResult<()> {
+        let value_size = encoding.value_size();
+        if decrypted_queries.len() != query_id_list_vec.len() {
+            return Err(sgx_status_t::SGX_ERROR_INVALID_PARAMETER);
+        }
+        if decrypted_queries.iter().any(|query| query.len() % value_size != 0 || query.len() / value_size > MAX_QUERY_SIZE) {
+            return Err(sgx_status_t::SGX_ERROR_INVALID_PARAMETER);
+        }
+        self.encoding = encoding;
+        self.queries.reserve(query_id_list_vec.len());
+        for (query_id, decrypted_query) in query_id_list_vec.iter().zip(decrypted_queries.iter()) {
+            let mut query = EncodedQueryRep::new(decrypted_query.len() / value_size);
+            query.id = *query_id;
+            for chunk in decrypted_query.chunks(value_size) {
+                match EncodedValue::from_slice(chunk) {
+                    Some(encoded_value) => query.parameters.push(encoded_value),
+                    None => { return Err(sgx_status_t::SGX_ERROR_INVALID_PARAMETER); }
+                }
+            }
+            self.queries.push(query);
+        }
+        Ok(())
+    }
+}
\ No newline at end of file
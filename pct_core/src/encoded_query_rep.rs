@@ -1,6 +1,5 @@
 use std::vec::Vec;
 use primitive::{ QueryId, EncodedValue };
-use constant::*;
 
 /* Type EncodedQueryRep */
 #[derive(Clone, Default, Debug)]
@@ -10,10 +9,10 @@ pub struct EncodedQueryRep {
 }
 
 impl EncodedQueryRep {
-    pub fn new() -> Self {
+    pub fn new(query_size: usize) -> Self {
         EncodedQueryRep {
             id: 0,
-            parameters: Vec::with_capacity(QUERY_SIZE),
+            parameters: Vec::with_capacity(query_size),
         }
     }
 }
\ No newline at end of file
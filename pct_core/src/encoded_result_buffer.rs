@@ -0,0 +1,206 @@
+use std::vec::Vec;
+use sgx_types::*;
+use encoded_query_buffer::EncodedQueryBuffer;
+use mapped_encoded_query_buffer::MappedEncodedQueryBuffer;
+use query_result::QueryResult;
+use contact_rules::ContactRules;
+use constant_time::*;
+use utils::zeroize;
+
+/*
+Type EncodedResultBuffer
+    hit flags aligned with MappedEncodedQueryBuffer::map, so both lookup modes
+    record a chunk the same way and the oblivious one can OR every flag in order.
+    Sized by the number of points rather than map.len(), the flags past map.len()
+    stay 0, so merging and reading them back does not depend on how many values repeat.
+*/
+#[derive(Clone, Default, Debug)]
+pub struct EncodedResultBuffer {
+    // 1 once the query value hit the same cell in any chunk
+    pub exact_hits: Vec<u8>,
+    // 1 once the query value hit a neighbouring cell in any chunk
+    pub neighbour_hits: Vec<u8>,
+}
+
+impl EncodedResultBuffer {
+    pub fn new() -> Self {
+        EncodedResultBuffer::default()
+    }
+
+    pub fn with_size(size: usize) -> Self {
+        EncodedResultBuffer {
+            exact_hits: vec![0; size],
+            neighbour_hits: vec![0; size],
+        }
+    }
+
+    // ORs the flags of one chunk in (see ContactTracer::merge) and zeroizes them, branch free
+    // like the oblivious lookup that produced them
+    pub fn merge(&mut self, mut other: EncodedResultBuffer) -> SgxResult<()> {
+        if other.exact_hits.len() != self.exact_hits.len() || other.neighbour_hits.len() != self.neighbour_hits.len() {
+            return Err(sgx_status_t::SGX_ERROR_INVALID_PARAMETER);
+        }
+        for (hit, other_hit) in self.exact_hits.iter_mut().zip(other.exact_hits.iter()) {
+            *hit |= *other_hit;
+        }
+        for (hit, other_hit) in self.neighbour_hits.iter_mut().zip(other.neighbour_hits.iter()) {
+            *hit |= *other_hit;
+        }
+        zeroize(&mut other.exact_hits);
+        zeroize(&mut other.neighbour_hits);
+        Ok(())
+    }
+
+    /*
+        (exact, neighbour) flags of every point in upload order, an exact hit in any
+        chunk wins over neighbour hits in other chunks. The flags of the values and the
+        points, which name their value by its secret index into map, are put into one
+        array, which is
+            1. bitonic sorted by index, the flags of a value before its points
+            2. scanned, every point takes the flags of the value before it
+            3. bitonic sorted back into upload order
+        like the mapping of MappedEncodedQueryBuffer. The flags are as many as the
+        points, so the accesses only depend on the number of points n, at
+        O(n log^2 n) work. trace records every index touched.
+    */
+    pub fn point_flags(
+        &self,
+        mapped_query_buffer: &MappedEncodedQueryBuffer,
+        trace: &mut Option<&mut AccessTrace>,
+    ) -> Vec<(u8, u8)> {
+        let total_size = mapped_query_buffer.positions.len();
+        let size = (2 * total_size).next_power_of_two();
+        let mut slots: Vec<Slot> = Vec::with_capacity(size);
+        for i in 0..total_size {
+            record(trace, i);
+            slots.push(Slot {
+                index: i as u32,
+                point: i as u32,
+                is_point: 0,
+                exact: self.exact_hits[i] as u32,
+                neighbour: self.neighbour_hits[i] as u32,
+            });
+        }
+        for (point, index) in mapped_query_buffer.positions.iter().enumerate() {
+            record(trace, total_size + point);
+            slots.push(Slot { index: *index, point: point as u32, is_point: 1, exact: 0, neighbour: 0 });
+        }
+        while slots.len() < size {
+            record(trace, slots.len());
+            slots.push(Slot { index: u32::MAX, point: u32::MAX, is_point: 1, exact: 0, neighbour: 0 });
+        }
+
+        bitonic_sort(&mut slots, greater_by_index, conditional_swap, trace);
+        let mut exact: u32 = 0;
+        let mut neighbour: u32 = 0;
+        for (i, slot) in slots.iter_mut().enumerate() {
+            record(trace, i);
+            exact = ct_select(slot.is_point, exact, slot.exact);
+            neighbour = ct_select(slot.is_point, neighbour, slot.neighbour);
+            slot.exact = exact;
+            slot.neighbour = neighbour;
+        }
+
+        bitonic_sort(&mut slots, greater_by_point, conditional_swap, trace);
+        let mut flags: Vec<(u8, u8)> = Vec::with_capacity(total_size);
+        for (i, slot) in slots.iter().take(total_size).enumerate() {
+            record(trace, i);
+            flags.push((slot.exact as u8, (slot.neighbour & (slot.exact ^ 1)) as u8));
+        }
+        zeroize(&mut slots);
+        flags
+    }
+
+    // reposne format
+    // query.id(8byte) + reuslt(see QueryResult::to_be_bytes), plain_response_size bytes per client
+    // every point of every client is visited once whether it hit or not, so the work
+    // only depends on the number of clients and points; trace records the indices
+    // point_flags touches.
+    // Used with LookupMode::Direct and LookupMode::Oblivious
+    pub fn build_query_response(
+        &self,
+        query_buffer: &EncodedQueryBuffer,
+        mapped_query_buffer: &MappedEncodedQueryBuffer,
+        rules: &ContactRules,
+        response_vec: &mut Vec<u8>,
+        mut trace: Option<&mut AccessTrace>,
+    ) {
+        let mut flags = self.point_flags(mapped_query_buffer, &mut trace);
+        let mut cursor: usize = 0;
+        for query in query_buffer.queries.iter() {
+            let size = query.parameters.len();
+            let mut result = QueryResult::with_size(size);
+            result.query_id = query.id;
+            result.score(flags[cursor..cursor+size].iter().cloned(), rules);
+            response_vec.extend_from_slice(&result.to_be_bytes());
+            cursor += size;
+        }
+        zeroize(&mut flags);
+    }
+
+    // same response, assembled from the query values that hit through the inverted index
+    // of mapped_query_buffer: only the points of those values are visited, so the work
+    // grows with the hits rather than with clients x points. Building the index, skipping
+    // the values that missed and sorting the hits of every client all depend on which
+    // points matched, so this is only used with LookupMode::Indexed, outside the enclave
+    pub fn build_query_response_from_index(
+        &self,
+        query_buffer: &EncodedQueryBuffer,
+        mapped_query_buffer: &MappedEncodedQueryBuffer,
+        rules: &ContactRules,
+        response_vec: &mut Vec<u8>,
+    ) {
+        // (point, exact, neighbour) of the points of every client that hit
+        let index = mapped_query_buffer.inverted_index(query_buffer);
+        let mut hits: Vec<Vec<(u32, u8, u8)>> = vec![Vec::new(); query_buffer.queries.len()];
+        for value in 0..mapped_query_buffer.map.len() {
+            let exact = self.exact_hits[value];
+            let neighbour = self.neighbour_hits[value] & (exact ^ 1);
+            if exact | neighbour == 0 {
+                continue;
+            }
+            for &(client, point) in index.points_of(value) {
+                hits[client as usize].push((point, exact, neighbour));
+            }
+        }
+        for (query, client_hits) in query_buffer.queries.iter().zip(hits.iter_mut()) {
+            client_hits.sort_unstable();
+            let mut result = QueryResult::with_size(query.parameters.len());
+            result.query_id = query.id;
+            result.score_hits(client_hits, rules);
+            response_vec.extend_from_slice(&result.to_be_bytes());
+            zeroize(client_hits);
+        }
+    }
+}
+
+#[derive(Clone, Copy, Default)]
+struct Slot {
+    // index into map, u32::MAX for padding
+    index: u32,
+    // upload order of a point, index of a value
+    point: u32,
+    // 0 for the flags of a value, 1 for a point or padding
+    is_point: u32,
+    exact: u32,
+    neighbour: u32,
+}
+
+// padding last, then index, a value before its points
+fn greater_by_index(a: &Slot, b: &Slot) -> u32 {
+    ct_lt(b.index, a.index) | (ct_eq(a.index, b.index) & ct_lt(b.is_point, a.is_point))
+}
+
+// the points first, in upload order
+fn greater_by_point(a: &Slot, b: &Slot) -> u32 {
+    ct_lt(a.is_point, b.is_point) | (ct_eq(a.is_point, b.is_point) & ct_lt(b.point, a.point))
+}
+
+fn conditional_swap(swap: u32, a: &mut Slot, b: &mut Slot) {
+    let mask = 0_u32.wrapping_sub(swap);
+    ct_swap_u32(mask, &mut a.index, &mut b.index);
+    ct_swap_u32(mask, &mut a.point, &mut b.point);
+    ct_swap_u32(mask, &mut a.is_point, &mut b.is_point);
+    ct_swap_u32(mask, &mut a.exact, &mut b.exact);
+    ct_swap_u32(mask, &mut a.neighbour, &mut b.neighbour);
+}
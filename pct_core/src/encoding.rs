@@ -0,0 +1,96 @@
+/*
+Type Encoding
+    Encoding of the trajectory data, chosen by the host when a query batch is
+    uploaded instead of at build time. The app detects it from the length of
+    the strings of a dataset, see detect.
+*/
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Encoding {
+    // TrajectoryHash, binary of 48/54/60/72 bits
+    Th48,
+    Th54,
+    Th60,
+    Th72,
+    // geohash (10 chars) + time slot (4 digits), ascii
+    Gp10,
+}
+
+// #[default] on the variant is newer than the toolchain of the Rust SGX SDK
+#[allow(clippy::derivable_impls)]
+impl Default for Encoding {
+    fn default() -> Self {
+        Encoding::Gp10
+    }
+}
+
+impl Encoding {
+    pub fn from_id(id: u8) -> Option<Self> {
+        match id {
+            1 => Some(Encoding::Th48),
+            2 => Some(Encoding::Th54),
+            3 => Some(Encoding::Th60),
+            4 => Some(Encoding::Th72),
+            5 => Some(Encoding::Gp10),
+            _ => None,
+        }
+    }
+
+    pub fn id(&self) -> u8 {
+        match *self {
+            Encoding::Th48 => 1,
+            Encoding::Th54 => 2,
+            Encoding::Th60 => 3,
+            Encoding::Th72 => 4,
+            Encoding::Gp10 => 5,
+        }
+    }
+
+    // bytes of one encoded value
+    pub fn value_size(&self) -> usize {
+        match *self {
+            Encoding::Th48 => 6,
+            Encoding::Th54 => 7,
+            Encoding::Th60 => 8,
+            Encoding::Th72 => 9,
+            Encoding::Gp10 => 14,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match *self {
+            Encoding::Th48 => "th48",
+            Encoding::Th54 => "th54",
+            Encoding::Th60 => "th60",
+            Encoding::Th72 => "th72",
+            Encoding::Gp10 => "gp10",
+        }
+    }
+
+    // length of the strings of this encoding, see detect
+    pub fn string_size(&self) -> usize {
+        match *self {
+            Encoding::Th48 => 16,
+            Encoding::Th54 => 18,
+            Encoding::Th60 => 20,
+            Encoding::Th72 => 24,
+            Encoding::Gp10 => 14,
+        }
+    }
+
+    // TrajectoryHash strings are base8 (3 bits per char), gp10 strings are 10 + 4 chars
+    pub fn detect(encoded_string: &str) -> Option<Self> {
+        match encoded_string.len() {
+            16 => Some(Encoding::Th48),
+            18 => Some(Encoding::Th54),
+            20 => Some(Encoding::Th60),
+            24 => Some(Encoding::Th72),
+            14 => Some(Encoding::Gp10),
+            _ => None,
+        }
+    }
+
+    // time and space are separable (time window and neighbouring cells can be computed)
+    pub fn is_gp10(&self) -> bool {
+        *self == Encoding::Gp10
+    }
+}
@@ -0,0 +1,141 @@
+use std::vec::Vec;
+use primitive::*;
+use contact_rules::ContactRules;
+
+/*
+gp10 encoding
+    10 geohash characters followed by a zero padded 4 digit time slot index (ascii),
+    so all keys of one cell are adjacent and ordered by time in a sorted dictionary.
+*/
+pub const TIME_SLOT_U8_SIZE: usize = 4;
+pub const CELL_U8_SIZE: usize = 10;
+pub const GP10_U8_SIZE: usize = CELL_U8_SIZE + TIME_SLOT_U8_SIZE;
+pub const MAX_TIME_SLOT: u32 = 9999;
+
+pub fn time_slot(value: &EncodedValue) -> Option<u32> {
+    if value.len() != GP10_U8_SIZE {
+        return None;
+    }
+    let mut slot: u32 = 0;
+    for digit in value.as_ref()[CELL_U8_SIZE..].iter() {
+        if *digit < b'0' || *digit > b'9' {
+            return None;
+        }
+        slot = slot * 10 + (*digit - b'0') as u32;
+    }
+    Some(slot)
+}
+
+pub fn with_time_slot(value: &EncodedValue, slot: u32) -> EncodedValue {
+    let mut encoded_value = *value;
+    let mut rest = slot;
+    for i in (CELL_U8_SIZE..GP10_U8_SIZE).rev() {
+        encoded_value.as_mut()[i] = b'0' + (rest % 10) as u8;
+        rest /= 10;
+    }
+    encoded_value
+}
+
+// inclusive key range [cell || t - before, cell || t + after] of the same cell
+pub fn time_window(value: &EncodedValue, rules: &ContactRules) -> Option<(EncodedValue, EncodedValue)> {
+    let slot = time_slot(value)?;
+    let start = slot.saturating_sub(rules.time_tolerance_before);
+    let end = slot.saturating_add(rules.time_tolerance_after).min(MAX_TIME_SLOT);
+    Some((with_time_slot(value, start), with_time_slot(value, end)))
+}
+
+// every key of the window, for dictionaries without range lookups
+pub fn time_window_values(value: &EncodedValue, rules: &ContactRules) -> Vec<EncodedValue> {
+    match time_window(value, rules) {
+        Some((start, end)) => {
+            let start_slot = time_slot(&start).unwrap();
+            let end_slot = time_slot(&end).unwrap();
+            (start_slot..end_slot+1).map(|slot| with_time_slot(value, slot)).collect()
+        },
+        None => vec![*value],
+    }
+}
+
+const GEOHASH_BASE32: &[u8; 32] = b"0123456789bcdefghjkmnpqrstuvwxyz";
+
+fn geohash_index(c: u8) -> Option<u64> {
+    GEOHASH_BASE32.iter().position(|b| *b == c).map(|i| i as u64)
+}
+
+// (longitude index, latitude index, longitude bits, latitude bits) of the cell on the geohash grid
+fn decode_cell(cell: &[u8]) -> Option<(u64, u64, u32, u32)> {
+    let mut lon: u64 = 0;
+    let mut lat: u64 = 0;
+    let mut lon_bits: u32 = 0;
+    let mut lat_bits: u32 = 0;
+    let mut is_lon = true;
+    for c in cell.iter() {
+        let index = geohash_index(*c)?;
+        for shift in (0..5).rev() {
+            let bit = (index >> shift) & 1;
+            if is_lon {
+                lon = (lon << 1) | bit;
+                lon_bits += 1;
+            } else {
+                lat = (lat << 1) | bit;
+                lat_bits += 1;
+            }
+            is_lon = !is_lon;
+        }
+    }
+    Some((lon, lat, lon_bits, lat_bits))
+}
+
+fn encode_cell(lon: u64, lat: u64, lon_bits: u32, lat_bits: u32, cell: &mut [u8]) {
+    let mut lon_left = lon_bits;
+    let mut lat_left = lat_bits;
+    let mut is_lon = true;
+    for c in cell.iter_mut() {
+        let mut index: u64 = 0;
+        for _ in 0..5 {
+            let bit = if is_lon {
+                lon_left -= 1;
+                (lon >> lon_left) & 1
+            } else {
+                lat_left -= 1;
+                (lat >> lat_left) & 1
+            };
+            index = (index << 1) | bit;
+            is_lon = !is_lon;
+        }
+        *c = GEOHASH_BASE32[index as usize];
+    }
+}
+
+// the 8 cells around the cell of value with the same time slot.
+// longitude wraps around the antimeridian, there is no cell beyond the poles.
+pub fn neighbours(value: &EncodedValue) -> Vec<EncodedValue> {
+    let mut neighbour_values: Vec<EncodedValue> = Vec::with_capacity(8);
+    if value.len() != GP10_U8_SIZE {
+        return neighbour_values;
+    }
+    let (lon, lat, lon_bits, lat_bits) = match decode_cell(&value.as_ref()[..CELL_U8_SIZE]) {
+        Some(decoded) => decoded,
+        None => { return neighbour_values; }
+    };
+    let lon_cells: u64 = 1 << lon_bits;
+    let lat_cells: u64 = 1 << lat_bits;
+    for d_lat in [-1_i64, 0, 1].iter() {
+        let neighbour_lat = lat as i64 + d_lat;
+        if neighbour_lat < 0 || neighbour_lat >= lat_cells as i64 {
+            continue;
+        }
+        for d_lon in [-1_i64, 0, 1].iter() {
+            if *d_lat == 0 && *d_lon == 0 {
+                continue;
+            }
+            let neighbour_lon = ((lon as i64 + d_lon).rem_euclid(lon_cells as i64)) as u64;
+            let mut neighbour_value = *value;
+            encode_cell(neighbour_lon, neighbour_lat as u64, lon_bits, lat_bits, &mut neighbour_value.as_mut()[..CELL_U8_SIZE]);
+            if neighbour_value != *value && !neighbour_values.contains(&neighbour_value) {
+                neighbour_values.push(neighbour_value);
+            }
+        }
+    }
+    neighbour_values
+}
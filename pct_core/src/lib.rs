@@ -0,0 +1,44 @@
+/*
+pct_core
+    Contact tracing logic of the enclave as plain Rust. The enclave builds it with
+    the mesalock_sgx feature on top of sgx_tstd, the app links the std build for
+    the in-process native backend. Nothing in here encrypts or attests, that is
+    up to the caller.
+*/
+#![cfg_attr(all(feature = "mesalock_sgx", not(target_env = "sgx")), no_std)]
+#![cfg_attr(target_env = "sgx", feature(rustc_private))]
+
+extern crate sgx_types;
+#[cfg(all(feature = "mesalock_sgx", not(target_env = "sgx")))]
+#[macro_use]
+extern crate sgx_tstd as std;
+#[cfg(feature = "mesalock_sgx")]
+extern crate sgx_fst as fst;
+#[cfg(feature = "mesalock_sgx")]
+extern crate bincode_sgx as bincode;
+#[cfg(not(feature = "mesalock_sgx"))]
+extern crate fst;
+#[cfg(not(feature = "mesalock_sgx"))]
+extern crate bincode;
+
+pub mod constant;
+pub mod primitive;
+pub mod utils;
+pub mod encoded_query_rep;
+pub mod period;
+pub mod query_result;
+pub mod encoded_query_buffer;
+pub mod encoded_result_buffer;
+pub mod mapped_encoded_query_buffer;
+pub mod encoded_dictionary_buffer;
+pub mod encoded_hash_table;
+pub mod encode_finite_state_transducer;
+pub mod constant_time;
+pub mod oblivious;
+pub mod contact_rules;
+pub mod encoding;
+pub mod gp10;
+pub mod contact_tracer;
+pub mod session;
+pub mod wire;
+pub mod chunk_codec;
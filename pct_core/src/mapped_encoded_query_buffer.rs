@@ -0,0 +1,195 @@
+use std::vec::Vec;
+use constant::*;
+use primitive::*;
+use utils::zeroize;
+use constant_time::*;
+use encoding::Encoding;
+use encoded_query_buffer::EncodedQueryBuffer;
+
+#[derive(Clone, Default, Debug)]
+pub struct MappedEncodedQueryBuffer {
+    pub encoding: Encoding,
+    // unique query values in key order
+    pub map: Vec<EncodedValue>,
+    // index into map of every point of every client, in upload order
+    pub positions: Vec<u32>,
+}
+
+/*
+Type InvertedIndex
+    the points of map[i] are postings[offsets[i]..offsets[i+1]], as (client, point of
+    that client) in upload order. Counting sorted by the secret positions, only built
+    for LookupMode::Indexed
+*/
+#[derive(Default)]
+pub struct InvertedIndex {
+    offsets: Vec<u32>,
+    postings: Vec<(u32, u32)>,
+}
+
+impl InvertedIndex {
+    // (client, point) of every occurrence of map[index]
+    pub fn points_of(&self, index: usize) -> &[(u32, u32)] {
+        &self.postings[self.offsets[index] as usize..self.offsets[index + 1] as usize]
+    }
+}
+
+impl Drop for InvertedIndex {
+    fn drop(&mut self) {
+        zeroize(&mut self.offsets);
+        zeroize(&mut self.postings);
+    }
+}
+
+impl MappedEncodedQueryBuffer {
+    pub fn new() -> Self {
+        MappedEncodedQueryBuffer::default()
+    }
+
+    // query_buffer has been checked by build_query_buffer, nothing can fail here
+    pub fn mapping(&mut self, query_buffer: &EncodedQueryBuffer) {
+        self.mapping_with_trace(query_buffer, None);
+    }
+
+    /*
+        same, recording the indices of the points it touches. The points are deduplicated
+        like the keys of oblivious.rs, with bitonic sorts and branch free scans:
+            1. sorted by value, the first point of every run of equal values is numbered
+            2. sorted with the numbered points first, their values become map
+            3. sorted back into upload order, their numbers become positions
+        The accesses only depend on the number of points, map.len() tells how many
+        distinct values the batch has.
+    */
+    pub fn mapping_with_trace(&mut self, query_buffer: &EncodedQueryBuffer, mut trace: Option<&mut AccessTrace>) {
+        self.encoding = query_buffer.encoding;
+        let value_size = query_buffer.encoding.value_size();
+        let total_size: usize = query_buffer.queries.iter().map(|query_rep| query_rep.parameters.len()).sum();
+        let size = total_size.next_power_of_two();
+        let mut points: Vec<Point> = Vec::with_capacity(size);
+        for query_rep in query_buffer.queries.iter() {
+            for encoded_value in query_rep.parameters.iter() {
+                record(&mut trace, points.len());
+                points.push(Point::new(encoded_value, points.len() as u32));
+            }
+        }
+        while points.len() < size {
+            record(&mut trace, points.len());
+            points.push(Point::padding());
+        }
+
+        bitonic_sort(&mut points, greater_by_key, conditional_swap, &mut trace);
+        let mut last_key = [0_u8; MAX_ENCODEDVALUE_SIZE];
+        let mut have_last: u32 = 0;
+        let mut unique: u32 = 0;
+        for (i, point) in points.iter_mut().enumerate() {
+            record(&mut trace, i);
+            let (_, same_key) = ct_cmp_bytes(&point.key, &last_key);
+            point.first = (1 ^ (have_last & same_key)) & (1 ^ point.padding);
+            unique += point.first;
+            point.index = unique.wrapping_sub(1);
+            last_key = point.key;
+            have_last = 1;
+        }
+
+        bitonic_sort(&mut points, greater_by_first, conditional_swap, &mut trace);
+        self.map = Vec::with_capacity(total_size);
+        for (i, point) in points.iter().take(total_size).enumerate() {
+            record(&mut trace, i);
+            self.map.push(EncodedValue::from_slice(&point.key[..value_size]).unwrap_or_default());
+        }
+        zeroize(&mut self.map[unique as usize..]);
+        self.map.truncate(unique as usize);
+
+        bitonic_sort(&mut points, greater_by_point, conditional_swap, &mut trace);
+        self.positions = Vec::with_capacity(total_size);
+        for (i, point) in points.iter().take(total_size).enumerate() {
+            record(&mut trace, i);
+            self.positions.push(point.index);
+        }
+        zeroize(&mut points);
+
+        // println!("Queris are merged, unique query size {}", self.map.len());
+    }
+
+    // counting sort of the points by their index into map
+    pub fn inverted_index(&self, query_buffer: &EncodedQueryBuffer) -> InvertedIndex {
+        let mut index = InvertedIndex::default();
+        index.offsets = vec![0; self.map.len() + 1];
+        for position in self.positions.iter() {
+            index.offsets[*position as usize + 1] += 1;
+        }
+        for i in 0..self.map.len() {
+            index.offsets[i + 1] += index.offsets[i];
+        }
+        let mut next: Vec<u32> = index.offsets[..self.map.len()].to_vec();
+        index.postings = vec![(0, 0); self.positions.len()];
+        let mut cursor = 0;
+        for (client, query_rep) in query_buffer.queries.iter().enumerate() {
+            for point in 0..query_rep.parameters.len() {
+                let position = self.positions[cursor] as usize;
+                index.postings[next[position] as usize] = (client as u32, point as u32);
+                next[position] += 1;
+                cursor += 1;
+            }
+        }
+        index
+    }
+}
+
+#[derive(Clone, Copy, Default)]
+struct Point {
+    // zero padded like oblivious::Entry
+    key: [u8; MAX_ENCODEDVALUE_SIZE],
+    // 1 for padding, which sorts after every point
+    padding: u32,
+    // upload order
+    point: u32,
+    // 1 for the first point of its value in key order
+    first: u32,
+    // index into map of its value
+    index: u32,
+}
+
+impl Point {
+    fn new(encoded_value: &EncodedValue, point: u32) -> Self {
+        let mut key = [0_u8; MAX_ENCODEDVALUE_SIZE];
+        key[..encoded_value.len()].copy_from_slice(encoded_value.as_ref());
+        Point { key, padding: 0, point, first: 0, index: 0 }
+    }
+
+    fn padding() -> Self {
+        Point { key: [0xff; MAX_ENCODEDVALUE_SIZE], padding: 1, point: u32::MAX, first: 0, index: 0 }
+    }
+}
+
+// padding last, then key
+fn greater_by_key(a: &Point, b: &Point) -> u32 {
+    let (b_lt_a, _) = ct_cmp_bytes(&b.key, &a.key);
+    ct_lt(b.padding, a.padding) | (ct_eq(a.padding, b.padding) & b_lt_a)
+}
+
+// the first points in the order of their index
+fn greater_by_first(a: &Point, b: &Point) -> u32 {
+    ct_lt(a.first, b.first) | (ct_eq(a.first, b.first) & ct_lt(b.index, a.index))
+}
+
+fn greater_by_point(a: &Point, b: &Point) -> u32 {
+    ct_lt(b.point, a.point)
+}
+
+fn conditional_swap(swap: u32, a: &mut Point, b: &mut Point) {
+    let mask = 0_u32.wrapping_sub(swap);
+    ct_swap_bytes(mask, &mut a.key, &mut b.key);
+    ct_swap_u32(mask, &mut a.padding, &mut b.padding);
+    ct_swap_u32(mask, &mut a.point, &mut b.point);
+    ct_swap_u32(mask, &mut a.first, &mut b.first);
+    ct_swap_u32(mask, &mut a.index, &mut b.index);
+}
+
+// the query values outlive their batch as long as a ChunkJob holds them, see ContactTracer
+impl Drop for MappedEncodedQueryBuffer {
+    fn drop(&mut self) {
+        zeroize(&mut self.map);
+        zeroize(&mut self.positions);
+    }
+}
@@ -0,0 +1,207 @@
+use std::vec::Vec;
+use constant::*;
+use primitive::*;
+use contact_rules::ContactRules;
+use mapped_encoded_query_buffer::MappedEncodedQueryBuffer;
+use encoded_result_buffer::EncodedResultBuffer;
+use gp10;
+use constant_time::*;
+
+/*
+Oblivious intersection (LookupMode::Oblivious)
+    The query values, the neighbouring cells of each one if the rules ask for them,
+    and every key of the chunk are put into one array, which is
+        1. bitonic sorted by key, a chunk key before an equal query value
+        2. scanned forwards and backwards: a query value hits if the closest chunk key
+           of the same cell before / after it is within the time window
+        3. bitonic sorted back by query value, then OR'ed into the result flags
+    The compare-exchange network of a bitonic sort only depends on the array length
+    and every step is branch free on the contents, so which entries are touched, and
+    in which order, only depends on the number of query values and chunk keys.
+    Sorting costs O(n log^2 n) for n = query values (x9 with neighbours) + chunk keys.
+*/
+
+// gp10::neighbours returns up to 8 cells, padded so every query value takes the same room
+const NEIGHBOUR_SLOTS: usize = 8;
+const NO_ORIGIN: u32 = u32::MAX;
+
+#[derive(Clone, Copy)]
+struct Entry {
+    // zero padded, all values of one batch have the length of its encoding
+    key: [u8; MAX_ENCODEDVALUE_SIZE],
+    // 0 for a chunk key, 1 for a query value or one of its neighbouring cells
+    from_query: u32,
+    // index into MappedEncodedQueryBuffer::map, NO_ORIGIN for chunk keys and padding
+    origin: u32,
+    // 1 if key is a neighbouring cell of the query value
+    neighbour: u32,
+    // 1 for padding, which never hits
+    padding: u32,
+    hit: u32,
+}
+
+impl Entry {
+    fn new(encoded_value: &EncodedValue, from_query: u32, origin: u32, neighbour: u32) -> Self {
+        let mut key = [0_u8; MAX_ENCODEDVALUE_SIZE];
+        key[..encoded_value.len()].copy_from_slice(encoded_value.as_ref());
+        Entry { key, from_query, origin, neighbour, padding: 0, hit: 0 }
+    }
+
+    // sorts after every real key
+    fn padding(origin: u32, neighbour: u32) -> Self {
+        Entry { key: [0xff; MAX_ENCODEDVALUE_SIZE], from_query: 1, origin, neighbour, padding: 1, hit: 0 }
+    }
+}
+
+struct WorkArray<'a> {
+    entries: Vec<Entry>,
+    trace: Option<&'a mut AccessTrace>,
+}
+
+impl<'a> WorkArray<'a> {
+    fn touch(&mut self, i: usize) {
+        record(&mut self.trace, i);
+    }
+
+    fn push(&mut self, entry: Entry) {
+        let i = self.entries.len();
+        self.touch(i);
+        self.entries.push(entry);
+    }
+
+    // the length has to be a power of two
+    fn bitonic_sort(&mut self, greater: fn(&Entry, &Entry) -> u32) {
+        bitonic_sort(&mut self.entries, greater, conditional_swap, &mut self.trace);
+    }
+
+    // a query value hits if the last chunk key before it is in the same cell and at most `before` slots earlier
+    fn scan_forward(&mut self, is_gp10: bool, before: u32) {
+        let mut last_cell = [0_u8; MAX_ENCODEDVALUE_SIZE];
+        let mut last_slot: u32 = 0;
+        let mut have_last: u32 = 0;
+        for i in 0..self.entries.len() {
+            self.touch(i);
+            let entry = &mut self.entries[i];
+            let (cell, slot) = cell_and_slot(&entry.key, is_gp10);
+            let (_, same_cell) = ct_cmp_bytes(&cell, &last_cell);
+            let within = have_last & same_cell & (1 ^ ct_lt(before, slot.wrapping_sub(last_slot)));
+            entry.hit |= within & entry.from_query & (1 ^ entry.padding);
+
+            let is_chunk = 1 ^ entry.from_query;
+            ct_select_bytes(is_chunk, &cell, &mut last_cell);
+            last_slot = ct_select(is_chunk, slot, last_slot);
+            have_last |= is_chunk;
+        }
+    }
+
+    // same with the first chunk key after it and `after` slots
+    fn scan_backward(&mut self, is_gp10: bool, after: u32) {
+        let mut next_cell = [0_u8; MAX_ENCODEDVALUE_SIZE];
+        let mut next_slot: u32 = 0;
+        let mut have_next: u32 = 0;
+        for i in (0..self.entries.len()).rev() {
+            self.touch(i);
+            let entry = &mut self.entries[i];
+            let (cell, slot) = cell_and_slot(&entry.key, is_gp10);
+            let (_, same_cell) = ct_cmp_bytes(&cell, &next_cell);
+            let within = have_next & same_cell & (1 ^ ct_lt(after, next_slot.wrapping_sub(slot)));
+            entry.hit |= within & entry.from_query & (1 ^ entry.padding);
+
+            let is_chunk = 1 ^ entry.from_query;
+            ct_select_bytes(is_chunk, &cell, &mut next_cell);
+            next_slot = ct_select(is_chunk, slot, next_slot);
+            have_next |= is_chunk;
+        }
+    }
+}
+
+// merge order: key, then chunk keys first
+fn greater_by_key(a: &Entry, b: &Entry) -> u32 {
+    let (b_lt_a, equal) = ct_cmp_bytes(&b.key, &a.key);
+    b_lt_a | (equal & ct_lt(b.from_query, a.from_query))
+}
+
+// back to query order: origin, then the value itself before its neighbours
+fn greater_by_origin(a: &Entry, b: &Entry) -> u32 {
+    ct_lt(b.origin, a.origin) | (ct_eq(a.origin, b.origin) & ct_lt(b.neighbour, a.neighbour))
+}
+
+// hit flags of every query value in mapped_query_buffer against the keys of one chunk
+pub fn intersect(
+    keys: &[EncodedValue],
+    mapped_query_buffer: &MappedEncodedQueryBuffer,
+    rules: &ContactRules,
+    result: &mut EncodedResultBuffer,
+    trace: Option<&mut AccessTrace>,
+) {
+    let query_size = mapped_query_buffer.map.len();
+    if query_size == 0 {
+        return;
+    }
+    let is_gp10 = mapped_query_buffer.encoding.is_gp10();
+    let stride = if rules.spatial_tolerance > 0 { 1 + NEIGHBOUR_SLOTS } else { 1 };
+    let size = (query_size * stride + keys.len()).next_power_of_two();
+
+    let mut work = WorkArray { entries: Vec::with_capacity(size), trace };
+    for (i, encoded_value) in mapped_query_buffer.map.iter().enumerate() {
+        work.push(Entry::new(encoded_value, 1, i as u32, 0));
+        if stride > 1 {
+            let neighbours = gp10::neighbours(encoded_value);
+            for slot in 0..NEIGHBOUR_SLOTS {
+                match neighbours.get(slot) {
+                    Some(neighbour) => work.push(Entry::new(neighbour, 1, i as u32, 1)),
+                    None => work.push(Entry::padding(i as u32, 1)),
+                }
+            }
+        }
+    }
+    for key in keys.iter() {
+        work.push(Entry::new(key, 0, NO_ORIGIN, 0));
+    }
+    while work.entries.len() < size {
+        work.push(Entry::padding(NO_ORIGIN, 0));
+    }
+
+    work.bitonic_sort(greater_by_key);
+    work.scan_forward(is_gp10, rules.time_tolerance_before);
+    work.scan_backward(is_gp10, rules.time_tolerance_after);
+    work.bitonic_sort(greater_by_origin);
+
+    // query value i is at i * stride, followed by its neighbours
+    for i in 0..query_size {
+        let base = i * stride;
+        work.touch(base);
+        let exact = work.entries[base].hit;
+        let mut neighbour: u32 = 0;
+        for j in base+1..base+stride {
+            work.touch(j);
+            neighbour |= work.entries[j].hit;
+        }
+        result.exact_hits[i] |= exact as u8;
+        result.neighbour_hits[i] |= neighbour as u8;
+    }
+}
+
+// (cell, time slot) of a gp10 key, (key, 0) for the other encodings
+fn cell_and_slot(key: &[u8; MAX_ENCODEDVALUE_SIZE], is_gp10: bool) -> ([u8; MAX_ENCODEDVALUE_SIZE], u32) {
+    let mut cell = *key;
+    if !is_gp10 {
+        return (cell, 0);
+    }
+    let mut slot: u32 = 0;
+    for i in gp10::CELL_U8_SIZE..gp10::GP10_U8_SIZE {
+        slot = slot.wrapping_mul(10).wrapping_add((key[i] as u32).wrapping_sub(b'0' as u32));
+        cell[i] = 0;
+    }
+    (cell, slot)
+}
+
+fn conditional_swap(swap: u32, a: &mut Entry, b: &mut Entry) {
+    let mask = 0_u32.wrapping_sub(swap);
+    ct_swap_bytes(mask, &mut a.key, &mut b.key);
+    ct_swap_u32(mask, &mut a.from_query, &mut b.from_query);
+    ct_swap_u32(mask, &mut a.origin, &mut b.origin);
+    ct_swap_u32(mask, &mut a.neighbour, &mut b.neighbour);
+    ct_swap_u32(mask, &mut a.padding, &mut b.padding);
+    ct_swap_u32(mask, &mut a.hit, &mut b.hit);
+}
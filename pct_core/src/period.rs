@@ -1,6 +1,7 @@
 use primitive::*;
 use std::vec::Vec;
 use constant::*;
+use contact_rules::ContactRules;
 
 /* Type Period */
 #[derive(Clone, Default, Debug)]
@@ -19,10 +20,10 @@ impl Period {
         self.0
     }
 
-    pub fn from_unixepoch_vector(unixepoch_vec: &Vec<UnixEpoch>) -> Vec<Period> {
+    pub fn from_unixepoch_vector(unixepoch_vec: &[UnixEpoch]) -> Vec<Period> {
         let mut period_vec: Vec<Period> = vec![];
         
-        assert!(unixepoch_vec.len() > 0);
+        assert!(!unixepoch_vec.is_empty());
         let mut latest_unixepoch: UnixEpoch = unixepoch_vec[0];
         let mut period = Period::with_start(latest_unixepoch);
         
@@ -41,8 +42,10 @@ impl Period {
         period_vec
     }
 
-    // period - CONTACT_TIME_THREASHOLD < unixepoch < period + CONTACT_TIME_THREASHOLD
-    pub fn is_include(&self, unixepoch: UnixEpoch) -> bool {
-        self.0 - CONTACT_TIME_THREASHOLD < unixepoch && unixepoch < self.1 + CONTACT_TIME_THREASHOLD
+    // period - before <= unixepoch <= period + after, tolerances of the rules are in TIME_INTERVAL slots
+    pub fn is_include(&self, unixepoch: UnixEpoch, rules: &ContactRules) -> bool {
+        let before = rules.time_tolerance_before as u64 * TIME_INTERVAL;
+        let after = rules.time_tolerance_after as u64 * TIME_INTERVAL;
+        self.0.saturating_sub(before) <= unixepoch && unixepoch <= self.1 + after
     }
 }
\ No newline at end of file
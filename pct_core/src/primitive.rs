@@ -0,0 +1,75 @@
+use std::string::String;
+use std::cmp::Ordering;
+
+use constant::*;
+
+pub type UnixEpoch = u64;
+pub fn unixepoch_from_u8(u_timestamp: [u8; UNIXEPOCH_U8_SIZE]) -> UnixEpoch {
+    let s_timestamp = String::from_utf8(u_timestamp.to_vec()).unwrap();
+    let num: UnixEpoch = s_timestamp.parse().unwrap();
+    num
+}
+
+// バファリングするクエリはせいぜい10000なので64bitで余裕
+pub type QueryId = u64;
+
+/*
+Type EncodedValue
+    length-tagged so that one enclave binary handles every encoding,
+    unused bytes are always zero.
+*/
+#[derive(Clone, Copy, Default, Debug, PartialEq, Eq, Hash)]
+pub struct EncodedValue {
+    len: u8,
+    bytes: [u8; MAX_ENCODEDVALUE_SIZE],
+}
+
+impl EncodedValue {
+    // None if the slice is longer than any encoding
+    pub fn from_slice(slice: &[u8]) -> Option<Self> {
+        if slice.len() > MAX_ENCODEDVALUE_SIZE {
+            return None;
+        }
+        let mut encoded_value = EncodedValue { len: slice.len() as u8, ..Default::default() };
+        encoded_value.bytes[..slice.len()].copy_from_slice(slice);
+        Some(encoded_value)
+    }
+
+    pub fn len(&self) -> usize {
+        self.len as usize
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+}
+
+impl AsRef<[u8]> for EncodedValue {
+    #[inline]
+    fn as_ref(&self) -> &[u8] {
+        &self.bytes[..self.len as usize]
+    }
+}
+
+impl AsMut<[u8]> for EncodedValue {
+    #[inline]
+    fn as_mut(&mut self) -> &mut [u8] {
+        &mut self.bytes[..self.len as usize]
+    }
+}
+
+// lexicographic like the keys of the FST
+impl Ord for EncodedValue {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.as_ref().cmp(other.as_ref())
+    }
+}
+
+impl PartialOrd for EncodedValue {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+// secure channel key negotiated by start_key_exchange / finish_key_exchange
+pub type SessionKey = [u8; SESSION_KEY_SIZE];
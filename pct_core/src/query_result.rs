@@ -0,0 +1,154 @@
+use std::vec::Vec;
+use sgx_types::*;
+use primitive::QueryId;
+use constant::*;
+use contact_rules::ContactRules;
+use constant_time::*;
+
+/* 
+Type QueryResult 
+    バイトへのシリアライズを担当するよ
+    exposure is counted on the client's trajectory: a matched point stands for
+    TIME_INTERVAL seconds and consecutive matched points form one contiguous exposure.
+    hits in a neighbouring cell are counted apart from hits in the same cell.
+    matched_points has a bit for every point of the trajectory (bit i % 8 of byte i / 8),
+    set if it hit in the same or a neighbouring cell, so the client can tell when it
+    was exposed. Its size only depends on the number of points.
+*/
+#[derive(Clone, Default, Debug)]
+pub struct QueryResult {
+    pub query_id: QueryId,
+    pub risk_level: u8,
+    pub matched_slots: u32,
+    pub neighbour_slots: u32,
+    // seconds
+    pub exposure_duration: u32,
+    // seconds
+    pub longest_exposure: u32,
+    pub matched_points: Vec<u8>,
+}
+
+// bytes of the matched points of a client with query_size points,
+// usize::div_ceil is newer than the toolchain of the Rust SGX SDK
+#[allow(clippy::manual_div_ceil)]
+pub fn matched_points_u8(query_size: usize) -> usize {
+    (query_size + 7) / 8
+}
+
+// plaintext response of one client, see to_be_bytes
+pub fn plain_response_size(query_size: usize) -> usize {
+    PLAIN_RESPONSE_DATA_SIZE_U8 + matched_points_u8(query_size)
+}
+
+impl QueryResult {
+    pub fn new() -> Self {
+        QueryResult {
+            query_id: 1,
+            risk_level: 0,
+            matched_slots: 0,
+            neighbour_slots: 0,
+            exposure_duration: 0,
+            longest_exposure: 0,
+            matched_points: Vec::new(),
+        }
+    }
+
+    pub fn with_size(query_size: usize) -> Self {
+        let mut result = QueryResult::new();
+        result.matched_points = vec![0; matched_points_u8(query_size)];
+        result
+    }
+
+    // matches[i] = (exact, neighbour) hit flags of the i-th point of the client's trajectory,
+    // at most one of them is 1. Branch free, so the time only depends on the number of points.
+    // The result has to be built with_size of the number of points.
+    pub fn score<I: Iterator<Item = (u8, u8)>>(&mut self, matches: I, rules: &ContactRules) {
+        let mut current_run: u32 = 0;
+        let mut longest_run: u32 = 0;
+        for (i, (exact, neighbour)) in matches.enumerate() {
+            let hit = (exact | neighbour) as u32;
+            self.matched_points[i / 8] |= (hit as u8) << (i % 8);
+            self.matched_slots += exact as u32;
+            self.neighbour_slots += neighbour as u32;
+            current_run = (current_run + 1) & 0_u32.wrapping_sub(hit);
+            longest_run = ct_max(longest_run, current_run);
+        }
+        self.finish(longest_run, rules);
+    }
+
+    // same result as score, from the points that hit only: hits = (point, exact, neighbour)
+    // sorted by point, one of the flags set. Its work follows the hits, see
+    // EncodedResultBuffer::build_query_response_from_index
+    pub fn score_hits(&mut self, hits: &[(u32, u8, u8)], rules: &ContactRules) {
+        let mut current_run: u32 = 0;
+        let mut longest_run: u32 = 0;
+        let mut previous: Option<u32> = None;
+        for &(point, exact, neighbour) in hits.iter() {
+            self.matched_points[point as usize / 8] |= 1 << (point % 8);
+            self.matched_slots += exact as u32;
+            self.neighbour_slots += neighbour as u32;
+            current_run = match previous {
+                Some(previous) if previous + 1 == point => current_run + 1,
+                _ => 1,
+            };
+            longest_run = ct_max(longest_run, current_run);
+            previous = Some(point);
+        }
+        self.finish(longest_run, rules);
+    }
+
+    fn finish(&mut self, longest_run: u32, rules: &ContactRules) {
+        let total_slots = self.matched_slots + self.neighbour_slots;
+        self.exposure_duration = total_slots.saturating_mul(TIME_INTERVAL as u32);
+        self.longest_exposure = longest_run.saturating_mul(TIME_INTERVAL as u32);
+        self.risk_level = rules.is_risky(total_slots, self.exposure_duration) as u8;
+    }
+
+    pub fn to_be_bytes(&self) -> Vec<u8> {
+        let mut res = vec![0; PLAIN_RESPONSE_DATA_SIZE_U8 + self.matched_points.len()];
+        res[..QUERY_ID_SIZE_U8].clone_from_slice(&self.query_id.to_be_bytes());
+        let mut cursor = QUERY_ID_SIZE_U8;
+        res[cursor] = RESPONSE_FORMAT_VERSION;
+        res[cursor+1] = self.risk_level;
+        cursor += 2;
+        res[cursor..cursor+4].clone_from_slice(&self.matched_slots.to_be_bytes());
+        res[cursor+4..cursor+8].clone_from_slice(&self.neighbour_slots.to_be_bytes());
+        res[cursor+8..cursor+12].clone_from_slice(&self.exposure_duration.to_be_bytes());
+        res[cursor+12..cursor+16].clone_from_slice(&self.longest_exposure.to_be_bytes());
+        res[cursor+16..].clone_from_slice(&self.matched_points);
+        res
+    }
+
+    // read back by the client of query_id, from to_be_bytes without the query id
+    // the host sees in clear
+    pub fn from_be_bytes(query_id: QueryId, bytes: &[u8], query_size: usize) -> SgxResult<Self> {
+        if bytes.len() != QUERY_RESULT_U8 + matched_points_u8(query_size) {
+            return Err(sgx_status_t::SGX_ERROR_INVALID_PARAMETER);
+        }
+        if bytes[0] != RESPONSE_FORMAT_VERSION {
+            return Err(sgx_status_t::SGX_ERROR_FEATURE_NOT_SUPPORTED);
+        }
+        Ok(QueryResult {
+            query_id,
+            risk_level: bytes[1],
+            matched_slots: u32_from_be(&bytes[2..6]),
+            neighbour_slots: u32_from_be(&bytes[6..10]),
+            exposure_duration: u32_from_be(&bytes[10..14]),
+            longest_exposure: u32_from_be(&bytes[14..18]),
+            matched_points: bytes[QUERY_RESULT_U8..].to_vec(),
+        })
+    }
+
+    // indices of the points that hit, out of query_size
+    pub fn matched_point_indices(&self, query_size: usize) -> Vec<usize> {
+        (0..query_size)
+            .filter(|i| self.matched_points[i / 8] >> (i % 8) & 1 == 1)
+            .collect()
+    }
+}
+
+fn u32_from_be(bytes: &[u8]) -> u32 {
+    let mut array: [u8; 4] = [0; 4];
+    array.copy_from_slice(bytes);
+    u32::from_be_bytes(array)
+}
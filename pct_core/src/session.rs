@@ -0,0 +1,101 @@
+use std::vec::Vec;
+use std::boxed::Box;
+use std::collections::HashMap;
+use std::sync::Arc;
+use sgx_types::*;
+use contact_tracer::{ ContactTracer, ChunkJob };
+use encoded_result_buffer::EncodedResultBuffer;
+use encoded_dictionary_buffer::ChunkCipher;
+
+pub type SessionId = u64;
+
+// every open session holds a decrypted query batch, this bounds the heap they take
+pub const MAX_SESSIONS: usize = 16;
+
+/*
+Type SessionTable
+    Query batches of the enclave, one ContactTracer per session so that batches
+    can be traced side by side:
+        open -> set_contact_rules / upload_queries / trace_chunk / build_response on session(id) -> close
+    An unknown or already closed handle is SGX_ERROR_INVALID_PARAMETER. Handles are
+    never reused, so a stale one cannot reach the batch of another session.
+    The chunk key belongs to the health authority rather than to a batch and is
+    shared by every session. No chunk is traced before it is set, unless the table
+    has been built with_plaintext_chunks for development.
+    To trace chunks of a session on several threads, take a chunk_job, trace the chunk
+    without holding the table and merge the hits back (see ContactTracer::chunk_job).
+*/
+#[derive(Default)]
+pub struct SessionTable {
+    last_id: SessionId,
+    sessions: HashMap<SessionId, ContactTracer>,
+    // every chunk has to be sealed with it
+    chunk_cipher: Option<Arc<dyn ChunkCipher>>,
+    // chunks are traced as plaintext while no cipher is set
+    plaintext_chunks: bool,
+}
+
+impl SessionTable {
+    pub fn new() -> Self {
+        SessionTable::default()
+    }
+
+    // takes plaintext chunks until a chunk cipher is set, the enclave only
+    // builds it with the unsealed_chunks feature
+    pub fn with_plaintext_chunks() -> Self {
+        SessionTable { plaintext_chunks: true, ..SessionTable::default() }
+    }
+
+    pub fn open(&mut self) -> SgxResult<SessionId> {
+        if self.sessions.len() >= MAX_SESSIONS {
+            return Err(sgx_status_t::SGX_ERROR_OUT_OF_MEMORY);
+        }
+        self.last_id += 1;
+        self.sessions.insert(self.last_id, ContactTracer::new());
+        Ok(self.last_id)
+    }
+
+    // the queries and results of the session are zeroized on drop
+    pub fn close(&mut self, id: SessionId) -> SgxResult<()> {
+        match self.sessions.remove(&id) {
+            Some(_) => Ok(()),
+            None => Err(sgx_status_t::SGX_ERROR_INVALID_PARAMETER),
+        }
+    }
+
+    pub fn session(&mut self, id: SessionId) -> SgxResult<&mut ContactTracer> {
+        self.sessions.get_mut(&id).ok_or(sgx_status_t::SGX_ERROR_INVALID_PARAMETER)
+    }
+
+    // query batches are not affected. An installed cipher is only replaced if replace
+    // is set, SGX_ERROR_INVALID_STATE otherwise
+    pub fn set_chunk_cipher(&mut self, cipher: Box<dyn ChunkCipher>, replace: bool) -> SgxResult<()> {
+        if self.chunk_cipher.is_some() && !replace {
+            return Err(sgx_status_t::SGX_ERROR_INVALID_STATE);
+        }
+        self.chunk_cipher = Some(Arc::from(cipher));
+        Ok(())
+    }
+
+    // job of the batch of session id and the chunk cipher its chunks have to be opened with,
+    // SGX_ERROR_INVALID_STATE while no cipher is set unless the table takes plaintext chunks
+    pub fn chunk_job(&self, id: SessionId) -> SgxResult<(ChunkJob, Option<Arc<dyn ChunkCipher>>)> {
+        let tracer = self.sessions.get(&id).ok_or(sgx_status_t::SGX_ERROR_INVALID_PARAMETER)?;
+        if self.chunk_cipher.is_none() && !self.plaintext_chunks {
+            return Err(sgx_status_t::SGX_ERROR_INVALID_STATE);
+        }
+        Ok((tracer.chunk_job()?, self.chunk_cipher.clone()))
+    }
+
+    // hits of a chunk traced by job, SGX_ERROR_INVALID_PARAMETER if the session has been closed meanwhile
+    pub fn merge(&mut self, id: SessionId, job: &ChunkJob, hits: EncodedResultBuffer) -> SgxResult<()> {
+        self.session(id)?.merge(job, hits)
+    }
+
+    // chunk of ContactTracer::trace_chunk, sealed unless the table takes plaintext chunks
+    pub fn trace_chunk(&mut self, id: SessionId, chunk: Vec<u8>) -> SgxResult<()> {
+        let (job, cipher) = self.chunk_job(id)?;
+        let hits = job.trace(chunk, cipher.as_deref())?;
+        self.merge(id, &job, hits)
+    }
+}
@@ -1,9 +1,11 @@
 use std::vec::Vec;
+use std::ptr;
+use std::sync::atomic::{ compiler_fence, Ordering };
 use primitive::*;
 
 
 // なんかめっちゃ長くなってしまったけど
-pub fn _sorted_merge(sorted_list_1: &Vec<UnixEpoch>, sorted_list_2: &Vec<UnixEpoch>) -> Vec<UnixEpoch> {
+pub fn _sorted_merge(sorted_list_1: &[UnixEpoch], sorted_list_2: &[UnixEpoch]) -> Vec<UnixEpoch> {
     let len1 = sorted_list_1.len();
     let len2 = sorted_list_2.len();
     let size = len1 + len2;
@@ -13,7 +15,7 @@ pub fn _sorted_merge(sorted_list_1: &Vec<UnixEpoch>, sorted_list_2: &Vec<UnixEpo
 
     let mut merged_vec = Vec::with_capacity(size);
     for _ in 0..size {
-        let mut candidate = if sorted_list_1[cursor1] < sorted_list_2[cursor2] {
+        let candidate = if sorted_list_1[cursor1] < sorted_list_2[cursor2] {
             cursor1 += 1;
             sorted_list_1[cursor1 - 1]
         } else if sorted_list_1[cursor1] == sorted_list_2[cursor2] {
@@ -31,18 +33,16 @@ pub fn _sorted_merge(sorted_list_1: &Vec<UnixEpoch>, sorted_list_2: &Vec<UnixEpo
         }
         
         if len1 == cursor1 && cursor2 < len2 {
-            for j in cursor2..len2 {
-                candidate = sorted_list_2[j];
-                if tmp_max != candidate { tmp_max = candidate; } else { continue; };
-                merged_vec.push(candidate);
+            for &rest in sorted_list_2[cursor2..].iter() {
+                if tmp_max != rest { tmp_max = rest; } else { continue; };
+                merged_vec.push(rest);
             }
             break;
         }
         if len2 == cursor2 && cursor1 < len1 {
-            for j in cursor1..len1 {
-                candidate = sorted_list_1[j];
-                if tmp_max != candidate { tmp_max = candidate; } else { continue; };
-                merged_vec.push(candidate);
+            for &rest in sorted_list_1[cursor1..].iter() {
+                if tmp_max != rest { tmp_max = rest; } else { continue; };
+                merged_vec.push(rest);
             }
             break;
         }
@@ -67,4 +67,13 @@ pub fn _sorted_push(sorted_list: &mut Vec<UnixEpoch>, unixepoch: UnixEpoch) {
         }
     }
     sorted_list.push(unixepoch);
-}
\ No newline at end of file
+}
+
+// overwrites decrypted data before its memory is released,
+// volatile so the stores are not dropped as dead by the optimizer
+pub fn zeroize<T: Copy + Default>(values: &mut [T]) {
+    for value in values.iter_mut() {
+        unsafe { ptr::write_volatile(value, T::default()); }
+    }
+    compiler_fence(Ordering::SeqCst);
+}
@@ -0,0 +1,241 @@
+use std::vec::Vec;
+use sgx_types::*;
+use constant::*;
+use encoding::Encoding;
+use encoded_dictionary_buffer::{ DICTIONARY_FST, DICTIONARY_HASHTABLE, LookupMode };
+use session::MAX_SESSIONS;
+use chunk_codec::{ CODEC_NONE, CODEC_LZ };
+
+/*
+Wire format
+    Every buffer crossing the enclave boundary in bulk is one frame, all integers big endian:
+        magic     4  "PCTW"
+        version   1  WIRE_VERSION
+        kind      1  FRAME_QUERIES / FRAME_CHUNK / FRAME_RESPONSE
+        encoding  1  Encoding::id of the values in the body
+        count     4  clients (queries, responses) or keys (chunk) in the body
+        body_len  4  bytes after the header
+        checksum  4  CRC-32 (IEEE) of the body
+        body
+    bodies
+        queries   per client: query_size (u32) || nonce || AES-GCM(values) || tag
+        chunk     backend id || codec || dictionary, or nonce || AES-GCM(backend id || codec || dictionary) || tag
+                  once the health authority has provisioned its chunk key, see chunk_codec.rs
+        response  per client: query_id (u64, in clear) || nonce || AES-GCM(QueryResult) || tag,
+                  the result grows with the points of the client, see query_result.rs
+    The checksum catches truncated or corrupted buffers before anything is parsed,
+    authenticity comes from AES-GCM. A malformed frame is reported with
+        SGX_ERROR_INVALID_PARAMETER      too short, wrong magic or kind
+        SGX_ERROR_INVALID_VERSION        version this build does not read
+        SGX_ERROR_FEATURE_NOT_SUPPORTED  unknown encoding
+        SGX_ERROR_INVALID_METADATA       body_len, count or checksum does not match the body
+    so that SGX_ERROR_MAC_MISMATCH only ever means a record failed AES-GCM, i.e. has
+    been tampered with, and a frame corrupted on the way is not taken for that.
+*/
+pub const WIRE_MAGIC: &[u8] = b"PCTW";
+// 2: codec byte after the backend id of a chunk
+// 3: max_threads in the capabilities
+pub const WIRE_VERSION: u8 = 3;
+pub const FRAME_HEADER_U8: usize = 4 + 1 + 1 + 1 + 4 + 4 + 4;
+
+pub const FRAME_QUERIES: u8 = 1;
+pub const FRAME_CHUNK: u8 = 2;
+pub const FRAME_RESPONSE: u8 = 3;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct FrameHeader {
+    pub kind: u8,
+    pub encoding: Encoding,
+    pub count: u32,
+    pub body_len: u32,
+    pub checksum: u32,
+}
+
+impl FrameHeader {
+    pub fn new(kind: u8, encoding: Encoding, count: u32, body: &[u8]) -> Self {
+        FrameHeader {
+            kind,
+            encoding,
+            count,
+            body_len: body.len() as u32,
+            checksum: crc32(body),
+        }
+    }
+
+    pub fn to_be_bytes(&self) -> [u8; FRAME_HEADER_U8] {
+        let mut res = [0; FRAME_HEADER_U8];
+        res[..4].copy_from_slice(WIRE_MAGIC);
+        res[4] = WIRE_VERSION;
+        res[5] = self.kind;
+        res[6] = self.encoding.id();
+        res[7..11].copy_from_slice(&self.count.to_be_bytes());
+        res[11..15].copy_from_slice(&self.body_len.to_be_bytes());
+        res[15..19].copy_from_slice(&self.checksum.to_be_bytes());
+        res
+    }
+}
+
+// header || body
+pub fn write_frame(kind: u8, encoding: Encoding, count: u32, body: &[u8]) -> Vec<u8> {
+    let mut frame: Vec<u8> = Vec::with_capacity(FRAME_HEADER_U8 + body.len());
+    frame.extend_from_slice(&FrameHeader::new(kind, encoding, count, body).to_be_bytes());
+    frame.extend_from_slice(body);
+    frame
+}
+
+// header and body of a frame of the given kind, count is checked by the reader of the body
+pub fn read_frame(frame: &[u8], kind: u8) -> SgxResult<(FrameHeader, &[u8])> {
+    if frame.len() < FRAME_HEADER_U8 || &frame[..4] != WIRE_MAGIC {
+        return Err(sgx_status_t::SGX_ERROR_INVALID_PARAMETER);
+    }
+    if frame[4] != WIRE_VERSION {
+        return Err(sgx_status_t::SGX_ERROR_INVALID_VERSION);
+    }
+    if frame[5] != kind {
+        return Err(sgx_status_t::SGX_ERROR_INVALID_PARAMETER);
+    }
+    let encoding = match Encoding::from_id(frame[6]) {
+        Some(encoding) => encoding,
+        None => { return Err(sgx_status_t::SGX_ERROR_FEATURE_NOT_SUPPORTED); }
+    };
+    let header = FrameHeader {
+        kind,
+        encoding,
+        count: u32_from_be(&frame[7..11]),
+        body_len: u32_from_be(&frame[11..15]),
+        checksum: u32_from_be(&frame[15..19]),
+    };
+    let body = &frame[FRAME_HEADER_U8..];
+    if header.body_len as usize != body.len() {
+        return Err(sgx_status_t::SGX_ERROR_INVALID_METADATA);
+    }
+    if header.checksum != crc32(body) {
+        return Err(sgx_status_t::SGX_ERROR_INVALID_METADATA);
+    }
+    Ok((header, body))
+}
+
+// CRC-32 as in zlib
+pub fn crc32(bytes: &[u8]) -> u32 {
+    let mut crc = !0_u32;
+    for byte in bytes.iter() {
+        crc = CRC32_TABLE[((crc ^ *byte as u32) & 0xff) as usize] ^ (crc >> 8);
+    }
+    !crc
+}
+
+// built once at compile time, read only like the rest of the enclave image
+const CRC32_TABLE: [u32; 256] = crc32_table();
+
+const fn crc32_table() -> [u32; 256] {
+    let mut table = [0_u32; 256];
+    let mut i = 0;
+    while i < 256 {
+        let mut c = i as u32;
+        let mut bit = 0;
+        while bit < 8 {
+            c = if c & 1 == 1 { 0xedb8_8320 ^ (c >> 1) } else { c >> 1 };
+            bit += 1;
+        }
+        table[i] = c;
+        i += 1;
+    }
+    table
+}
+
+fn u32_from_be(bytes: &[u8]) -> u32 {
+    let mut array = [0_u8; 4];
+    array.copy_from_slice(bytes);
+    u32::from_be_bytes(array)
+}
+
+fn u64_from_be(bytes: &[u8]) -> u64 {
+    let mut array = [0_u8; 8];
+    array.copy_from_slice(bytes);
+    u64::from_be_bytes(array)
+}
+
+/*
+Type Capabilities
+    What an enclave build reads, returned by the get_capabilities ECALL so the host
+    can refuse to run against an enclave that does not match it. Sets are bitmasks
+    over the ids (bit id), integers are big endian:
+        wire_version (1) || response_format_version (1) || encodings (1)
+        || dictionaries (1) || lookup_modes (1) || max_sessions (4) || max_query_size (4)
+        || heap_size (8) || codecs (1) || max_threads (4)
+*/
+pub const CAPABILITIES_U8: usize = 1 + 1 + 1 + 1 + 1 + 4 + 4 + 8 + 1 + 4;
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Capabilities {
+    pub wire_version: u8,
+    pub response_format_version: u8,
+    pub encodings: u8,
+    pub dictionaries: u8,
+    pub lookup_modes: u8,
+    pub max_sessions: u32,
+    pub max_query_size: u32,
+    // HeapMaxSize of the enclave in bytes, 0 if unknown (native backend)
+    pub heap_size: u64,
+    pub codecs: u8,
+    // TCSNum of the enclave, how many chunks it can trace at once, 0 if unbounded (native backend)
+    pub max_threads: u32,
+}
+
+impl Capabilities {
+    // of this build, the heap size and threads are filled in by the enclave
+    pub fn current() -> Self {
+        let encodings = [Encoding::Th48, Encoding::Th54, Encoding::Th60, Encoding::Th72, Encoding::Gp10];
+        Capabilities {
+            wire_version: WIRE_VERSION,
+            response_format_version: RESPONSE_FORMAT_VERSION,
+            encodings: encodings.iter().fold(0, |mask, encoding| mask | 1 << encoding.id()),
+            dictionaries: 1 << DICTIONARY_FST | 1 << DICTIONARY_HASHTABLE,
+            // LookupMode::Indexed is not oblivious, the enclave refuses it and only the
+            // native backend adds it to its capabilities
+            lookup_modes: 1 << LookupMode::Direct.id() | 1 << LookupMode::Oblivious.id(),
+            max_sessions: MAX_SESSIONS as u32,
+            max_query_size: MAX_QUERY_SIZE as u32,
+            heap_size: 0,
+            codecs: 1 << CODEC_NONE | 1 << CODEC_LZ,
+            max_threads: 0,
+        }
+    }
+
+    pub fn supports(mask: u8, id: u8) -> bool {
+        id < 8 && mask & (1 << id) != 0
+    }
+
+    pub fn to_be_bytes(&self) -> [u8; CAPABILITIES_U8] {
+        let mut res = [0; CAPABILITIES_U8];
+        res[0] = self.wire_version;
+        res[1] = self.response_format_version;
+        res[2] = self.encodings;
+        res[3] = self.dictionaries;
+        res[4] = self.lookup_modes;
+        res[5..9].copy_from_slice(&self.max_sessions.to_be_bytes());
+        res[9..13].copy_from_slice(&self.max_query_size.to_be_bytes());
+        res[13..21].copy_from_slice(&self.heap_size.to_be_bytes());
+        res[21] = self.codecs;
+        res[22..26].copy_from_slice(&self.max_threads.to_be_bytes());
+        res
+    }
+
+    pub fn from_be_bytes(bytes: &[u8]) -> SgxResult<Self> {
+        if bytes.len() != CAPABILITIES_U8 {
+            return Err(sgx_status_t::SGX_ERROR_INVALID_PARAMETER);
+        }
+        Ok(Capabilities {
+            wire_version: bytes[0],
+            response_format_version: bytes[1],
+            encodings: bytes[2],
+            dictionaries: bytes[3],
+            lookup_modes: bytes[4],
+            max_sessions: u32_from_be(&bytes[5..9]),
+            max_query_size: u32_from_be(&bytes[9..13]),
+            heap_size: u64_from_be(&bytes[13..21]),
+            codecs: bytes[21],
+            max_threads: u32_from_be(&bytes[22..26]),
+        })
+    }
+}
@@ -0,0 +1,715 @@
+/*
+    End-to-end runs of the tracing logic over data/sample without SGX,
+    checked against a brute force intersection of the same files.
+    $ cd pct_core && cargo test
+*/
+extern crate pct_core;
+extern crate sgx_types;
+extern crate fst;
+extern crate bincode;
+extern crate serde_json;
+
+use std::fs::File;
+use std::io::BufReader;
+use std::collections::{ HashSet, HashMap };
+use std::thread;
+use std::sync::mpsc;
+use sgx_types::*;
+use serde_json::Value;
+use pct_core::constant::*;
+use pct_core::encoding::Encoding;
+use pct_core::contact_tracer::ContactTracer;
+use pct_core::session::{ SessionTable, MAX_SESSIONS };
+use pct_core::primitive::EncodedValue;
+use pct_core::encoded_dictionary_buffer::{ DICTIONARY_FST, DICTIONARY_HASHTABLE, ChunkCipher, LookupMode };
+use pct_core::contact_rules::ContactRules;
+use pct_core::query_result::{ QueryResult, plain_response_size };
+use pct_core::encoded_query_buffer::EncodedQueryBuffer;
+use pct_core::mapped_encoded_query_buffer::MappedEncodedQueryBuffer;
+use pct_core::encoded_result_buffer::EncodedResultBuffer;
+use pct_core::oblivious;
+use pct_core::constant_time::AccessTrace;
+use pct_core::wire::{ self, write_frame, read_frame, Capabilities, CAPABILITIES_U8, FRAME_CHUNK, FRAME_QUERIES, FRAME_HEADER_U8 };
+use pct_core::chunk_codec::{ self, CODEC_NONE, CODEC_LZ };
+
+const CHUNK_SIZE: usize = 1000;
+
+struct Sample {
+    query_id_list: Vec<u64>,
+    queries: Vec<Vec<Vec<u8>>>,
+    central: Vec<Vec<u8>>,
+}
+
+fn read_json(name: &str) -> Value {
+    let path = format!("{}/../data/sample/{}", env!("CARGO_MANIFEST_DIR"), name);
+    serde_json::from_reader(BufReader::new(File::open(path).unwrap())).unwrap()
+}
+
+// data/sample is gp10, values are the ascii bytes of the strings
+fn read_sample() -> Sample {
+    let client = read_json("client.json");
+    let central = read_json("central.json");
+    let mut sample = Sample { query_id_list: vec![], queries: vec![], central: vec![] };
+    for detail in client["data"].as_array().unwrap() {
+        sample.query_id_list.push(detail["query_id"].as_u64().unwrap());
+        sample.queries.push(
+            detail["geodata"].as_array().unwrap().iter()
+                .map(|v| v.as_str().unwrap().as_bytes().to_vec()).collect()
+        );
+    }
+    let set: HashSet<Vec<u8>> = central["data"].as_array().unwrap().iter()
+        .map(|v| v.as_str().unwrap().as_bytes().to_vec()).collect();
+    sample.central = set.into_iter().collect();
+    sample.central.sort();
+    sample
+}
+
+// the oblivious mode is slow in debug builds, so its tests only take a few clients
+fn first_clients(sample: &Sample, n: usize) -> Sample {
+    Sample {
+        query_id_list: sample.query_id_list[..n].to_vec(),
+        queries: sample.queries[..n].to_vec(),
+        central: sample.central.clone(),
+    }
+}
+
+// backend id || codec || dictionary
+fn fst_body(values: &[Vec<u8>]) -> Vec<u8> {
+    let mut chunk = vec![DICTIONARY_FST, CODEC_NONE];
+    chunk.extend(fst::Set::from_iter(values.iter()).unwrap().as_ref().as_bytes());
+    chunk
+}
+
+fn chunk_frame(body: &[u8], keys: usize) -> Vec<u8> {
+    write_frame(FRAME_CHUNK, Encoding::Gp10, keys as u32, body)
+}
+
+fn fst_chunk(values: &[Vec<u8>]) -> Vec<u8> {
+    chunk_frame(&fst_body(values), values.len())
+}
+
+fn hashtable_chunk(values: &[Vec<u8>]) -> Vec<u8> {
+    let set: HashSet<Vec<u8>> = values.iter().cloned().collect();
+    let mut chunk = vec![DICTIONARY_HASHTABLE, CODEC_NONE];
+    chunk.extend(bincode::serialize(&set).unwrap());
+    chunk_frame(&chunk, set.len())
+}
+
+// the body of chunk with its dictionary compressed by the LZ codec
+fn lz_chunk(chunk: Vec<u8>) -> Vec<u8> {
+    let (header, body) = read_frame(&chunk, FRAME_CHUNK).unwrap();
+    let mut compressed = vec![body[0], CODEC_LZ];
+    compressed.extend(chunk_codec::compress(CODEC_LZ, &body[2..]).unwrap());
+    chunk_frame(&compressed, header.count as usize)
+}
+
+// min_exposure_duration, before, after, spatial_tolerance, min_hits
+fn rules_u8(before: u32, after: u32, spatial_tolerance: u8) -> Vec<u8> {
+    let mut rules: Vec<u8> = vec![];
+    rules.extend_from_slice(&0_u32.to_be_bytes());
+    rules.extend_from_slice(&before.to_be_bytes());
+    rules.extend_from_slice(&after.to_be_bytes());
+    rules.push(spatial_tolerance);
+    rules.extend_from_slice(&1_u32.to_be_bytes());
+    rules
+}
+
+// plaintext response of every client, their size depends on their number of points
+fn records<'a>(response: &'a [u8], queries: &[Vec<Vec<u8>>]) -> Vec<&'a [u8]> {
+    let mut cursor = 0;
+    let records = queries.iter().map(|query| {
+        let record = &response[cursor..cursor + plain_response_size(query.len())];
+        cursor += record.len();
+        record
+    }).collect();
+    assert_eq!(cursor, response.len());
+    records
+}
+
+// chunk of the given index built from the central values in it
+type BuildChunk = dyn Fn(usize, &[Vec<u8>]) -> Vec<u8>;
+
+// (query_id, risk_level, matched_slots, matched points) of every client
+fn run(sample: &Sample, rules: &[u8], build_chunk: &BuildChunk) -> Vec<(u64, u8, u32, Vec<usize>)> {
+    run_with_mode(sample, rules, LookupMode::Direct, build_chunk)
+}
+
+fn run_with_mode(
+    sample: &Sample,
+    rules: &[u8],
+    mode: LookupMode,
+    build_chunk: &BuildChunk,
+) -> Vec<(u64, u8, u32, Vec<usize>)> {
+    let mut tracer = ContactTracer::new();
+    tracer.set_contact_rules(rules).unwrap();
+    tracer.set_lookup_mode(mode);
+    let queries: Vec<Vec<u8>> = sample.queries.iter().map(|query| query.concat()).collect();
+    tracer.upload_queries(Encoding::Gp10, queries, sample.query_id_list.clone()).unwrap();
+    for (i, values) in sample.central.chunks(CHUNK_SIZE).enumerate() {
+        tracer.trace_chunk(build_chunk(i, values)).unwrap();
+    }
+
+    let response = tracer.build_response().unwrap();
+    records(&response, &sample.queries).iter().zip(sample.queries.iter()).map(|(record, query)| {
+        let mut query_id = [0_u8; 8];
+        query_id.copy_from_slice(&record[..8]);
+        let mut matched_slots = [0_u8; 4];
+        matched_slots.copy_from_slice(&record[10..14]);
+        assert_eq!(record[8], RESPONSE_FORMAT_VERSION);
+        let bitmap = &record[PLAIN_RESPONSE_DATA_SIZE_U8..];
+        let matched_points = (0..query.len()).filter(|i| bitmap[i / 8] >> (i % 8) & 1 == 1).collect();
+        (u64::from_be_bytes(query_id), record[9], u32::from_be_bytes(matched_slots), matched_points)
+    }).collect()
+}
+
+fn time_slot(value: &[u8]) -> u32 {
+    std::str::from_utf8(&value[10..]).unwrap().parse().unwrap()
+}
+
+// brute force: a point matches if the same cell has a central point within [t - before, t + after]
+fn expected(sample: &Sample, before: u32, after: u32) -> Vec<(u64, u8, u32, Vec<usize>)> {
+    let mut slots_of_cell: HashMap<&[u8], Vec<u32>> = HashMap::new();
+    for central in sample.central.iter() {
+        slots_of_cell.entry(&central[..10]).or_default().push(time_slot(central));
+    }
+    sample.query_id_list.iter().zip(sample.queries.iter()).map(|(query_id, query)| {
+        let matched_points: Vec<usize> = query.iter().enumerate().filter(|(_, value)| {
+            let slot = time_slot(value);
+            match slots_of_cell.get(&value[..10]) {
+                Some(slots) => slots.iter().any(|s| *s + before >= slot && *s <= slot + after),
+                None => false,
+            }
+        }).map(|(i, _)| i).collect();
+        let matched = matched_points.len() as u32;
+        (*query_id, if matched > 0 { 1 } else { 0 }, matched, matched_points)
+    }).collect()
+}
+
+#[test]
+fn exact_match_with_fst_chunks() {
+    let sample = read_sample();
+    let result = run(&sample, &rules_u8(0, 0, 0), &|_, values| fst_chunk(values));
+    assert_eq!(result, expected(&sample, 0, 0));
+    assert!(result.iter().any(|r| r.1 == 1));
+}
+
+#[test]
+fn backends_agree_on_mixed_chunks() {
+    let sample = read_sample();
+    let fst_result = run(&sample, &rules_u8(0, 0, 0), &|_, values| fst_chunk(values));
+    let hashtable_result = run(&sample, &rules_u8(0, 0, 0), &|_, values| hashtable_chunk(values));
+    let mixed_result = run(&sample, &rules_u8(0, 0, 0), &|i, values| {
+        if i % 2 == 0 { fst_chunk(values) } else { hashtable_chunk(values) }
+    });
+    assert_eq!(fst_result, hashtable_result);
+    assert_eq!(fst_result, mixed_result);
+}
+
+#[test]
+fn time_window_match() {
+    let sample = read_sample();
+    let fst_result = run(&sample, &rules_u8(2, 1, 0), &|_, values| fst_chunk(values));
+    let hashtable_result = run(&sample, &rules_u8(2, 1, 0), &|_, values| hashtable_chunk(values));
+    assert_eq!(fst_result, expected(&sample, 2, 1));
+    assert_eq!(fst_result, hashtable_result);
+}
+
+#[test]
+fn tolerance_needs_gp10() {
+    let mut tracer = ContactTracer::new();
+    tracer.set_contact_rules(&rules_u8(1, 0, 0)).unwrap();
+    let ret = tracer.upload_queries(Encoding::Th48, vec![vec![0; 6]], vec![0]);
+    assert_eq!(ret, Err(sgx_status_t::SGX_ERROR_FEATURE_NOT_SUPPORTED));
+}
+
+#[test]
+fn malformed_input_is_rejected() {
+    let mut tracer = ContactTracer::new();
+    // not a multiple of the gp10 value size
+    let ret = tracer.upload_queries(Encoding::Gp10, vec![vec![0; 13]], vec![0]);
+    assert_eq!(ret, Err(sgx_status_t::SGX_ERROR_INVALID_PARAMETER));
+    tracer.upload_queries(Encoding::Gp10, vec![vec![b'0'; 14]], vec![0]).unwrap();
+    // unknown dictionary backend
+    assert_eq!(tracer.trace_chunk(chunk_frame(&[0xff, 0, 0], 0)), Err(sgx_status_t::SGX_ERROR_FEATURE_NOT_SUPPORTED));
+    assert_eq!(tracer.trace_chunk(vec![]), Err(sgx_status_t::SGX_ERROR_INVALID_PARAMETER));
+    // not a dictionary at all
+    assert_eq!(tracer.trace_chunk(chunk_frame(&[DICTIONARY_FST, CODEC_NONE, 1, 2, 3], 0)), Err(sgx_status_t::SGX_ERROR_INVALID_PARAMETER));
+    assert_eq!(tracer.trace_chunk(chunk_frame(&[DICTIONARY_HASHTABLE, CODEC_NONE, 0xff], 0)), Err(sgx_status_t::SGX_ERROR_INVALID_PARAMETER));
+    // unknown codec
+    assert_eq!(tracer.trace_chunk(chunk_frame(&[DICTIONARY_FST, 0xff, 0], 0)), Err(sgx_status_t::SGX_ERROR_FEATURE_NOT_SUPPORTED));
+}
+
+#[test]
+fn compressed_chunks_agree_with_plain_ones() {
+    let sample = read_sample();
+    let plain = run(&sample, &rules_u8(0, 0, 0), &|i, values| {
+        if i % 2 == 0 { fst_chunk(values) } else { hashtable_chunk(values) }
+    });
+    let compressed = run(&sample, &rules_u8(0, 0, 0), &|i, values| {
+        lz_chunk(if i % 2 == 0 { fst_chunk(values) } else { hashtable_chunk(values) })
+    });
+    assert_eq!(compressed, plain);
+    let chunk = hashtable_chunk(&sample.central[..CHUNK_SIZE]);
+    assert!(lz_chunk(chunk.clone()).len() < chunk.len());
+}
+
+#[test]
+fn lz_codec_round_trips_and_rejects_malformed_payloads() {
+    let inputs: Vec<Vec<u8>> = vec![
+        vec![],
+        b"abc".to_vec(),
+        vec![7; 10000],
+        (0..70000_u32).map(|i| (i % 251) as u8).collect(),
+        (0..5000_u32).map(|i| (i.wrapping_mul(2654435761) >> 24) as u8).collect(),
+    ];
+    for input in inputs.iter() {
+        let payload = chunk_codec::compress(CODEC_LZ, input).unwrap();
+        assert_eq!(&chunk_codec::decompress(CODEC_LZ, payload).unwrap(), input);
+    }
+    let invalid = |payload: Vec<u8>| {
+        assert_eq!(chunk_codec::decompress(CODEC_LZ, payload), Err(sgx_status_t::SGX_ERROR_INVALID_PARAMETER));
+    };
+    let payload = chunk_codec::compress(CODEC_LZ, &vec![7; 10000]).unwrap();
+    // truncated, wrong original length, forged original length
+    invalid(payload[..payload.len() - 1].to_vec());
+    invalid(payload[..3].to_vec());
+    let mut longer = payload.clone();
+    longer[3] ^= 1;
+    invalid(longer);
+    let mut forged = payload.clone();
+    forged[..4].copy_from_slice(&u32::MAX.to_be_bytes());
+    invalid(forged);
+    // a match before the start of the output
+    invalid(vec![0, 0, 0, 8, 0x10, b'a', 0, 2]);
+    invalid(vec![0, 0, 0, 8, 0x10, b'a', 0, 0]);
+    assert_eq!(chunk_codec::decompress(0xff, vec![]), Err(sgx_status_t::SGX_ERROR_FEATURE_NOT_SUPPORTED));
+}
+
+#[test]
+fn malformed_frames_are_rejected() {
+    let sample = read_sample();
+    let mut tracer = ContactTracer::new();
+    tracer.upload_queries(Encoding::Gp10, vec![sample.queries[0].concat()], vec![0]).unwrap();
+    let values = &sample.central[..CHUNK_SIZE];
+    let frame = fst_chunk(values);
+    let (header, body) = read_frame(&frame, FRAME_CHUNK).unwrap();
+    assert_eq!((header.encoding, header.count as usize, body), (Encoding::Gp10, CHUNK_SIZE, &frame[FRAME_HEADER_U8..]));
+
+    let modified = |i: usize, byte: u8| -> Vec<u8> {
+        let mut frame = frame.clone();
+        frame[i] = byte;
+        frame
+    };
+    let invalid = |ret: SgxResult<()>, status: sgx_status_t| assert_eq!(ret, Err(status));
+    // magic, version (frames of the previous version too), kind, encoding
+    assert_eq!(wire::WIRE_VERSION, 3);
+    invalid(tracer.trace_chunk(modified(0, b'X')), sgx_status_t::SGX_ERROR_INVALID_PARAMETER);
+    invalid(tracer.trace_chunk(modified(4, wire::WIRE_VERSION + 1)), sgx_status_t::SGX_ERROR_INVALID_VERSION);
+    invalid(tracer.trace_chunk(modified(4, wire::WIRE_VERSION - 1)), sgx_status_t::SGX_ERROR_INVALID_VERSION);
+    invalid(tracer.trace_chunk(modified(5, FRAME_QUERIES)), sgx_status_t::SGX_ERROR_INVALID_PARAMETER);
+    invalid(tracer.trace_chunk(modified(6, 0)), sgx_status_t::SGX_ERROR_FEATURE_NOT_SUPPORTED);
+    // keys of another encoding than the queries
+    invalid(tracer.trace_chunk(write_frame(FRAME_CHUNK, Encoding::Th48, CHUNK_SIZE as u32, &fst_body(values))), sgx_status_t::SGX_ERROR_INVALID_PARAMETER);
+    // count, truncated body, flipped body byte
+    invalid(tracer.trace_chunk(chunk_frame(&fst_body(values), CHUNK_SIZE + 1)), sgx_status_t::SGX_ERROR_INVALID_METADATA);
+    invalid(tracer.trace_chunk(frame[..frame.len() - 1].to_vec()), sgx_status_t::SGX_ERROR_INVALID_METADATA);
+    let last = frame.len() - 1;
+    invalid(tracer.trace_chunk(modified(last, frame[last] ^ 1)), sgx_status_t::SGX_ERROR_INVALID_METADATA);
+    assert_eq!(wire::crc32(b"123456789"), 0xcbf4_3926);
+
+    tracer.trace_chunk(frame).unwrap();
+    let mut capabilities = Capabilities::current();
+    capabilities.heap_size = 0x9000_0000;
+    capabilities.max_threads = 8;
+    assert_eq!(Capabilities::from_be_bytes(&capabilities.to_be_bytes()), Ok(capabilities));
+    assert_eq!(capabilities.wire_version, 3);
+    // the capabilities of version 2 ended before max_threads
+    assert_eq!(CAPABILITIES_U8, 26);
+    assert_eq!(Capabilities::from_be_bytes(&capabilities.to_be_bytes()[..22]), Err(sgx_status_t::SGX_ERROR_INVALID_PARAMETER));
+    assert!(Capabilities::supports(capabilities.encodings, Encoding::Gp10.id()));
+    assert!(!Capabilities::supports(capabilities.encodings, 0));
+}
+
+#[test]
+fn responses_and_rules_round_trip() {
+    let rules = ContactRules { min_exposure_duration: 1200, time_tolerance_before: 1, time_tolerance_after: 2, spatial_tolerance: 1, min_hits: 3 };
+    assert_eq!(rules.to_be_bytes(), {
+        let mut bytes = rules_u8(1, 2, 1);
+        bytes[..4].copy_from_slice(&1200_u32.to_be_bytes());
+        bytes[13..].copy_from_slice(&3_u32.to_be_bytes());
+        bytes
+    });
+    assert_eq!(ContactRules::from_be_bytes(&rules.to_be_bytes()), Ok(rules.clone()));
+
+    let mut result = QueryResult::with_size(10);
+    result.query_id = 42;
+    result.score([(1, 0), (1, 0), (0, 0), (0, 1)].iter().cloned().chain((0..6).map(|_| (0, 0))), &rules);
+    let bytes = result.to_be_bytes();
+    assert_eq!(bytes.len(), plain_response_size(10));
+    let read = QueryResult::from_be_bytes(42, &bytes[QUERY_ID_SIZE_U8..], 10).unwrap();
+    assert_eq!(read.to_be_bytes(), bytes);
+    assert_eq!(read.matched_point_indices(10), vec![0, 1, 3]);
+    assert_eq!(QueryResult::from_be_bytes(42, &bytes[QUERY_ID_SIZE_U8..], 20).err(), Some(sgx_status_t::SGX_ERROR_INVALID_PARAMETER));
+    let mut other_version = bytes[QUERY_ID_SIZE_U8..].to_vec();
+    other_version[0] = RESPONSE_FORMAT_VERSION + 1;
+    assert_eq!(QueryResult::from_be_bytes(42, &other_version, 10).err(), Some(sgx_status_t::SGX_ERROR_FEATURE_NOT_SUPPORTED));
+}
+
+// stands in for the AES-GCM chunk key of the enclave: "sealed" = MAGIC || chunk
+struct PrefixCipher;
+const MAGIC: &[u8] = b"sealed";
+
+impl ChunkCipher for PrefixCipher {
+    fn open(&self, sealed_chunk: &[u8]) -> SgxResult<Vec<u8>> {
+        if !sealed_chunk.starts_with(MAGIC) {
+            return Err(sgx_status_t::SGX_ERROR_MAC_MISMATCH);
+        }
+        Ok(sealed_chunk[MAGIC.len()..].to_vec())
+    }
+}
+
+#[test]
+fn sealed_chunks_need_the_cipher() {
+    let sample = read_sample();
+    let mut table = SessionTable::new();
+    let id = table.open().unwrap();
+    table.session(id).unwrap().set_contact_rules(&rules_u8(0, 0, 0)).unwrap();
+    let queries: Vec<Vec<u8>> = sample.queries.iter().map(|query| query.concat()).collect();
+    table.session(id).unwrap().upload_queries(Encoding::Gp10, queries, sample.query_id_list.clone()).unwrap();
+    // nothing is traced before the chunk key has been provisioned
+    let chunk = fst_body(&sample.central);
+    assert_eq!(table.trace_chunk(id, chunk_frame(&chunk, sample.central.len())), Err(sgx_status_t::SGX_ERROR_INVALID_STATE));
+    table.set_chunk_cipher(Box::new(PrefixCipher), false).unwrap();
+    // an installed cipher is only replaced when the authority allows it
+    assert_eq!(table.set_chunk_cipher(Box::new(PrefixCipher), false), Err(sgx_status_t::SGX_ERROR_INVALID_STATE));
+    table.set_chunk_cipher(Box::new(PrefixCipher), true).unwrap();
+
+    // plaintext chunks are not accepted once a cipher is set either
+    assert_eq!(table.trace_chunk(id, chunk_frame(&chunk, sample.central.len())), Err(sgx_status_t::SGX_ERROR_MAC_MISMATCH));
+
+    let mut sealed_chunk = MAGIC.to_vec();
+    sealed_chunk.extend_from_slice(&chunk);
+    table.trace_chunk(id, chunk_frame(&sealed_chunk, sample.central.len())).unwrap();
+    let response = table.session(id).unwrap().build_response().unwrap();
+    let positives: u32 = records(&response, &sample.queries).iter()
+        .map(|record| record[9] as u32)
+        .sum();
+    let expected_positives: u32 = expected(&sample, 0, 0).iter().map(|r| r.1 as u32).sum();
+    assert_eq!(positives, expected_positives);
+}
+
+#[test]
+fn sessions_keep_their_own_batch() {
+    let sample = read_sample();
+    let mut table = SessionTable::with_plaintext_chunks();
+    let first = table.open().unwrap();
+    let second = table.open().unwrap();
+    assert!(first != second);
+
+    // out of order: nothing has been uploaded to the session yet
+    let chunk = fst_chunk(&sample.central);
+    assert_eq!(table.trace_chunk(first, chunk.clone()), Err(sgx_status_t::SGX_ERROR_INVALID_STATE));
+    assert_eq!(table.session(first).unwrap().build_response(), Err(sgx_status_t::SGX_ERROR_INVALID_STATE));
+
+    let queries: Vec<Vec<u8>> = sample.queries.iter().map(|query| query.concat()).collect();
+    table.session(first).unwrap().upload_queries(Encoding::Gp10, queries[..1].to_vec(), sample.query_id_list[..1].to_vec()).unwrap();
+    table.session(second).unwrap().upload_queries(Encoding::Gp10, queries.clone(), sample.query_id_list.clone()).unwrap();
+    table.trace_chunk(first, chunk.clone()).unwrap();
+    table.trace_chunk(second, chunk).unwrap();
+    let first_response = table.session(first).unwrap().build_response().unwrap();
+    let second_response = table.session(second).unwrap().build_response().unwrap();
+    assert_eq!(records(&first_response, &sample.queries[..1])[0], records(&second_response, &sample.queries)[0]);
+
+    // closed and never opened handles are unknown, handles are not reused
+    table.close(first).unwrap();
+    assert_eq!(table.close(first), Err(sgx_status_t::SGX_ERROR_INVALID_PARAMETER));
+    assert!(table.session(first).is_err());
+    assert_eq!(table.trace_chunk(first, vec![DICTIONARY_FST]), Err(sgx_status_t::SGX_ERROR_INVALID_PARAMETER));
+    assert!(table.session(second + 1).is_err());
+    assert!(table.open().unwrap() > second);
+    table.session(second).unwrap().build_response().unwrap();
+
+}
+
+#[test]
+fn chunks_traced_on_threads_merge_into_the_same_result() {
+    let sample = read_sample();
+    let queries: Vec<Vec<u8>> = sample.queries.iter().map(|query| query.concat()).collect();
+
+    let mut tracer = ContactTracer::new();
+    tracer.set_contact_rules(&rules_u8(2, 1, 0)).unwrap();
+    tracer.upload_queries(Encoding::Gp10, queries.clone(), sample.query_id_list.clone()).unwrap();
+    let job = tracer.chunk_job().unwrap();
+    let (sender, receiver) = mpsc::channel();
+    let workers: Vec<thread::JoinHandle<()>> = (0..4).map(|worker| {
+        let (job, sender) = (job.clone(), sender.clone());
+        let chunks: Vec<Vec<u8>> = sample.central.chunks(CHUNK_SIZE).skip(worker).step_by(4)
+            .map(|values| if worker % 2 == 0 { fst_chunk(values) } else { hashtable_chunk(values) })
+            .collect();
+        thread::spawn(move || {
+            for chunk in chunks {
+                sender.send(job.trace(chunk, None).unwrap()).unwrap();
+            }
+        })
+    }).collect();
+    drop(sender);
+    for hits in receiver.iter() {
+        tracer.merge(&job, hits).unwrap();
+    }
+    for worker in workers {
+        worker.join().unwrap();
+    }
+    let response = tracer.build_response().unwrap();
+    let mut sequential_tracer = ContactTracer::new();
+    sequential_tracer.set_contact_rules(&rules_u8(2, 1, 0)).unwrap();
+    sequential_tracer.upload_queries(Encoding::Gp10, queries.clone(), sample.query_id_list.clone()).unwrap();
+    for values in sample.central.chunks(CHUNK_SIZE) {
+        sequential_tracer.trace_chunk(fst_chunk(values)).unwrap();
+    }
+    assert_eq!(response, sequential_tracer.build_response().unwrap());
+
+    // the hits of a replaced batch are not merged into the new one
+    let hits = job.trace(fst_chunk(&sample.central[..CHUNK_SIZE]), None).unwrap();
+    tracer.upload_queries(Encoding::Gp10, queries, sample.query_id_list.clone()).unwrap();
+    assert_eq!(tracer.merge(&job, hits), Err(sgx_status_t::SGX_ERROR_INVALID_STATE));
+}
+
+#[test]
+fn open_sessions_are_bounded() {
+    let mut table = SessionTable::new();
+    let ids: Vec<u64> = (0..MAX_SESSIONS).map(|_| table.open().unwrap()).collect();
+    assert_eq!(table.open(), Err(sgx_status_t::SGX_ERROR_OUT_OF_MEMORY));
+    table.close(ids[0]).unwrap();
+    assert!(table.open().is_ok());
+}
+
+#[test]
+fn oblivious_lookup_agrees_with_direct() {
+    let sample = first_clients(&read_sample(), 2);
+    for rules in [rules_u8(0, 0, 0), rules_u8(2, 1, 0)].iter() {
+        let direct = run(&sample, rules, &|_, values| fst_chunk(values));
+        let oblivious_fst = run_with_mode(&sample, rules, LookupMode::Oblivious, &|_, values| fst_chunk(values));
+        let oblivious_hashtable = run_with_mode(&sample, rules, LookupMode::Oblivious, &|_, values| hashtable_chunk(values));
+        assert_eq!(direct, oblivious_fst);
+        assert_eq!(direct, oblivious_hashtable);
+    }
+    assert!(run_with_mode(&sample, &rules_u8(0, 0, 0), LookupMode::Oblivious, &|_, values| fst_chunk(values)).iter().any(|r| r.1 == 1));
+
+    let sample = first_clients(&sample, 1);
+    let direct = run(&sample, &rules_u8(1, 1, 1), &|_, values| fst_chunk(values));
+    let oblivious = run_with_mode(&sample, &rules_u8(1, 1, 1), LookupMode::Oblivious, &|_, values| hashtable_chunk(values));
+    assert_eq!(direct, oblivious);
+}
+
+fn encoded_values(values: &[Vec<u8>]) -> Vec<EncodedValue> {
+    values.iter().map(|value| EncodedValue::from_slice(value).unwrap()).collect()
+}
+
+// accessed indices and hit flags of one oblivious intersection
+fn oblivious_trace(queries: &[Vec<u8>], keys: &[EncodedValue], rules: &ContactRules) -> (AccessTrace, EncodedResultBuffer) {
+    let mut mapped_query_buffer = MappedEncodedQueryBuffer::new();
+    mapped_query_buffer.encoding = Encoding::Gp10;
+    mapped_query_buffer.map = encoded_values(queries);
+    let mut result = EncodedResultBuffer::with_size(queries.len());
+    let mut trace = AccessTrace::new();
+    oblivious::intersect(keys, &mapped_query_buffer, rules, &mut result, Some(&mut trace));
+    (trace, result)
+}
+
+#[test]
+fn oblivious_access_trace_is_independent_of_queries() {
+    let sample = read_sample();
+    let keys = encoded_values(&sample.central[..CHUNK_SIZE]);
+    // every value hits the chunk
+    let hitting: Vec<Vec<u8>> = sample.central[..100].to_vec();
+    // the same number of values, none of them in the chunk
+    let missing: Vec<Vec<u8>> = sample.central[CHUNK_SIZE..CHUNK_SIZE+100].to_vec();
+    // a single cell at every time slot of the window
+    let one_cell: Vec<Vec<u8>> = (0..100).map(|slot| {
+        let mut value = sample.central[0][..10].to_vec();
+        value.extend_from_slice(format!("{:04}", slot).as_bytes());
+        value
+    }).collect();
+
+    let mut rules = ContactRules::default();
+    for &(before, after, spatial_tolerance) in [(0, 0, 0), (2, 1, 0), (1, 1, 1)].iter() {
+        rules.time_tolerance_before = before;
+        rules.time_tolerance_after = after;
+        rules.spatial_tolerance = spatial_tolerance;
+        let (hitting_trace, hitting_result) = oblivious_trace(&hitting, &keys, &rules);
+        let (missing_trace, missing_result) = oblivious_trace(&missing, &keys, &rules);
+        let (one_cell_trace, _) = oblivious_trace(&one_cell, &keys, &rules);
+        assert_eq!(hitting_trace, missing_trace);
+        assert_eq!(hitting_trace, one_cell_trace);
+        assert!(hitting_result.exact_hits.iter().all(|hit| *hit == 1));
+        assert!(missing_result.exact_hits.iter().all(|hit| *hit == 0));
+    }
+}
+
+// indices read by the mapping of the queries and by the response construction of mode,
+// and the response of one batch; the chunks are looked up directly to keep the test fast
+fn response_trace(sample: &Sample, queries: Vec<Vec<u8>>, mode: LookupMode) -> (AccessTrace, AccessTrace, Vec<u8>) {
+    let mut query_buffer = EncodedQueryBuffer::new();
+    query_buffer.build_query_buffer(Encoding::Gp10, &queries, sample.query_id_list.clone()).unwrap();
+    let mut mapped_query_buffer = MappedEncodedQueryBuffer::new();
+    let mut mapping_trace = AccessTrace::new();
+    mapped_query_buffer.mapping_with_trace(&query_buffer, Some(&mut mapping_trace));
+
+    let mut tracer = ContactTracer::new();
+    tracer.set_contact_rules(&rules_u8(1, 1, 1)).unwrap();
+    tracer.upload_queries(Encoding::Gp10, queries, sample.query_id_list.clone()).unwrap();
+    for values in sample.central.chunks(CHUNK_SIZE) {
+        tracer.trace_chunk(fst_chunk(values)).unwrap();
+    }
+    tracer.set_lookup_mode(mode);
+    let mut trace = AccessTrace::new();
+    let response = tracer.build_response_with_trace(Some(&mut trace)).unwrap();
+    (mapping_trace, trace, response)
+}
+
+// indices point_flags records for n points: the array of 2n slots padded to a power of two
+// is filled, sorted, scanned and sorted again, then the n points are read back
+fn point_flags_trace_len(n: usize) -> usize {
+    let size = (2 * n).next_power_of_two();
+    let stages = size.trailing_zeros() as usize;
+    let sort = size / 2 * stages * (stages + 1) / 2 * 2;
+    size + sort + size + sort + n
+}
+
+#[test]
+fn indexed_response_matches_the_constant_time_one() {
+    let sample = read_sample();
+    // the same values at several points of a client and in several clients,
+    // half of a client twice to stay within MAX_QUERY_SIZE
+    let mut queries: Vec<Vec<u8>> = sample.queries.iter().map(|query| query.concat()).collect();
+    let half = sample.queries[0][..sample.queries[0].len() / 2].concat();
+    queries[1] = [half.clone(), half].concat();
+    let mut query_id_list = sample.query_id_list.clone();
+    query_id_list[1] = 1_000_000;
+    for rules in [rules_u8(0, 0, 0), rules_u8(2, 1, 0), rules_u8(1, 1, 1)].iter() {
+        let mut tracer = ContactTracer::new();
+        tracer.set_contact_rules(rules).unwrap();
+        tracer.upload_queries(Encoding::Gp10, queries.clone(), query_id_list.clone()).unwrap();
+        for values in sample.central.chunks(CHUNK_SIZE) {
+            tracer.trace_chunk(fst_chunk(values)).unwrap();
+        }
+        tracer.set_lookup_mode(LookupMode::Indexed);
+        let mut trace = AccessTrace::new();
+        let indexed = tracer.build_response_with_trace(Some(&mut trace)).unwrap();
+        assert!(trace.is_empty());
+        tracer.set_lookup_mode(LookupMode::Direct);
+        assert_eq!(tracer.build_response_with_trace(Some(&mut trace)).unwrap(), indexed);
+        let points = queries.iter().map(|query| query.len() / 14).sum::<usize>();
+        assert_eq!(trace.len(), point_flags_trace_len(points));
+    }
+}
+
+#[test]
+fn response_work_is_independent_of_queries() {
+    let sample = read_sample();
+    let points = sample.queries[0].len();
+    let far_away = |slot: usize| -> Vec<u8> { format!("0000000000{:04}", slot % 10000).into_bytes() };
+    // no hit at all
+    let negative: Vec<Vec<u8>> = sample.queries.iter().enumerate().map(|(c, query)| {
+        (0..query.len()).map(|p| far_away(c * points + p)).collect::<Vec<Vec<u8>>>().concat()
+    }).collect();
+    // every client hits at its first point / at its last point / everywhere
+    let hit_at = |position: &dyn Fn(usize) -> bool| -> Vec<Vec<u8>> {
+        sample.queries.iter().enumerate().map(|(c, query)| {
+            (0..query.len()).map(|p| {
+                if position(p) { sample.central[(c * points + p) % sample.central.len()].clone() } else { far_away(c * points + p) }
+            }).collect::<Vec<Vec<u8>>>().concat()
+        }).collect()
+    };
+    let early = hit_at(&|p| p == 0);
+    let late = hit_at(&|p| p + 1 == points);
+    let everywhere = hit_at(&|_| true);
+
+    let (negative_mapping_trace, negative_trace, negative_response) = response_trace(&sample, negative, LookupMode::Oblivious);
+    assert_eq!(negative_trace.len(), point_flags_trace_len(sample.queries.len() * points));
+    for queries in [early, late, everywhere] {
+        let (mapping_trace, trace, response) = response_trace(&sample, queries, LookupMode::Oblivious);
+        assert_eq!(mapping_trace, negative_mapping_trace);
+        assert_eq!(trace, negative_trace);
+        assert_eq!(response.len(), negative_response.len());
+        assert!(response != negative_response);
+    }
+}
+
+#[test]
+fn direct_response_work_is_independent_of_hits() {
+    let sample = read_sample();
+    let points = sample.queries[0].len();
+    let far_away = |slot: usize| -> Vec<u8> { format!("0000000000{:04}", slot % 10000).into_bytes() };
+    // none of the points / every other point of every client hits
+    let negative: Vec<Vec<u8>> = sample.queries.iter().enumerate().map(|(c, query)| {
+        (0..query.len()).map(|p| far_away(c * points + p)).collect::<Vec<Vec<u8>>>().concat()
+    }).collect();
+    let alternating: Vec<Vec<u8>> = sample.queries.iter().enumerate().map(|(c, query)| {
+        (0..query.len()).map(|p| {
+            if p % 2 == 0 { sample.central[(c * points + p) % sample.central.len()].clone() } else { far_away(c * points + p) }
+        }).collect::<Vec<Vec<u8>>>().concat()
+    }).collect();
+
+    let (negative_mapping_trace, negative_trace, negative_response) = response_trace(&sample, negative, LookupMode::Direct);
+    let (mapping_trace, trace, response) = response_trace(&sample, alternating, LookupMode::Direct);
+    assert_eq!(trace.len(), point_flags_trace_len(sample.queries.len() * points));
+    assert_eq!(mapping_trace, negative_mapping_trace);
+    assert_eq!(trace, negative_trace);
+    assert!(response != negative_response);
+}
+
+#[test]
+fn response_work_is_independent_of_repeated_values() {
+    let sample = read_sample();
+    // the same number of points, every client at a single place / the sample trajectories
+    let repeated: Vec<Vec<u8>> = sample.queries.iter().map(|query| {
+        query.iter().map(|_| query[0].clone()).collect::<Vec<Vec<u8>>>().concat()
+    }).collect();
+    let trajectories: Vec<Vec<u8>> = sample.queries.iter().map(|query| query.concat()).collect();
+    let distinct = |queries: &[Vec<u8>]| -> usize {
+        queries.iter().flat_map(|query| query.chunks(14)).collect::<HashSet<&[u8]>>().len()
+    };
+    assert!(distinct(&repeated) < distinct(&trajectories));
+
+    for mode in [LookupMode::Direct, LookupMode::Oblivious] {
+        let (repeated_mapping_trace, repeated_trace, _) = response_trace(&sample, repeated.clone(), mode);
+        let (mapping_trace, trace, _) = response_trace(&sample, trajectories.clone(), mode);
+        assert_eq!(mapping_trace, repeated_mapping_trace);
+        assert_eq!(trace, repeated_trace);
+    }
+}
+
+// StackMaxSize of enclave/Enclave.config.xml, the stack of every enclave thread
+fn enclave_stack_size() -> usize {
+    let path = format!("{}/../enclave/Enclave.config.xml", env!("CARGO_MANIFEST_DIR"));
+    let config = std::fs::read_to_string(path).unwrap();
+    let start = config.find("<StackMaxSize>0x").unwrap() + "<StackMaxSize>0x".len();
+    let end = start + config[start..].find("</StackMaxSize>").unwrap();
+    usize::from_str_radix(&config[start..end], 16).unwrap()
+}
+
+#[test]
+fn tracing_fits_a_quarter_of_the_enclave_stack() {
+    let sample = read_sample();
+    let queries: Vec<Vec<u8>> = sample.queries.iter().map(|query| query.concat()).collect();
+    // one chunk of either backend, the stack does not grow with their number
+    let chunks: Vec<Vec<u8>> = sample.central.chunks(CHUNK_SIZE).take(2).enumerate()
+        .map(|(i, values)| if i % 2 == 0 { fst_chunk(values) } else { hashtable_chunk(values) })
+        .collect();
+    let query_id_list = sample.query_id_list.clone();
+    // what an ECALL thread runs, the chunks are built by the host
+    let traced = thread::Builder::new().stack_size(enclave_stack_size() / 4).spawn(move || {
+        for &mode in [LookupMode::Direct, LookupMode::Oblivious].iter() {
+            let mut tracer = ContactTracer::new();
+            tracer.set_contact_rules(&rules_u8(2, 1, 1)).unwrap();
+            tracer.set_lookup_mode(mode);
+            tracer.upload_queries(Encoding::Gp10, queries.clone(), query_id_list.clone()).unwrap();
+            for chunk in chunks.iter() {
+                tracer.trace_chunk(chunk.clone()).unwrap();
+            }
+            tracer.build_response().unwrap();
+        }
+    }).unwrap().join();
+    assert!(traced.is_ok());
+}
@@ -12,10 +12,13 @@ filename () {
     echo "data/result/journal/output-$1-$2-$3-$4-$5-$(timestampNow).txt"
 }
 
+# one build serves every dictionary and encoding
+make clean && make
+
 # ## using FSA and gp10
 # DS=fsa
 # EN=gp10
-# make clean && FEATURE="$DS $EN" make
+# export PCT_DICTIONARY="$DS"
 # for chunksize in 1000000 10000000 20000000
 # do
 #     echo "start $DS $EN $chunksize"
@@ -27,7 +30,7 @@ filename () {
 # ## using HashTable and gp10
 # DS=hashtable
 # EN=gp10
-# make clean && FEATURE="$DS $EN" make
+# export PCT_DICTIONARY="$DS"
 # for chunksize in 1000000 10000000 20000000
 # do
 #     echo "start $DS $EN $chunksize"
@@ -39,7 +42,7 @@ filename () {
 # ## using FSA and th48
 # DS=fsa
 # EN=th48
-# make clean && FEATURE="$DS $EN" make
+# export PCT_DICTIONARY="$DS"
 # for chunksize in 1000000 10000000 20000000 50000000
 # do
 #     echo "start $DS $EN $chunksize"
@@ -51,7 +54,7 @@ filename () {
 # ## using HashTable and th48
 # DS=hashtable
 # EN=th48
-# make clean && FEATURE="$DS $EN" make
+# export PCT_DICTIONARY="$DS"
 # for chunksize in 1000000 10000000 20000000 50000000
 # do
 #     echo "start $DS $EN $chunksize"
@@ -63,7 +66,7 @@ filename () {
 ## using FSA and th48
 DS=fsa
 EN=th48
-make clean && FEATURE="$DS $EN" make
+export PCT_DICTIONARY="$DS"
 for chunksize in 10000000 20000000 50000000
 do
     echo "start $DS $EN $chunksize"
@@ -73,7 +76,7 @@ done
 ## using HashTable and th48
 DS=hashtable
 EN=th48
-make clean && FEATURE="$DS $EN" make
+export PCT_DICTIONARY="$DS"
 for chunksize in 10000000 20000000 50000000
 do
     echo "start $DS $EN $chunksize"
@@ -28,6 +28,7 @@ def parse_from_result_files(dir_name):
                         "Read Query Data",
                         "ECALL get_result",
                         "ECALL init_enclave",
+                        "ECALL key_exchange",
                         "Distribute central data",
                         "ECALL upload_query_data",
                         "ECALL private_contact_trace",